@@ -2,14 +2,17 @@ use crate::*;
 use near_sdk::{env, ext_contract, require, AccountId, Gas, Promise};
 
 const GAS_FOR_NFT_APPROVE: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_RESOLVE_APPROVE: Gas = Gas(5_000_000_000_000);
 
 pub trait NonFungibleTokenApproval {
     //approve an account ID to transfer a token on your behalf
+    //if `revoke_on_failure` is set, the approval is revoked when `nft_on_approve` fails
     fn nft_approve(
         &mut self,
         token_id: TokenId,
         account_id: AccountId,
         msg: Option<String>,
+        revoke_on_failure: Option<bool>,
     ) -> Option<Promise>;
 
     //check if the passed in account has access to approve the token ID
@@ -39,8 +42,20 @@ trait NonFungibleTokenApprovalsReceiver {
     );
 }
 
+/// Used when an approval is granted using `nft_approve` with `revoke_on_failure`. This is the method that's called after `nft_on_approve`. This trait is implemented on the NFT contract.
+#[ext_contract(ext_nft_approval_resolver)]
+pub trait NonFungibleTokenApprovalResolver {
+    fn nft_resolve_approve(
+        &mut self,
+        owner_id: AccountId,
+        token_id: TokenId,
+        account_id: AccountId,
+        approval_id: u64,
+    ) -> bool;
+}
+
 /// Returns the Token if exists
-fn expect_token_found<T>(option: Option<T>) -> T {
+pub(crate) fn expect_token_found<T>(option: Option<T>) -> T {
     option.unwrap_or_else(|| env::panic_str("cypher: Token not found"))
 }
 
@@ -60,8 +75,16 @@ impl NonFungibleTokenApproval for Contract {
         token_id: TokenId,
         account_id: AccountId,
         msg: Option<String>,
+        revoke_on_failure: Option<bool>,
     ) -> Option<Promise> {
         assert_at_least_one_yocto();
+        require!(
+            !self.internal_is_staked(&token_id),
+            "cypher: Token is staked"
+        );
+        self.internal_assert_not_frozen(&token_id);
+        self.internal_assert_not_blocked(&env::predecessor_account_id());
+        self.internal_assert_not_blocked(&account_id);
 
         // Ensure the contract implements the Approval management
         let approvals_by_id = self.approvals_by_id.as_mut().unwrap_or_else(|| {
@@ -77,24 +100,54 @@ impl NonFungibleTokenApproval for Contract {
         // update Hashmap of approvals for this token
         let next_approval_by_id = expect_approval(self.next_approval_id_by_id.as_mut());
         let approved_account_ids = &mut approvals_by_id.get(&token_id).unwrap_or_default();
+        require!(
+            approved_account_ids.contains_key(&account_id)
+                || (approved_account_ids.len() as u32) < self.max_approvals_per_token,
+            "cypher: Token has reached the maximum number of approvals"
+        );
         let approval_id = next_approval_by_id.get(&token_id).unwrap_or(1u64);
         let old_approval_id = approved_account_ids.insert(account_id.clone(), approval_id);
         approvals_by_id.insert(&token_id, approved_account_ids);
         next_approval_by_id.insert(&token_id, &(approval_id + 1));
 
-        // calculate cost for storing new authorized AccountId
+        // charge the owner's storage balance, topped up by the deposit, for the new authorized AccountId
         let storage_used = if old_approval_id.is_none() {
             bytes_for_approved_account_id(&account_id)
         } else {
             0
         };
-        refund_deposit(storage_used);
+        self.internal_storage_deposit(&owner_id, env::attached_deposit());
+        self.internal_storage_debit(&owner_id, storage_used);
+
+        EventLogVariant::NftApprove(vec![NftApproveLog {
+            owner_id: owner_id.to_string(),
+            token_id: token_id.clone(),
+            account_id: account_id.to_string(),
+            approval_id,
+        }])
+        .emit();
 
         // CCC to marketplace contract to list NFT for sale
         msg.map(|msg| {
-            ext_nft_approval_receiver::ext(account_id)
-                .with_static_gas(env::prepaid_gas() - GAS_FOR_NFT_APPROVE)
-                .nft_on_approve(token_id, owner_id, approval_id, msg)
+            if !revoke_on_failure.unwrap_or(false) {
+                return ext_nft_approval_receiver::ext(account_id)
+                    .with_static_gas(env::prepaid_gas() - GAS_FOR_NFT_APPROVE)
+                    .nft_on_approve(token_id, owner_id, approval_id, msg);
+            }
+
+            // the callback is budgeted on top of the approve itself
+            require!(
+                env::prepaid_gas() > GAS_FOR_NFT_APPROVE + GAS_FOR_RESOLVE_APPROVE,
+                "cypher: More gas is required"
+            );
+            ext_nft_approval_receiver::ext(account_id.clone())
+                .with_static_gas(env::prepaid_gas() - GAS_FOR_NFT_APPROVE - GAS_FOR_RESOLVE_APPROVE)
+                .nft_on_approve(token_id.clone(), owner_id.clone(), approval_id, msg)
+                .then(
+                    ext_nft_approval_resolver::ext(env::current_account_id())
+                        .with_static_gas(GAS_FOR_RESOLVE_APPROVE)
+                        .nft_resolve_approve(owner_id, token_id, account_id, approval_id),
+                )
         })
     }
 
@@ -155,15 +208,24 @@ impl NonFungibleTokenApproval for Contract {
         // if token has no approvals, do nothing
         if let Some(approved_account_ids) = &mut approvals_by_id.get(&token_id) {
             if approved_account_ids.remove(&account_id).is_some() {
-                refund_approved_account_ids_iter(
-                    predecessor_account_id,
-                    core::iter::once(&account_id),
-                );
                 if approved_account_ids.is_empty() {
                     approvals_by_id.remove(&token_id);
                 } else {
                     approvals_by_id.insert(&token_id, approved_account_ids);
                 }
+                // credit the owner's storage balance for the released approval
+                self.internal_storage_credit(
+                    &predecessor_account_id,
+                    bytes_for_approved_account_id(&account_id),
+                );
+
+                EventLogVariant::NftRevoke(vec![NftRevokeLog {
+                    owner_id: owner_id.to_string(),
+                    token_id,
+                    account_id: account_id.to_string(),
+                    memo: None,
+                }])
+                .emit();
             }
         }
     }
@@ -187,12 +249,76 @@ impl NonFungibleTokenApproval for Contract {
         );
 
         // if token has no approvals, do nothing
-        if let Some(approved_account_ids) = &mut approvals_by_id.get(&token_id) {
-            // otherwise, refund owner for storage costs of all approvals...
-            refund_approved_account_ids(predecessor_account_id, approved_account_ids);
-            // ...and remove whole HashMap of approvals
+        if let Some(approved_account_ids) = approvals_by_id.remove(&token_id) {
+            // otherwise, credit owner for storage costs of all approvals
+            let storage_released = approved_account_ids
+                .keys()
+                .map(bytes_for_approved_account_id)
+                .sum();
+            self.internal_storage_credit(&predecessor_account_id, storage_released);
+
+            EventLogVariant::NftRevokeAll(vec![NftRevokeAllLog {
+                owner_id: owner_id.to_string(),
+                token_id,
+            }])
+            .emit();
+        }
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenApprovalResolver for Contract {
+    /// Returns true if the approval is still active after `nft_on_approve`.
+    #[private]
+    fn nft_resolve_approve(
+        &mut self,
+        owner_id: AccountId,
+        token_id: TokenId,
+        account_id: AccountId,
+        approval_id: u64,
+    ) -> bool {
+        // if call succeeded, keep the approval
+        match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => return true,
+            PromiseResult::Failed => {}
+        };
+
+        // The token may have moved or the approval may have been replaced in the meantime.
+        if self.owner_by_id.get(&token_id).as_ref() != Some(&owner_id) {
+            return false;
+        }
+        let approvals_by_id = if let Some(a) = self.approvals_by_id.as_mut() {
+            a
+        } else {
+            return false;
+        };
+        let mut approved_account_ids = if let Some(ids) = approvals_by_id.get(&token_id) {
+            ids
+        } else {
+            return false;
+        };
+        if approved_account_ids.get(&account_id) != Some(&approval_id) {
+            return false;
+        }
+
+        // revoke the approval and credit the storage back to the owner
+        approved_account_ids.remove(&account_id);
+        if approved_account_ids.is_empty() {
             approvals_by_id.remove(&token_id);
+        } else {
+            approvals_by_id.insert(&token_id, &approved_account_ids);
         }
+        self.internal_storage_credit(&owner_id, bytes_for_approved_account_id(&account_id));
+
+        EventLogVariant::NftRevoke(vec![NftRevokeLog {
+            owner_id: owner_id.to_string(),
+            token_id,
+            account_id: account_id.to_string(),
+            memo: Some("nft_on_approve failed".to_string()),
+        }])
+        .emit();
+        false
     }
 }
 
@@ -221,4 +347,88 @@ pub trait NonFungibleTokenApprovalReceiver {
     ) -> Option<near_sdk::PromiseOrValue<String>>; // TODO: how to make "any"?
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn approve(contract: &mut Contract, token_id: &str, account_id: &str) {
+        call(&account("alice.near"), NEAR / 100, 0);
+        contract.nft_approve(token_id.to_string(), account(account_id), None, None);
+    }
+
+    #[test]
+    fn failed_nft_on_approve_revokes_the_approval() {
+        let mut contract = setup(&["alice.near"]);
+        mint(&mut contract, "1", "alice.near");
+        approve(&mut contract, "1", "market.near");
+        let used_bytes = contract
+            .internal_storage_account(&account("alice.near"))
+            .used_bytes;
+
+        callback(vec![PromiseResult::Failed]);
+        let active = contract.nft_resolve_approve(
+            account("alice.near"),
+            "1".to_string(),
+            account("market.near"),
+            1,
+        );
+
+        assert!(!active);
+        assert!(!contract.nft_is_approved("1".to_string(), account("market.near"), None));
+        assert_eq!(
+            contract
+                .internal_storage_account(&account("alice.near"))
+                .used_bytes,
+            used_bytes - bytes_for_approved_account_id(&account("market.near"))
+        );
+        assert_eq!(
+            near_sdk::test_utils::get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_revoke","data":[{"owner_id":"alice.near","token_id":"1","account_id":"market.near","memo":"nft_on_approve failed"}]}"#
+            ]
+        );
+    }
 
+    #[test]
+    fn successful_nft_on_approve_keeps_the_approval() {
+        let mut contract = setup(&["alice.near"]);
+        mint(&mut contract, "1", "alice.near");
+        approve(&mut contract, "1", "market.near");
+
+        callback(vec![PromiseResult::Successful(vec![])]);
+        assert!(contract.nft_resolve_approve(
+            account("alice.near"),
+            "1".to_string(),
+            account("market.near"),
+            1,
+        ));
+        assert!(contract.nft_is_approved("1".to_string(), account("market.near"), Some(1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Token has reached the maximum number of approvals")]
+    fn approvals_stop_at_the_cap() {
+        let mut contract = setup(&["alice.near"]);
+        call(&owner(), 0, 0);
+        contract.set_max_approvals_per_token(2);
+        mint(&mut contract, "1", "alice.near");
+        approve(&mut contract, "1", "market.near");
+        approve(&mut contract, "1", "other.near");
+        approve(&mut contract, "1", "third.near");
+    }
+
+    #[test]
+    fn approved_accounts_can_be_approved_again_at_the_cap() {
+        let mut contract = setup(&["alice.near"]);
+        call(&owner(), 0, 0);
+        contract.set_max_approvals_per_token(2);
+        mint(&mut contract, "1", "alice.near");
+        approve(&mut contract, "1", "market.near");
+        approve(&mut contract, "1", "other.near");
+        approve(&mut contract, "1", "market.near");
+
+        assert!(contract.nft_is_approved("1".to_string(), account("market.near"), Some(3)));
+        assert!(contract.nft_is_approved("1".to_string(), account("other.near"), Some(2)));
+    }
+}
@@ -0,0 +1,412 @@
+use crate::*;
+use near_sdk::json_types::U64;
+
+/// Time left on the clock that a late bid extends the auction to when none is given (10 minutes)
+const DEFAULT_AUCTION_EXTENSION: u64 = 10 * 60 * 1_000_000_000;
+
+/// The highest bid of an auction, escrowed by the contract
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Bid {
+    pub bidder_id: AccountId,
+    pub amount: U128,
+}
+
+/// A timed English auction started with `nft_start_auction`.
+/// The token stays with its owner and can't be transferred until the auction is over.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Auction {
+    pub token_id: TokenId,
+    pub seller_id: AccountId,
+    // lowest first bid in yoctoNEAR
+    pub reserve_price: U128,
+    // how much every bid must add to the highest one
+    pub min_increment: U128,
+    // nanoseconds since the epoch at which bidding closes
+    pub end_at: U64,
+    // a bid placed with less than this left pushes the end back to this far from the bid
+    pub extension: U64,
+    pub highest_bid: Option<Bid>,
+    // NEP-141 contract bids are paid in through `ft_transfer_call`, NEAR if unset
+    pub ft_contract_id: Option<AccountId>,
+}
+
+impl Auction {
+    pub fn is_over(&self) -> bool {
+        env::block_timestamp() >= self.end_at.0
+    }
+}
+
+/// Logs an auction event for `auction`
+fn emit_auction_event(variant: fn(Vec<NftAuctionLog>) -> EventLogVariant, auction: &Auction) {
+    variant(vec![NftAuctionLog {
+        seller_id: auction.seller_id.to_string(),
+        token_id: auction.token_id.clone(),
+        bidder_id: auction
+            .highest_bid
+            .as_ref()
+            .map(|bid| bid.bidder_id.to_string()),
+        amount: auction
+            .highest_bid
+            .as_ref()
+            .map(|bid| bid.amount.0.to_string()),
+        end_at: auction.end_at.0.to_string(),
+        ft_contract_id: auction.ft_contract_id.as_ref().map(|id| id.to_string()),
+    }])
+    .emit();
+}
+
+impl Contract {
+    /// Takes the highest bid out of an auction, crediting the storage it used back to the
+    /// bidder. The bid itself is not refunded.
+    fn internal_take_bid(&mut self, auction: &mut Auction) -> Option<Bid> {
+        let initial_storage_usage = env::storage_usage();
+        let bid = auction.highest_bid.take()?;
+        self.auctions.insert(&auction.token_id, auction);
+        self.internal_storage_credit(
+            &bid.bidder_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
+        Some(bid)
+    }
+
+    /// Removes an auction, crediting the storage back to the seller and the highest bidder
+    fn internal_remove_auction(&mut self, token_id: &TokenId) -> Option<Auction> {
+        let mut auction = self.auctions.get(token_id)?;
+        let bid = self.internal_take_bid(&mut auction);
+        let initial_storage_usage = env::storage_usage();
+        self.auctions.remove(token_id);
+        self.internal_storage_credit(
+            &auction.seller_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
+        auction.highest_bid = bid;
+        Some(auction)
+    }
+
+    /// Cancels the auction of a token, if any, refunding the highest bid in its currency.
+    /// Used when the token can't go to the winner anymore, e.g. because it was frozen.
+    pub(crate) fn internal_cancel_auction(&mut self, token_id: &TokenId) {
+        let auction = if let Some(auction) = self.internal_remove_auction(token_id) {
+            auction
+        } else {
+            return;
+        };
+        if let Some(bid) = &auction.highest_bid {
+            self.internal_pay(
+                auction.ft_contract_id.as_ref(),
+                &bid.bidder_id,
+                bid.amount.0,
+                &bid.bidder_id,
+            );
+        }
+        emit_auction_event(EventLogVariant::NftAuctionCancel, &auction);
+    }
+
+    /// Returns true if the token is in an auction that hasn't been settled
+    pub(crate) fn internal_is_in_auction(&self, token_id: &TokenId) -> bool {
+        self.auctions.get(token_id).is_some()
+    }
+
+    /// Bids `amount` paid in `ft_contract_id`, or NEAR if unset, on the auction of a token.
+    /// The whole amount is escrowed and the outbid bidder is refunded in the same currency.
+    pub(crate) fn internal_bid(
+        &mut self,
+        bidder_id: &AccountId,
+        token_id: &TokenId,
+        ft_contract_id: Option<&AccountId>,
+        amount: Balance,
+    ) {
+        self.internal_assert_not_blocked(bidder_id);
+        let mut auction = self
+            .auctions
+            .get(token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token is not in an auction"));
+        require!(
+            auction.ft_contract_id.as_ref() == ft_contract_id,
+            "cypher: The auction is in another currency"
+        );
+        require!(!auction.is_over(), "cypher: The auction is over");
+        require!(
+            bidder_id != &auction.seller_id,
+            "cypher: The seller cannot bid"
+        );
+
+        let min_bid = match &auction.highest_bid {
+            Some(bid) => bid.amount.0 + auction.min_increment.0,
+            None => auction.reserve_price.0,
+        };
+        require!(
+            amount >= min_bid,
+            format!("cypher: Bid must be at least {}", min_bid)
+        );
+
+        // refund the outbid bidder right away
+        if let Some(outbid) = self.internal_take_bid(&mut auction) {
+            self.internal_pay(
+                ft_contract_id,
+                &outbid.bidder_id,
+                outbid.amount.0,
+                bidder_id,
+            );
+        }
+        auction.highest_bid = Some(Bid {
+            bidder_id: bidder_id.clone(),
+            amount: amount.into(),
+        });
+
+        // a late bid pushes back the end so that others get a chance to answer
+        let now = env::block_timestamp();
+        if auction.end_at.0 - now < auction.extension.0 {
+            auction.end_at = now.saturating_add(auction.extension.0).into();
+        }
+
+        // the bidder pays for the storage their bid takes in the auction
+        let initial_storage_usage = env::storage_usage();
+        self.auctions.insert(token_id, &auction);
+        self.internal_storage_debit(
+            bidder_id,
+            env::storage_usage().saturating_sub(initial_storage_usage),
+        );
+        emit_auction_event(EventLogVariant::NftBid, &auction);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Put a token you own up for auction for `duration` nanoseconds. The first bid must reach
+    /// `reserve_price` and every following one must add at least `min_increment`. Bids are in
+    /// yoctoNEAR, or in an accepted fungible token if `ft_contract_id` is set.
+    #[payable]
+    pub fn nft_start_auction(
+        &mut self,
+        token_id: TokenId,
+        reserve_price: U128,
+        min_increment: U128,
+        duration: U64,
+        extension: Option<U64>,
+        ft_contract_id: Option<AccountId>,
+    ) {
+        assert_one_yocto();
+        let seller_id = env::predecessor_account_id();
+        let owner_id = self
+            .owner_by_id
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token not found"));
+        require!(
+            seller_id == owner_id,
+            "cypher: Predecessor must be token owner"
+        );
+        self.internal_assert_not_blocked(&seller_id);
+        require!(
+            reserve_price.0 > 0 && min_increment.0 > 0,
+            "cypher: Reserve price and minimum increment must be positive"
+        );
+        self.internal_assert_accepted_ft(ft_contract_id.as_ref());
+        self.internal_assert_token_unlocked(&token_id);
+
+        let auction = Auction {
+            token_id,
+            seller_id,
+            reserve_price,
+            min_increment,
+            end_at: env::block_timestamp().saturating_add(duration.0).into(),
+            extension: extension.unwrap_or_else(|| DEFAULT_AUCTION_EXTENSION.into()),
+            highest_bid: None,
+            ft_contract_id,
+        };
+        let initial_storage_usage = env::storage_usage();
+        self.auctions.insert(&auction.token_id, &auction);
+        self.internal_storage_debit(
+            &auction.seller_id,
+            env::storage_usage() - initial_storage_usage,
+        );
+        emit_auction_event(EventLogVariant::NftAuctionStart, &auction);
+    }
+
+    /// Bid the attached deposit on a token. The deposit is escrowed until you are outbid,
+    /// at which point it is refunded. The storage the bid takes is charged to your storage
+    /// balance until then.
+    #[payable]
+    pub fn nft_bid(&mut self, token_id: TokenId) {
+        self.internal_bid(
+            &env::predecessor_account_id(),
+            &token_id,
+            None,
+            env::attached_deposit(),
+        );
+    }
+
+    /// Cancel an auction of your token that has no bids yet
+    #[payable]
+    pub fn nft_cancel_auction(&mut self, token_id: TokenId) {
+        assert_one_yocto();
+        let auction = self
+            .auctions
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token is not in an auction"));
+        require!(
+            env::predecessor_account_id() == auction.seller_id,
+            "cypher: Predecessor must be the seller"
+        );
+        require!(
+            auction.highest_bid.is_none(),
+            "cypher: Cannot cancel an auction with bids"
+        );
+
+        self.internal_remove_auction(&token_id);
+        emit_auction_event(EventLogVariant::NftAuctionCancel, &auction);
+    }
+
+    /// Settle an auction once it is over. The token goes to the highest bidder and their bid is
+    /// split between the royalty recipients and the seller. Anyone can settle. If the token or
+    /// the seller has been frozen since, or the seller or the winner blocked, the auction is
+    /// cancelled and the bid refunded instead.
+    pub fn nft_settle_auction(&mut self, token_id: TokenId) {
+        let auction = self
+            .auctions
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token is not in an auction"));
+        require!(auction.is_over(), "cypher: The auction is not over yet");
+        let blocked = self.blocklist.contains(&auction.seller_id)
+            || matches!(&auction.highest_bid, Some(bid) if self.blocklist.contains(&bid.bidder_id));
+        if blocked
+            || self
+                .internal_token_freeze(&token_id, &auction.seller_id)
+                .is_some()
+        {
+            self.internal_cancel_auction(&token_id);
+            return;
+        }
+        self.internal_remove_auction(&token_id);
+
+        if let Some(bid) = &auction.highest_bid {
+            let payout =
+                self.internal_payout(&auction.seller_id, &token_id, bid.amount.0, u32::MAX);
+
+            // The bid is already escrowed so settling must not fail,
+            // the winner is charged for storage without a balance check.
+            let (storage_released, storage_used) = self.internal_transfer_unguarded(
+                &token_id,
+                &auction.seller_id,
+                &bid.bidder_id,
+                ProvenanceKind::Sale {
+                    price: bid.amount,
+                    ft_contract_id: auction.ft_contract_id.clone(),
+                },
+            );
+            if let Some(approved_account_ids) = self
+                .approvals_by_id
+                .as_mut()
+                .and_then(|by_id| by_id.remove_raw(&token_id_key(&token_id)))
+            {
+                self.internal_storage_credit(
+                    &auction.seller_id,
+                    bytes_for_approved_account_ids_raw(&approved_account_ids),
+                );
+            }
+            self.internal_storage_credit(&auction.seller_id, storage_released);
+            self.internal_storage_debit_unchecked(&bid.bidder_id, storage_used);
+
+            emit_nft_transfers(
+                &[(
+                    auction.seller_id.clone(),
+                    bid.bidder_id.clone(),
+                    None,
+                    token_id.clone(),
+                )],
+                Some("nft_settle_auction"),
+            );
+            self.internal_record_sale(
+                &token_id,
+                &auction.seller_id,
+                &bid.bidder_id,
+                bid.amount.0,
+                auction.ft_contract_id.as_ref(),
+                &payout,
+            );
+            self.internal_distribute_payout(
+                auction.ft_contract_id.as_ref(),
+                payout,
+                &bid.bidder_id,
+            );
+        }
+        emit_auction_event(EventLogVariant::NftAuctionSettle, &auction);
+    }
+
+    /// get the auction of a token, if it is in one
+    pub fn nft_auction(&self, token_id: TokenId) -> Option<Auction> {
+        self.auctions.get(&token_id)
+    }
+
+    /// Query for the running auctions using pagination
+    pub fn nft_auctions(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Auction> {
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        require!(
+            (self.auctions.len() as u128) >= start_index,
+            "cypher: Out of bounds, please use a smaller from_index."
+        );
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+
+        self.auctions
+            .values()
+            .skip(start_index as usize)
+            .take(limit)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    const LATER: u64 = 1_000_000_000_000_000;
+
+    fn auction_with_bid(contract: &mut Contract) {
+        call(&account("alice.near"), 1, 0);
+        contract.nft_start_auction(
+            "1".to_string(),
+            NEAR.into(),
+            NEAR.into(),
+            U64(1_000),
+            None,
+            None,
+        );
+        call(&account("bob.near"), NEAR, 1);
+        contract.nft_bid("1".to_string());
+        call(&account("carol.near"), 2 * NEAR, 2);
+        contract.nft_bid("1".to_string());
+    }
+
+    #[test]
+    fn settling_gives_the_token_to_the_highest_bidder() {
+        let mut contract = setup(&["alice.near", "bob.near", "carol.near"]);
+        mint(&mut contract, "1", "alice.near");
+        auction_with_bid(&mut contract);
+
+        call(&account("dave.near"), 0, LATER);
+        contract.nft_settle_auction("1".to_string());
+        assert_eq!(owner_of(&contract, "1"), account("carol.near"));
+        assert!(!contract.internal_is_in_auction(&"1".to_string()));
+        let stats = contract.nft_sale_stats("1".to_string()).unwrap();
+        assert_eq!(stats.last_sale.price.0, 2 * NEAR);
+    }
+
+    #[test]
+    fn settling_a_frozen_token_refunds_the_bid() {
+        let mut contract = setup(&["alice.near", "bob.near", "carol.near"]);
+        mint(&mut contract, "1", "alice.near");
+        auction_with_bid(&mut contract);
+        call(&owner(), 0, 3);
+        contract.nft_freeze_token("1".to_string(), "stolen".to_string());
+
+        call(&account("dave.near"), 0, LATER);
+        contract.nft_settle_auction("1".to_string());
+        assert_eq!(owner_of(&contract, "1"), account("alice.near"));
+        assert!(!contract.internal_is_in_auction(&"1".to_string()));
+        assert!(contract.nft_sale_stats("1".to_string()).is_none());
+    }
+}
@@ -0,0 +1,484 @@
+use crate::*;
+
+const GAS_FOR_RESOLVE_TRANSFER_PER_TOKEN: Gas = Gas(5_000_000_000_000);
+/// Gas burnt moving each token before the receiver is called
+const GAS_FOR_TRANSFER_PER_TOKEN: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_NFT_BATCH_TRANSFER_CALL: Gas = Gas(25_000_000_000_000);
+/// Tokens moved by a single `nft_move_all` call when no limit is given
+const DEFAULT_MOVE_ALL_LIMIT: u64 = 50;
+
+/// What a call to `nft_move_all` did
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MoveAllPage {
+    // locked tokens (staked, rented, in an auction, frozen...) that were left with the caller
+    pub skipped_token_ids: Vec<TokenId>,
+    // `from_index` of the next call, past the skipped tokens
+    pub next_index: U128,
+    // tokens left to go through from `next_index`, 0 once the whole set was
+    pub left: U128,
+}
+
+/// Used when NFTs are transferred using `nft_batch_transfer_call`. This trait is implemented on the receiving contract, not on the NFT contract.
+#[ext_contract(ext_nft_batch_receiver)]
+pub trait NonFungibleTokenBatchReceiver {
+    /// Returns, for every token in `token_ids`, whether it should be returned to its previous owner
+    fn nft_on_batch_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_ids: Vec<AccountId>,
+        token_ids: Vec<TokenId>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<bool>>;
+}
+
+/// Used when NFTs are transferred using `nft_batch_transfer_call`. This is the method that's called after `nft_on_batch_transfer`. This trait is implemented on the NFT contract.
+#[ext_contract(ext_nft_batch_resolver)]
+pub trait NonFungibleTokenBatchResolver {
+    fn nft_resolve_batch_transfer(
+        &mut self,
+        previous_owner_ids: Vec<AccountId>,
+        receiver_id: AccountId,
+        token_ids: Vec<TokenId>,
+    ) -> Vec<bool>;
+}
+
+impl Contract {
+    /// Transfers every `(token_id, approval_id)` in `tokens` to `receiver_id` and emits one event for the batch.
+    /// Returns the previous owner of each token.
+    fn internal_batch_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        tokens: &[(TokenId, Option<u64>)],
+        memo: Option<&str>,
+    ) -> Vec<(AccountId, Option<RawApprovals>)> {
+        require!(!tokens.is_empty(), "cypher: Cannot transfer an empty batch");
+
+        let mut transfers = Vec::with_capacity(tokens.len());
+        let mut previous_owners = Vec::with_capacity(tokens.len());
+        for (token_id, approval_id) in tokens {
+            let (owner_id, approved_account_ids) =
+                self.internal_transfer_unlogged(sender_id, receiver_id, token_id, *approval_id);
+            let authorized_id = Some(sender_id.clone()).filter(|sender_id| *sender_id != owner_id);
+            transfers.push((owner_id.clone(), receiver_id.clone(), authorized_id, token_id.clone()));
+            previous_owners.push((owner_id, approved_account_ids));
+        }
+        emit_nft_transfers(&transfers, memo);
+
+        previous_owners
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Transfer several tokens to `receiver_id` at once. Fails as a whole if any token can't be transferred.
+    #[payable]
+    pub fn nft_batch_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        tokens: Vec<(TokenId, Option<u64>)>,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+
+        let previous_owners =
+            self.internal_batch_transfer(&sender_id, &receiver_id, &tokens, memo.as_deref());
+
+        // credit the previous owners for the storage of the cleared approvals
+        for (previous_owner_id, approved_account_ids) in previous_owners {
+            if let Some(approved_account_ids) = approved_account_ids {
+                self.internal_storage_credit(
+                    &previous_owner_id,
+                    bytes_for_approved_account_ids_raw(&approved_account_ids),
+                );
+            }
+        }
+    }
+
+    /// Move a page of the caller's tokens to `new_owner_id`, starting at `from_index` in the
+    /// caller's token set and clearing their approvals. At most `limit` tokens are gone through.
+    /// Locked tokens are skipped and stay with the caller, moved ones leave the set, so the next
+    /// call starts at the returned `next_index`. Call again until `left` reaches 0.
+    #[payable]
+    pub fn nft_move_all(
+        &mut self,
+        new_owner_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> MoveAllPage {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+        self.internal_assert_transfer_accounts(&owner_id, &owner_id, &new_owner_id);
+        require!(
+            self.account_freezes.get(&owner_id).is_none(),
+            "cypher: Token owner is frozen"
+        );
+
+        let mut index: u64 = from_index.map(|i| i.0 as u64).unwrap_or_default();
+        let limit = limit.unwrap_or(DEFAULT_MOVE_ALL_LIMIT);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+
+        let mut transfers = Vec::new();
+        let mut skipped_token_ids = Vec::new();
+        let mut storage_released = 0;
+        let mut storage_used = 0;
+        for _ in 0..limit {
+            // read the set again every time, moving a token swaps the last one into its place
+            let token_id = match self
+                .tokens_per_owner
+                .as_ref()
+                .and_then(|tokens_per_owner| tokens_per_owner.get(&owner_id))
+                .and_then(|token_set| token_set.as_vector().get(index))
+            {
+                Some(token_id) => token_id,
+                None => break,
+            };
+            if self.internal_is_token_locked(&token_id) {
+                skipped_token_ids.push(token_id);
+                index += 1;
+                continue;
+            }
+
+            let (released, used) = self.internal_transfer_unguarded(
+                &token_id,
+                &owner_id,
+                &new_owner_id,
+                ProvenanceKind::Transfer,
+            );
+            storage_released += released;
+            storage_used += used;
+
+            // approvals don't survive the move
+            if let Some(approved_account_ids) = self
+                .approvals_by_id
+                .as_mut()
+                .and_then(|by_id| by_id.remove_raw(&token_id_key(&token_id)))
+            {
+                storage_released += bytes_for_approved_account_ids_raw(&approved_account_ids);
+            }
+            transfers.push((owner_id.clone(), new_owner_id.clone(), None, token_id));
+        }
+        self.internal_storage_credit(&owner_id, storage_released);
+        self.internal_storage_debit(&new_owner_id, storage_used);
+        if !transfers.is_empty() {
+            emit_nft_transfers(&transfers, None);
+        }
+
+        MoveAllPage {
+            skipped_token_ids,
+            next_index: U128(index.into()),
+            left: self.nft_tokens_left_to_move(owner_id, Some(U128(index.into()))),
+        }
+    }
+
+    /// Number of tokens `nft_move_all` still has to go through for `account_id` from `from_index`
+    pub fn nft_tokens_left_to_move(&self, account_id: AccountId, from_index: Option<U128>) -> U128 {
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        self.nft_supply_for_owner(account_id)
+            .0
+            .saturating_sub(start_index)
+            .into()
+    }
+
+    /// Transfer several tokens to `receiver_id` and make a single CCC on the receiver's account.
+    /// Each token the receiver asks to return is resolved on its own.
+    #[payable]
+    pub fn nft_batch_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        tokens: Vec<(TokenId, Option<u64>)>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<bool>> {
+        assert_one_yocto();
+        let gas_for_resolve = Gas(GAS_FOR_RESOLVE_TRANSFER_PER_TOKEN.0 * tokens.len() as u64);
+        let gas_for_call = GAS_FOR_NFT_BATCH_TRANSFER_CALL
+            + Gas(GAS_FOR_TRANSFER_PER_TOKEN.0 * tokens.len() as u64)
+            + gas_for_resolve;
+        require!(
+            env::prepaid_gas() > gas_for_call,
+            "cypher: More gas is required"
+        );
+        let sender_id = env::predecessor_account_id();
+
+        let previous_owners =
+            self.internal_batch_transfer(&sender_id, &receiver_id, &tokens, memo.as_deref());
+
+        // keep the previous owners' approvals aside until we know which tokens come back
+        let mut previous_owner_ids = Vec::with_capacity(previous_owners.len());
+        for ((token_id, _), (previous_owner_id, approved_account_ids)) in
+            tokens.iter().zip(previous_owners)
+        {
+            if let Some(approved_account_ids) = approved_account_ids {
+                self.internal_park_approvals(token_id, &previous_owner_id, &approved_account_ids);
+            }
+            previous_owner_ids.push(previous_owner_id);
+        }
+        let token_ids: Vec<TokenId> = tokens.into_iter().map(|(token_id, _)| token_id).collect();
+
+        // Initiating receiver's call and the callback
+        ext_nft_batch_receiver::ext(receiver_id.clone())
+            .with_static_gas(env::prepaid_gas() - gas_for_call)
+            .nft_on_batch_transfer(sender_id, previous_owner_ids.clone(), token_ids.clone(), msg)
+            .then(
+                ext_nft_batch_resolver::ext(env::current_account_id())
+                    .with_static_gas(gas_for_resolve)
+                    .nft_resolve_batch_transfer(previous_owner_ids, receiver_id, token_ids),
+            )
+            .into()
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenBatchResolver for Contract {
+    /// Returns, for every token, true if it was successfully transferred to `receiver_id`.
+    #[private]
+    fn nft_resolve_batch_transfer(
+        &mut self,
+        previous_owner_ids: Vec<AccountId>,
+        receiver_id: AccountId,
+        token_ids: Vec<TokenId>,
+    ) -> Vec<bool> {
+        // Get which tokens should be returned, all of them if the receiver didn't answer properly
+        let must_revert = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<Vec<bool>>(&value)
+                    .ok()
+                    .filter(|must_revert| must_revert.len() == token_ids.len())
+                    .unwrap_or_else(|| vec![true; token_ids.len()])
+            }
+            PromiseResult::Failed => vec![true; token_ids.len()],
+        };
+
+        let mut reverted = Vec::new();
+        let mut approve_logs = Vec::new();
+        let transferred = previous_owner_ids
+            .iter()
+            .zip(token_ids)
+            .zip(must_revert)
+            .map(|((previous_owner_id, token_id), must_revert)| {
+                let transferred = self.internal_resolve_transfer(
+                    previous_owner_id,
+                    &receiver_id,
+                    &token_id,
+                    must_revert,
+                );
+                if !transferred {
+                    approve_logs.extend(self.internal_approve_logs(previous_owner_id, &token_id));
+                    reverted.push((receiver_id.clone(), previous_owner_id.clone(), None, token_id));
+                }
+                transferred
+            })
+            .collect();
+
+        if !reverted.is_empty() {
+            emit_nft_transfers(&reverted, None);
+        }
+        // the approvals the returned tokens got back, after the transfers that cleared them
+        if !approve_logs.is_empty() {
+            EventLogVariant::NftApprove(approve_logs).emit();
+        }
+        transferred
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn approve(contract: &mut Contract, token_id: &str, account_id: &str) {
+        call(&account("alice.near"), NEAR / 100, 0);
+        contract.nft_approve(token_id.to_string(), account(account_id), None, None);
+    }
+
+    fn approvals(contract: &Contract, token_id: &str) -> HashMap<AccountId, u64> {
+        contract
+            .nft_token(token_id.to_string())
+            .unwrap()
+            .approved_account_ids
+            .unwrap()
+    }
+
+    /// Mints "1", "2" and "3" to alice, approves market on "1" and "2", and sends all three to
+    /// bob with a batch transfer call
+    fn batch_transfer_call(contract: &mut Contract) {
+        for token_id in ["1", "2", "3"] {
+            mint(contract, token_id, "alice.near");
+        }
+        approve(contract, "1", "market.near");
+        approve(contract, "2", "market.near");
+        call(&account("alice.near"), 1, 0);
+        let _ = contract.nft_batch_transfer_call(
+            account("bob.near"),
+            vec![
+                ("1".to_string(), None),
+                ("2".to_string(), None),
+                ("3".to_string(), None),
+            ],
+            None,
+            "".to_string(),
+        );
+    }
+
+    fn resolve(contract: &mut Contract, result: PromiseResult) -> Vec<bool> {
+        callback(vec![result]);
+        contract.nft_resolve_batch_transfer(
+            vec![account("alice.near"); 3],
+            account("bob.near"),
+            vec!["1".to_string(), "2".to_string(), "3".to_string()],
+        )
+    }
+
+    #[test]
+    fn batch_resolve_returns_only_the_refused_tokens() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        batch_transfer_call(&mut contract);
+        for token_id in ["1", "2", "3"] {
+            assert_eq!(owner_of(&contract, token_id), account("bob.near"));
+            assert!(approvals(&contract, token_id).is_empty());
+        }
+
+        let transferred = resolve(
+            &mut contract,
+            PromiseResult::Successful(b"[true,false,true]".to_vec()),
+        );
+
+        assert_eq!(transferred, vec![false, true, false]);
+        assert_eq!(owner_of(&contract, "1"), account("alice.near"));
+        assert_eq!(owner_of(&contract, "2"), account("bob.near"));
+        assert_eq!(owner_of(&contract, "3"), account("alice.near"));
+        // each refused token gets the approvals it had back, the kept one loses them
+        assert_eq!(approvals(&contract, "1")[&account("market.near")], 1);
+        assert!(approvals(&contract, "2").is_empty());
+        assert!(approvals(&contract, "3").is_empty());
+        for token_id in ["1", "2"] {
+            assert!(contract
+                .pending_approvals
+                .get(&(token_id.to_string(), account("alice.near")))
+                .is_none());
+        }
+        assert_eq!(
+            near_sdk::test_utils::get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_transfer","data":[{"old_owner_id":"bob.near","new_owner_id":"alice.near","token_ids":["1","3"]}]}"#,
+                r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_approve","data":[{"owner_id":"alice.near","token_id":"1","account_id":"market.near","approval_id":1}]}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn batch_resolve_returns_everything_on_a_malformed_answer() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        batch_transfer_call(&mut contract);
+
+        let transferred = resolve(
+            &mut contract,
+            PromiseResult::Successful(b"[false]".to_vec()),
+        );
+
+        assert_eq!(transferred, vec![false, false, false]);
+        for token_id in ["1", "2", "3"] {
+            assert_eq!(owner_of(&contract, token_id), account("alice.near"));
+        }
+        assert_eq!(approvals(&contract, "1")[&account("market.near")], 1);
+        assert_eq!(approvals(&contract, "2")[&account("market.near")], 1);
+    }
+
+    #[test]
+    fn batch_resolve_returns_everything_when_the_receiver_fails() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        batch_transfer_call(&mut contract);
+
+        let transferred = resolve(&mut contract, PromiseResult::Failed);
+
+        assert_eq!(transferred, vec![false, false, false]);
+        for token_id in ["1", "2", "3"] {
+            assert_eq!(owner_of(&contract, token_id), account("alice.near"));
+        }
+    }
+
+    fn move_all(contract: &mut Contract, from_index: u128, limit: u64) -> MoveAllPage {
+        call(&account("alice.near"), 1, 0);
+        contract.nft_move_all(account("bob.near"), Some(U128(from_index)), Some(limit))
+    }
+
+    #[test]
+    fn move_all_pages_through_every_token() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        for token_id in ["1", "2", "3", "4", "5"] {
+            mint(&mut contract, token_id, "alice.near");
+        }
+        approve(&mut contract, "1", "market.near");
+
+        let mut from_index = 0;
+        let mut pages = 0;
+        loop {
+            let page = move_all(&mut contract, from_index, 2);
+            pages += 1;
+            assert!(page.skipped_token_ids.is_empty());
+            from_index = page.next_index.0;
+            if page.left.0 == 0 {
+                break;
+            }
+        }
+
+        assert_eq!(pages, 3);
+        assert_eq!(from_index, 0);
+        for token_id in ["1", "2", "3", "4", "5"] {
+            assert_eq!(owner_of(&contract, token_id), account("bob.near"));
+        }
+        assert!(approvals(&contract, "1").is_empty());
+        assert_eq!(contract.nft_supply_for_owner(account("alice.near")).0, 0);
+        assert_eq!(
+            contract
+                .internal_storage_account(&account("alice.near"))
+                .used_bytes,
+            contract.storage_registration_bytes
+        );
+    }
+
+    #[test]
+    fn move_all_skips_locked_tokens() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        for token_id in ["1", "2", "3", "4", "5"] {
+            mint(&mut contract, token_id, "alice.near");
+        }
+        call(&account("alice.near"), 1, 0);
+        contract.nft_stake("2".to_string());
+        call(&owner(), 0, 0);
+        contract.nft_freeze_token("4".to_string(), "stolen".to_string());
+
+        let first = move_all(&mut contract, 0, 3);
+        let second = move_all(&mut contract, first.next_index.0, 3);
+
+        assert!(first.left.0 > 0);
+        assert_eq!(second.left.0, 0);
+        assert_eq!(second.next_index.0, 2);
+        let mut skipped: Vec<TokenId> = first
+            .skipped_token_ids
+            .into_iter()
+            .chain(second.skipped_token_ids)
+            .collect();
+        skipped.sort();
+        assert_eq!(skipped, vec!["2".to_string(), "4".to_string()]);
+        for token_id in ["1", "3", "5"] {
+            assert_eq!(owner_of(&contract, token_id), account("bob.near"));
+        }
+        assert_eq!(owner_of(&contract, "2"), account("alice.near"));
+        assert_eq!(owner_of(&contract, "4"), account("alice.near"));
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Token owner is frozen")]
+    fn move_all_from_a_frozen_account_fails() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        call(&owner(), 0, 0);
+        contract.nft_freeze_account(account("alice.near"), "stolen".to_string());
+        move_all(&mut contract, 0, 10);
+    }
+}
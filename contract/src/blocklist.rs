@@ -0,0 +1,281 @@
+use crate::*;
+use near_sdk::json_types::U64;
+
+/// Accounts added or removed per call at most, to stay within the gas limit
+pub const MAX_BLOCKLIST_UPDATE: usize = 100;
+
+/// Logs accounts being added to or removed from the blocklist
+fn emit_blocklist_event(
+    variant: fn(Vec<NftBlocklistLog>) -> EventLogVariant,
+    account_ids: &[AccountId],
+) {
+    if account_ids.is_empty() {
+        return;
+    }
+    variant(vec![NftBlocklistLog {
+        account_ids: account_ids.iter().map(|id| id.to_string()).collect(),
+    }])
+    .emit();
+}
+
+impl Contract {
+    fn assert_blocklist_update(&self, account_ids: &[AccountId]) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only owner can update the blocklist",
+        );
+        require!(
+            account_ids.len() <= MAX_BLOCKLIST_UPDATE,
+            format!(
+                "cypher: Cannot update more than {} accounts at once",
+                MAX_BLOCKLIST_UPDATE
+            )
+        );
+    }
+
+    /// Panics if the account is on the blocklist.
+    ///
+    /// Checked when minting, transferring, approving, bidding, starting an auction and renting.
+    /// An auction is cancelled at settlement if its seller or winner was blocked since.
+    /// `nft_resolve_transfer` is exempt on purpose: a token refused by the receiver of
+    /// `nft_transfer_call` always goes back to its previous owner, even if one of them was
+    /// blocked while the call was in flight.
+    pub(crate) fn internal_assert_not_blocked(&self, account_id: &AccountId) {
+        require!(
+            !self.blocklist.contains(account_id),
+            format!("cypher: Account {} is blocked", account_id)
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Add accounts to the blocklist: they can't mint, send, receive, bid on, rent or be
+    /// approved for tokens until removed. Accounts already blocked are skipped.
+    pub fn nft_block_accounts(&mut self, account_ids: Vec<AccountId>) {
+        self.assert_blocklist_update(&account_ids);
+
+        let added: Vec<AccountId> = account_ids
+            .into_iter()
+            .filter(|account_id| self.blocklist.insert(account_id))
+            .collect();
+        emit_blocklist_event(EventLogVariant::NftBlock, &added);
+    }
+
+    /// Remove accounts from the blocklist. Accounts that aren't blocked are skipped.
+    pub fn nft_unblock_accounts(&mut self, account_ids: Vec<AccountId>) {
+        self.assert_blocklist_update(&account_ids);
+
+        let removed: Vec<AccountId> = account_ids
+            .into_iter()
+            .filter(|account_id| self.blocklist.remove(account_id))
+            .collect();
+        emit_blocklist_event(EventLogVariant::NftUnblock, &removed);
+    }
+
+    /// get whether an account is on the blocklist
+    pub fn nft_is_blocked(&self, account_id: AccountId) -> bool {
+        self.blocklist.contains(&account_id)
+    }
+
+    /// Query for the blocked accounts using pagination
+    pub fn nft_blocked_accounts(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<AccountId> {
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        require!(
+            (self.blocklist.len() as u128) >= start_index,
+            "cypher: Out of bounds, please use a smaller from_index."
+        );
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+
+        self.blocklist
+            .iter()
+            .skip(start_index as usize)
+            .take(limit)
+            .collect()
+    }
+
+    /// get the number of blocked accounts
+    pub fn nft_blocked_count(&self) -> U64 {
+        self.blocklist.len().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    /// A contract with token "1" owned by alice.near, and mallory.near registered
+    fn setup_token() -> Contract {
+        let mut contract = setup(&["alice.near", "mallory.near"]);
+        mint(&mut contract, "1", "alice.near");
+        contract
+    }
+
+    fn block(contract: &mut Contract, account_id: &str) {
+        call(&owner(), 0, 0);
+        contract.nft_block_accounts(vec![account(account_id)]);
+    }
+
+    fn start_auction(contract: &mut Contract) {
+        call(&account("alice.near"), 1, 0);
+        contract.nft_start_auction(
+            "1".to_string(),
+            NEAR.into(),
+            NEAR.into(),
+            U64(1_000),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account mallory.near is blocked")]
+    fn transfer_to_blocked_account_fails() {
+        let mut contract = setup_token();
+        block(&mut contract, "mallory.near");
+        transfer(&mut contract, "1", "mallory.near", 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account alice.near is blocked")]
+    fn transfer_from_blocked_account_fails() {
+        let mut contract = setup_token();
+        block(&mut contract, "alice.near");
+        transfer(&mut contract, "1", "mallory.near", 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account mallory.near is blocked")]
+    fn transfer_call_to_blocked_account_fails() {
+        let mut contract = setup_token();
+        block(&mut contract, "mallory.near");
+        call(&account("alice.near"), 1, 1);
+        contract.nft_transfer_call(
+            account("mallory.near"),
+            "1".to_string(),
+            None,
+            None,
+            String::new(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account mallory.near is blocked")]
+    fn batch_transfer_to_blocked_account_fails() {
+        let mut contract = setup_token();
+        block(&mut contract, "mallory.near");
+        call(&account("alice.near"), 1, 1);
+        contract.nft_batch_transfer(account("mallory.near"), vec![("1".to_string(), None)], None);
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account mallory.near is blocked")]
+    fn move_all_to_blocked_account_fails() {
+        let mut contract = setup_token();
+        block(&mut contract, "mallory.near");
+        call(&account("alice.near"), 1, 1);
+        contract.nft_move_all(account("mallory.near"), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account mallory.near is blocked")]
+    fn safe_transfer_to_blocked_account_fails() {
+        let mut contract = setup_token();
+        block(&mut contract, "mallory.near");
+        call(&account("alice.near"), 1, 1);
+        contract.nft_safe_transfer(account("mallory.near"), "1".to_string(), None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account mallory.near is blocked")]
+    fn blocked_account_cannot_buy_a_listing() {
+        let mut contract = setup_token();
+        call(&account("alice.near"), 1, 0);
+        contract.nft_list("1".to_string(), NEAR.into(), None);
+        block(&mut contract, "mallory.near");
+        call(&account("mallory.near"), NEAR, 1);
+        contract.nft_buy("1".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account mallory.near is blocked")]
+    fn offer_of_blocked_account_cannot_be_accepted() {
+        let mut contract = setup_token();
+        call(&account("mallory.near"), NEAR, 0);
+        contract.nft_make_offer("1".to_string(), None);
+        block(&mut contract, "mallory.near");
+        call(&account("alice.near"), 1, 1);
+        contract.nft_accept_offer("1".to_string(), account("mallory.near"));
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account mallory.near is blocked")]
+    fn collection_offer_of_blocked_account_cannot_be_filled() {
+        let mut contract = setup_token();
+        call(&account("mallory.near"), NEAR, 0);
+        let offer_id = contract.nft_make_collection_offer(NEAR.into(), 1, None, None);
+        block(&mut contract, "mallory.near");
+        call(&account("alice.near"), 1, 1);
+        contract.nft_fill_collection_offer(offer_id, "1".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account mallory.near is blocked")]
+    fn blocked_account_cannot_buy_from_a_dutch_auction() {
+        let mut contract = setup_token();
+        call(&account("alice.near"), 1, 0);
+        contract.nft_start_dutch_auction(
+            "1".to_string(),
+            DutchAuctionSchedule {
+                start_price: (2 * NEAR).into(),
+                floor_price: NEAR.into(),
+                start_at: None,
+                duration: U64(1_000),
+                step: None,
+                ft_contract_id: None,
+            },
+        );
+        block(&mut contract, "mallory.near");
+        call(&account("mallory.near"), 2 * NEAR, 1);
+        contract.nft_buy_dutch("1".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account mallory.near is blocked")]
+    fn blocked_account_cannot_bid() {
+        let mut contract = setup_token();
+        start_auction(&mut contract);
+        block(&mut contract, "mallory.near");
+        call(&account("mallory.near"), NEAR, 1);
+        contract.nft_bid("1".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account alice.near is blocked")]
+    fn blocked_account_cannot_start_an_auction() {
+        let mut contract = setup_token();
+        block(&mut contract, "alice.near");
+        start_auction(&mut contract);
+    }
+
+    #[test]
+    fn auction_won_by_blocked_account_is_cancelled() {
+        let mut contract = setup_token();
+        start_auction(&mut contract);
+        call(&account("mallory.near"), NEAR, 1);
+        contract.nft_bid("1".to_string());
+        block(&mut contract, "mallory.near");
+
+        call(&account("alice.near"), 0, 1_000_000_000_000_000);
+        contract.nft_settle_auction("1".to_string());
+        assert_eq!(owner_of(&contract, "1"), account("alice.near"));
+        assert!(contract.nft_auction("1".to_string()).is_none());
+    }
+}
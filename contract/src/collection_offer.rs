@@ -0,0 +1,417 @@
+use crate::*;
+use near_sdk::json_types::U64;
+
+/// NEAR or fungible tokens escrowed by `buyer_id` to buy up to `quantity` tokens of the collection, or of one
+/// series, whichever holders fill the offer first, at `price_per_token` each
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionOffer {
+    pub offer_id: U64,
+    pub buyer_id: AccountId,
+    // yoctoNEAR, or units of `ft_contract_id`, paid for each token
+    pub price_per_token: U128,
+    // only tokens minted in this series can fill the offer, any token if unset
+    pub series_id: Option<SeriesId>,
+    // number of tokens the offer was made for
+    pub quantity: u32,
+    // number of tokens bought so far
+    pub filled: u32,
+    // nanoseconds since the epoch after which the offer can't be filled
+    pub expires_at: Option<U64>,
+    // NEP-141 contract the offer is escrowed in, NEAR if unset
+    pub ft_contract_id: Option<AccountId>,
+}
+
+impl CollectionOffer {
+    pub fn remaining(&self) -> u32 {
+        self.quantity - self.filled
+    }
+
+    /// amount still held in escrow for the unfilled tokens
+    pub fn escrowed(&self) -> Balance {
+        self.price_per_token.0 * self.remaining() as Balance
+    }
+
+    pub fn is_expired(&self) -> bool {
+        matches!(self.expires_at, Some(expires_at) if env::block_timestamp() >= expires_at.0)
+    }
+}
+
+/// Logs a collection offer event for `offer`, with the token and seller of a fill
+fn emit_collection_offer_event(
+    variant: fn(Vec<NftCollectionOfferLog>) -> EventLogVariant,
+    offer: &CollectionOffer,
+    fill: Option<(&TokenId, &AccountId)>,
+) {
+    variant(vec![NftCollectionOfferLog {
+        offer_id: offer.offer_id.0.to_string(),
+        buyer_id: offer.buyer_id.to_string(),
+        price_per_token: offer.price_per_token.0.to_string(),
+        remaining: offer.remaining(),
+        series_id: offer.series_id.clone(),
+        expires_at: offer.expires_at.map(|expires_at| expires_at.0.to_string()),
+        token_id: fill.map(|(token_id, _)| token_id.clone()),
+        seller_id: fill.map(|(_, seller_id)| seller_id.to_string()),
+        ft_contract_id: offer.ft_contract_id.as_ref().map(|id| id.to_string()),
+    }])
+    .emit();
+}
+
+impl Contract {
+    /// Returns the collection offer with the given ID or panics
+    fn expect_collection_offer(&self, offer_id: u64) -> CollectionOffer {
+        self.collection_offers
+            .get(&offer_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Collection offer not found"))
+    }
+
+    /// Removes a collection offer and its index entry, crediting the storage back to the buyer.
+    /// The escrowed amount is left to the caller.
+    fn internal_remove_collection_offer(&mut self, offer: &CollectionOffer) {
+        let initial_storage_usage = env::storage_usage();
+        self.collection_offers.remove(&offer.offer_id.0);
+        if let Some(mut offer_ids) = self.collection_offers_by_buyer.get(&offer.buyer_id) {
+            offer_ids.remove(&offer.offer_id.0);
+            if offer_ids.is_empty() {
+                self.collection_offers_by_buyer.remove(&offer.buyer_id);
+            } else {
+                self.collection_offers_by_buyer
+                    .insert(&offer.buyer_id, &offer_ids);
+            }
+        }
+        self.internal_storage_credit(
+            &offer.buyer_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
+    }
+
+    /// Escrows a collection offer out of `amount` in `ft_contract_id`, or NEAR if unset.
+    /// Returns the offer ID and the amount escrowed, the excess is left to the caller.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn internal_make_collection_offer(
+        &mut self,
+        buyer_id: &AccountId,
+        price_per_token: U128,
+        quantity: u32,
+        expiry: Option<U64>,
+        series_id: Option<SeriesId>,
+        ft_contract_id: Option<&AccountId>,
+        amount: Balance,
+    ) -> (U64, Balance) {
+        require!(
+            price_per_token.0 > 0,
+            "cypher: Price must be greater than 0"
+        );
+        require!(quantity > 0, "cypher: Quantity must be greater than 0");
+        require!(
+            !matches!(expiry, Some(expiry) if expiry.0 <= env::block_timestamp()),
+            "cypher: Expiry must be in the future"
+        );
+        let total = price_per_token
+            .0
+            .checked_mul(quantity as Balance)
+            .unwrap_or_else(|| env::panic_str("cypher: Offer total overflows"));
+        require!(
+            amount >= total,
+            format!("cypher: Attached amount must cover {}", total)
+        );
+
+        let offer_id = self.next_collection_offer_id;
+        self.next_collection_offer_id += 1;
+        let offer = CollectionOffer {
+            offer_id: offer_id.into(),
+            buyer_id: buyer_id.clone(),
+            price_per_token,
+            series_id,
+            quantity,
+            filled: 0,
+            expires_at: expiry,
+            ft_contract_id: ft_contract_id.cloned(),
+        };
+
+        let initial_storage_usage = env::storage_usage();
+        self.collection_offers.insert(&offer_id, &offer);
+        let mut offer_ids = self
+            .collection_offers_by_buyer
+            .get(&offer.buyer_id)
+            .unwrap_or_else(|| {
+                UnorderedSet::new(StorageKey::CollectionOffersByBuyerInner {
+                    account_hash: env::sha256(offer.buyer_id.as_bytes()),
+                })
+            });
+        offer_ids.insert(&offer_id);
+        self.collection_offers_by_buyer
+            .insert(&offer.buyer_id, &offer_ids);
+        self.internal_storage_debit(
+            &offer.buyer_id,
+            env::storage_usage() - initial_storage_usage,
+        );
+
+        emit_collection_offer_event(EventLogVariant::NftCollectionOfferMake, &offer, None);
+        (offer_id.into(), total)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Offer `price_per_token` for each of up to `quantity` tokens of the collection, or of
+    /// `series_id` if set. The attached deposit must cover all of them; any excess is refunded.
+    /// Returns the offer ID.
+    #[payable]
+    pub fn nft_make_collection_offer(
+        &mut self,
+        price_per_token: U128,
+        quantity: u32,
+        expiry: Option<U64>,
+        series_id: Option<SeriesId>,
+    ) -> U64 {
+        let deposit = env::attached_deposit();
+        let (offer_id, total) = self.internal_make_collection_offer(
+            &env::predecessor_account_id(),
+            price_per_token,
+            quantity,
+            expiry,
+            series_id,
+            None,
+            deposit,
+        );
+        if deposit > total {
+            Promise::new(env::predecessor_account_id()).transfer(deposit - total);
+        }
+        offer_id
+    }
+
+    /// Sell a token you own into a collection offer. The token goes to the buyer and the price
+    /// is split between the royalty recipients and you as in `nft_payout`.
+    #[payable]
+    pub fn nft_fill_collection_offer(&mut self, offer_id: U64, token_id: TokenId) {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+        let mut offer = self.expect_collection_offer(offer_id.0);
+        require!(!offer.is_expired(), "cypher: The offer has expired");
+        require!(
+            self.owner_by_id.get(&token_id).as_ref() == Some(&owner_id),
+            "cypher: Predecessor must be token owner"
+        );
+        require!(
+            owner_id != offer.buyer_id,
+            "cypher: Cannot fill your own offer"
+        );
+        if let Some(series_id) = &offer.series_id {
+            require!(
+                self.series_by_token.get(&token_id).as_ref() == Some(series_id),
+                "cypher: Token is not from the series of the offer"
+            );
+        }
+
+        offer.filled += 1;
+        if offer.remaining() == 0 {
+            self.internal_remove_collection_offer(&offer);
+        } else {
+            self.collection_offers.insert(&offer_id.0, &offer);
+        }
+
+        let payout = self.internal_payout(&owner_id, &token_id, offer.price_per_token.0, u32::MAX);
+        let (_, approved_account_ids) = self.internal_transfer(
+            &owner_id,
+            &offer.buyer_id,
+            &token_id,
+            None,
+            &Some("nft_fill_collection_offer".to_string()),
+        );
+
+        // credit the seller for the storage of the cleared approvals
+        if let Some(approved_account_ids) = approved_account_ids {
+            self.internal_storage_credit(
+                &owner_id,
+                bytes_for_approved_account_ids_raw(&approved_account_ids),
+            );
+        }
+        self.internal_record_sale(
+            &token_id,
+            &owner_id,
+            &offer.buyer_id,
+            offer.price_per_token.0,
+            offer.ft_contract_id.as_ref(),
+            &payout,
+        );
+        self.internal_distribute_payout(offer.ft_contract_id.as_ref(), payout, &offer.buyer_id);
+        emit_collection_offer_event(
+            EventLogVariant::NftCollectionOfferFill,
+            &offer,
+            Some((&token_id, &owner_id)),
+        );
+    }
+
+    /// Cancel a collection offer and get back the escrow of the unfilled tokens. The buyer can
+    /// cancel at any time, anyone can clear an expired offer.
+    #[payable]
+    pub fn nft_cancel_collection_offer(&mut self, offer_id: U64) {
+        assert_one_yocto();
+        let offer = self.expect_collection_offer(offer_id.0);
+        require!(
+            env::predecessor_account_id() == offer.buyer_id || offer.is_expired(),
+            "cypher: Only the buyer can cancel a live offer"
+        );
+
+        self.internal_remove_collection_offer(&offer);
+        self.internal_pay(
+            offer.ft_contract_id.as_ref(),
+            &offer.buyer_id,
+            offer.escrowed(),
+            &offer.buyer_id,
+        );
+        emit_collection_offer_event(EventLogVariant::NftCollectionOfferCancel, &offer, None);
+    }
+
+    /// get a collection offer
+    pub fn nft_collection_offer(&self, offer_id: U64) -> Option<CollectionOffer> {
+        self.collection_offers.get(&offer_id.0)
+    }
+
+    /// Query for the open collection offers using pagination, expired offers included
+    pub fn nft_collection_offers(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<CollectionOffer> {
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+        self.collection_offers
+            .values()
+            .skip(start_index as usize)
+            .take(limit)
+            .collect()
+    }
+
+    /// Query for the collection offers made by an account using pagination
+    pub fn nft_collection_offers_by_account(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<CollectionOffer> {
+        let offer_ids = if let Some(offer_ids) = self.collection_offers_by_buyer.get(&account_id) {
+            offer_ids
+        } else {
+            return vec![];
+        };
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+        offer_ids
+            .iter()
+            .skip(start_index as usize)
+            .take(limit)
+            .filter_map(|offer_id| self.collection_offers.get(&offer_id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn mint_in_series(contract: &mut Contract, token_id: &str, owner_id: &str, series_id: &str) {
+        call(&owner(), 0, 0);
+        contract.nft_mint(
+            token_id.to_string(),
+            metadata(),
+            account(owner_id),
+            None,
+            Some(series_id.to_string()),
+        );
+    }
+
+    fn series_offer(contract: &mut Contract, quantity: u32) -> U64 {
+        call(&account("carol.near"), 2 * NEAR * quantity as Balance, 0);
+        contract.nft_make_collection_offer(
+            (2 * NEAR).into(),
+            quantity,
+            None,
+            Some("genesis".to_string()),
+        )
+    }
+
+    #[test]
+    fn series_offer_is_filled_by_tokens_of_the_series() {
+        let mut contract = setup(&["alice.near", "bob.near", "carol.near"]);
+        mint_in_series(&mut contract, "1", "alice.near", "genesis");
+        mint_in_series(&mut contract, "2", "bob.near", "genesis");
+        let offer_id = series_offer(&mut contract, 3);
+
+        call(&account("alice.near"), 1, 1);
+        contract.nft_fill_collection_offer(offer_id, "1".to_string());
+        call(&account("bob.near"), 1, 1);
+        contract.nft_fill_collection_offer(offer_id, "2".to_string());
+
+        assert_eq!(owner_of(&contract, "1"), account("carol.near"));
+        assert_eq!(owner_of(&contract, "2"), account("carol.near"));
+        let offer = contract.nft_collection_offer(offer_id).unwrap();
+        assert_eq!(offer.remaining(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Token is not from the series of the offer")]
+    fn series_offer_rejects_other_tokens() {
+        let mut contract = setup(&["alice.near", "carol.near"]);
+        mint_in_series(&mut contract, "1", "alice.near", "other");
+        mint(&mut contract, "2", "alice.near");
+        let offer_id = series_offer(&mut contract, 1);
+
+        call(&account("alice.near"), 1, 1);
+        contract.nft_fill_collection_offer(offer_id, "1".to_string());
+    }
+
+    #[test]
+    fn collection_offer_without_series_takes_any_token() {
+        let mut contract = setup(&["alice.near", "carol.near"]);
+        mint(&mut contract, "1", "alice.near");
+        call(&account("carol.near"), NEAR, 0);
+        let offer_id = contract.nft_make_collection_offer(NEAR.into(), 1, None, None);
+
+        call(&account("alice.near"), 1, 1);
+        contract.nft_fill_collection_offer(offer_id, "1".to_string());
+        assert_eq!(owner_of(&contract, "1"), account("carol.near"));
+        assert!(contract.nft_collection_offer(offer_id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Predecessor is not allowed to mint")]
+    fn outsiders_cannot_mint_into_a_series_to_fill_an_offer() {
+        let mut contract = setup(&["alice.near", "carol.near", "mallory.near"]);
+        mint_in_series(&mut contract, "1", "alice.near", "genesis");
+        series_offer(&mut contract, 1);
+
+        call(&account("mallory.near"), NEAR, 0);
+        contract.nft_mint(
+            "fake".to_string(),
+            metadata(),
+            account("mallory.near"),
+            None,
+            Some("genesis".to_string()),
+        );
+    }
+
+    #[test]
+    fn minters_can_mint_into_a_series() {
+        let mut contract = setup(&["alice.near", "carol.near", "minter.near"]);
+        call(&owner(), 0, 0);
+        contract.allow_minting_access(account("minter.near"));
+        call(&account("minter.near"), 0, 0);
+        contract.nft_mint(
+            "1".to_string(),
+            metadata(),
+            account("alice.near"),
+            None,
+            Some("genesis".to_string()),
+        );
+        let offer_id = series_offer(&mut contract, 1);
+
+        call(&account("alice.near"), 1, 1);
+        contract.nft_fill_collection_offer(offer_id, "1".to_string());
+        assert_eq!(owner_of(&contract, "1"), account("carol.near"));
+    }
+}
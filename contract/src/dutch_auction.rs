@@ -0,0 +1,466 @@
+use crate::*;
+use near_sdk::json_types::U64;
+
+/// What a Dutch auction sells: a token minted to the buyer, or an existing token
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum DutchAuctionKind {
+    Mint {
+        token_metadata: Box<TokenMetadata>,
+        perpetual_royalties: Option<HashMap<AccountId, u32>>,
+    },
+    Resale,
+}
+
+/// Price schedule of a Dutch auction: the price decays from `start_price` to `floor_price`
+/// over `duration` nanoseconds from `start_at` (now by default), every `step` nanoseconds if set.
+/// Prices are in yoctoNEAR, or in an accepted fungible token if `ft_contract_id` is set.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DutchAuctionSchedule {
+    pub start_price: U128,
+    pub floor_price: U128,
+    pub start_at: Option<U64>,
+    pub duration: U64,
+    pub step: Option<U64>,
+    pub ft_contract_id: Option<AccountId>,
+}
+
+/// A descending-price sale started with `nft_start_dutch_auction` or `nft_start_dutch_drop`.
+/// The first buyer pays the current price.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DutchAuction {
+    pub token_id: TokenId,
+    pub seller_id: AccountId,
+    // price at `start_at`, in yoctoNEAR or the smallest unit of `ft_contract_id`
+    pub start_price: U128,
+    // price from `end_at` on
+    pub floor_price: U128,
+    // nanoseconds since the epoch between which the price decays
+    pub start_at: U64,
+    pub end_at: U64,
+    // if set, the price drops every `step` nanoseconds instead of continuously
+    pub step: Option<U64>,
+    pub kind: DutchAuctionKind,
+    // NEP-141 contract the price is paid in through `ft_transfer_call`, NEAR if unset
+    pub ft_contract_id: Option<AccountId>,
+}
+
+impl DutchAuction {
+    /// The price a buyer pays at `timestamp`
+    pub fn price_at(&self, timestamp: u64) -> Balance {
+        let (start_at, end_at) = (self.start_at.0, self.end_at.0);
+        if timestamp <= start_at {
+            return self.start_price.0;
+        }
+        if timestamp >= end_at {
+            return self.floor_price.0;
+        }
+        let mut elapsed = timestamp - start_at;
+        if let Some(step) = self.step.filter(|step| step.0 > 0) {
+            elapsed -= elapsed % step.0;
+        }
+        // decay * elapsed / duration, split so that no product overflows
+        let decay = self.start_price.0 - self.floor_price.0;
+        let (elapsed, duration) = (u128::from(elapsed), u128::from(end_at - start_at));
+        self.start_price.0 - (decay / duration * elapsed + decay % duration * elapsed / duration)
+    }
+}
+
+/// Logs a Dutch auction event for `auction`, with the buyer and price of a sale
+fn emit_dutch_auction_event(
+    variant: fn(Vec<NftDutchAuctionLog>) -> EventLogVariant,
+    auction: &DutchAuction,
+    sale: Option<(&AccountId, Balance)>,
+) {
+    variant(vec![NftDutchAuctionLog {
+        seller_id: auction.seller_id.to_string(),
+        token_id: auction.token_id.clone(),
+        start_price: auction.start_price.0.to_string(),
+        floor_price: auction.floor_price.0.to_string(),
+        end_at: auction.end_at.0.to_string(),
+        buyer_id: sale.map(|(buyer_id, _)| buyer_id.to_string()),
+        price: sale.map(|(_, price)| price.to_string()),
+        ft_contract_id: auction.ft_contract_id.as_ref().map(|id| id.to_string()),
+    }])
+    .emit();
+}
+
+impl Contract {
+    /// Stores a Dutch auction following `schedule`, charging the seller for the storage
+    fn internal_add_dutch_auction(
+        &mut self,
+        token_id: TokenId,
+        seller_id: AccountId,
+        schedule: DutchAuctionSchedule,
+        kind: DutchAuctionKind,
+    ) {
+        require!(
+            schedule.start_price.0 >= schedule.floor_price.0,
+            "cypher: Start price must not be lower than the floor price"
+        );
+        require!(schedule.duration.0 > 0, "cypher: Duration must be positive");
+        self.internal_assert_accepted_ft(schedule.ft_contract_id.as_ref());
+        let start_at = schedule
+            .start_at
+            .map(|start_at| start_at.0)
+            .unwrap_or_else(env::block_timestamp);
+        let auction = DutchAuction {
+            token_id,
+            seller_id,
+            start_price: schedule.start_price,
+            floor_price: schedule.floor_price,
+            start_at: start_at.into(),
+            end_at: start_at.saturating_add(schedule.duration.0).into(),
+            step: schedule.step,
+            kind,
+            ft_contract_id: schedule.ft_contract_id,
+        };
+
+        let initial_storage_usage = env::storage_usage();
+        self.dutch_auctions.insert(&auction.token_id, &auction);
+        self.internal_storage_debit(
+            &auction.seller_id,
+            env::storage_usage() - initial_storage_usage,
+        );
+        emit_dutch_auction_event(EventLogVariant::NftDutchAuctionStart, &auction, None);
+    }
+
+    /// Removes a Dutch auction, crediting the storage back to the seller
+    fn internal_remove_dutch_auction(&mut self, token_id: &TokenId) -> Option<DutchAuction> {
+        let initial_storage_usage = env::storage_usage();
+        let auction = self.dutch_auctions.remove(token_id)?;
+        self.internal_storage_credit(
+            &auction.seller_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
+        Some(auction)
+    }
+
+    /// Cancels the Dutch auction of a token, if any. Nothing is escrowed until a sale.
+    pub(crate) fn internal_cancel_dutch_auction(&mut self, token_id: &TokenId) {
+        if let Some(auction) = self.internal_remove_dutch_auction(token_id) {
+            emit_dutch_auction_event(EventLogVariant::NftDutchAuctionCancel, &auction, None);
+        }
+    }
+
+    /// Returns true if the token is to be minted by a Dutch auction started as a drop
+    pub(crate) fn internal_is_drop(&self, token_id: &TokenId) -> bool {
+        matches!(
+            self.dutch_auctions
+                .get(token_id)
+                .map(|auction| auction.kind),
+            Some(DutchAuctionKind::Mint { .. })
+        )
+    }
+
+    /// Sells a token from a Dutch auction to `buyer_id` for `amount` paid in `ft_contract_id`,
+    /// or NEAR if unset. Returns the current price and how it is split, the whole price going
+    /// to the seller of a drop, for the caller to pay out in the same currency and to refund
+    /// the rest of `amount`.
+    pub(crate) fn internal_buy_dutch(
+        &mut self,
+        buyer_id: &AccountId,
+        token_id: &TokenId,
+        ft_contract_id: Option<&AccountId>,
+        amount: Balance,
+    ) -> (Balance, Payout) {
+        let auction = self
+            .dutch_auctions
+            .get(token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token is not in a Dutch auction"));
+        require!(
+            auction.ft_contract_id.as_ref() == ft_contract_id,
+            "cypher: The Dutch auction is in another currency"
+        );
+        let now = env::block_timestamp();
+        require!(
+            now >= auction.start_at.0,
+            "cypher: The Dutch auction has not started yet"
+        );
+        let price = auction.price_at(now);
+        require!(
+            amount >= price,
+            format!("cypher: Must pay at least {}", price)
+        );
+        require!(
+            buyer_id != &auction.seller_id,
+            "cypher: The seller cannot buy"
+        );
+        self.internal_remove_dutch_auction(token_id);
+
+        let payout = match &auction.kind {
+            DutchAuctionKind::Mint {
+                token_metadata,
+                perpetual_royalties,
+            } => {
+                // the seller pays for the new token, as when minting, but without a balance
+                // check: a seller short of storage deposit must not be able to refuse the sale
+                let initial_storage_usage = env::storage_usage();
+                self.internal_mint(
+                    buyer_id.clone(),
+                    token_id.clone(),
+                    perpetual_royalties.clone(),
+                    token_metadata.as_ref().clone(),
+                );
+                self.internal_storage_debit_unchecked(
+                    &auction.seller_id,
+                    env::storage_usage() - initial_storage_usage,
+                );
+                let mut payout = HashMap::new();
+                payout.insert(auction.seller_id.clone(), price.into());
+                Payout { payout }
+            }
+            DutchAuctionKind::Resale => {
+                let payout = self.internal_payout(&auction.seller_id, token_id, price, u32::MAX);
+                let (_, approved_account_ids) = self.internal_transfer(
+                    &auction.seller_id,
+                    buyer_id,
+                    token_id,
+                    None,
+                    &Some("nft_buy_dutch".to_string()),
+                );
+
+                // credit the seller for the storage of the cleared approvals
+                if let Some(approved_account_ids) = approved_account_ids {
+                    self.internal_storage_credit(
+                        &auction.seller_id,
+                        bytes_for_approved_account_ids_raw(&approved_account_ids),
+                    );
+                }
+                payout
+            }
+        };
+
+        self.internal_record_sale(
+            token_id,
+            &auction.seller_id,
+            buyer_id,
+            price,
+            ft_contract_id,
+            &payout,
+        );
+        emit_dutch_auction_event(
+            EventLogVariant::NftDutchAuctionSale,
+            &auction,
+            Some((buyer_id, price)),
+        );
+        (price, payout)
+    }
+
+    /// Returns true if the token, or the token ID of a drop, is in a Dutch auction
+    pub(crate) fn internal_is_in_dutch_auction(&self, token_id: &TokenId) -> bool {
+        self.dutch_auctions.get(token_id).is_some()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sell a token you own at a price decaying along `schedule`.
+    /// The token can't be transferred until it is sold or the auction is cancelled.
+    #[payable]
+    pub fn nft_start_dutch_auction(&mut self, token_id: TokenId, schedule: DutchAuctionSchedule) {
+        assert_one_yocto();
+        let seller_id = env::predecessor_account_id();
+        let owner_id = self
+            .owner_by_id
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token not found"));
+        require!(
+            seller_id == owner_id,
+            "cypher: Predecessor must be token owner"
+        );
+        self.internal_assert_token_unlocked(&token_id);
+
+        self.internal_add_dutch_auction(token_id, seller_id, schedule, DutchAuctionKind::Resale);
+    }
+
+    /// Sell a new token, minted to the first buyer, at a price decaying along `schedule`.
+    /// Only the contract owner and accounts with minting access can start a drop.
+    #[payable]
+    pub fn nft_start_dutch_drop(
+        &mut self,
+        token_id: TokenId,
+        token_metadata: TokenMetadata,
+        perpetual_royalties: Option<HashMap<AccountId, u32>>,
+        schedule: DutchAuctionSchedule,
+    ) {
+        assert_one_yocto();
+        let seller_id = env::predecessor_account_id();
+        require!(
+            self.internal_is_minter(&seller_id),
+            "cypher: Predecessor is not allowed to mint"
+        );
+        require!(
+            self.owner_by_id.get(&token_id).is_none()
+                && !self.internal_is_in_dutch_auction(&token_id),
+            "cypher: token_id must be unique"
+        );
+        // fail now rather than in the buyer's transaction
+        if let Some(perpetual_royalties) = &perpetual_royalties {
+            self.royalty_policy
+                .assert_valid_royalties(perpetual_royalties);
+        }
+
+        self.internal_add_dutch_auction(
+            token_id,
+            seller_id,
+            schedule,
+            DutchAuctionKind::Mint {
+                token_metadata: Box::new(token_metadata),
+                perpetual_royalties,
+            },
+        );
+    }
+
+    /// Buy a token from a Dutch auction with the attached deposit, paying the current price.
+    /// Resales are split between the royalty recipients and the seller as in `nft_payout`,
+    /// any overpayment is refunded.
+    #[payable]
+    pub fn nft_buy_dutch(&mut self, token_id: TokenId) {
+        let buyer_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        let (price, payout) = self.internal_buy_dutch(&buyer_id, &token_id, None, deposit);
+
+        distribute_payout(payout);
+        if deposit > price {
+            Promise::new(buyer_id).transfer(deposit - price);
+        }
+    }
+
+    /// Cancel a Dutch auction you started
+    #[payable]
+    pub fn nft_cancel_dutch_auction(&mut self, token_id: TokenId) {
+        assert_one_yocto();
+        let auction = self
+            .dutch_auctions
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token is not in a Dutch auction"));
+        require!(
+            env::predecessor_account_id() == auction.seller_id,
+            "cypher: Predecessor must be the seller"
+        );
+
+        self.internal_remove_dutch_auction(&token_id);
+        emit_dutch_auction_event(EventLogVariant::NftDutchAuctionCancel, &auction, None);
+    }
+
+    /// get the price a buyer would pay right now for a token in a Dutch auction
+    pub fn nft_dutch_price(&self, token_id: TokenId) -> Option<U128> {
+        self.dutch_auctions
+            .get(&token_id)
+            .map(|auction| auction.price_at(env::block_timestamp()).into())
+    }
+
+    /// get the Dutch auction of a token, if it is in one
+    pub fn nft_dutch_auction(&self, token_id: TokenId) -> Option<DutchAuction> {
+        self.dutch_auctions.get(&token_id)
+    }
+
+    /// Query for the running Dutch auctions using pagination
+    pub fn nft_dutch_auctions(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<DutchAuction> {
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        require!(
+            (self.dutch_auctions.len() as u128) >= start_index,
+            "cypher: Out of bounds, please use a smaller from_index."
+        );
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+
+        self.dutch_auctions
+            .values()
+            .skip(start_index as usize)
+            .take(limit)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_contract_standards::storage_management::StorageManagement;
+
+    const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+    fn auction(start_price: Balance, floor_price: Balance, duration: u64) -> DutchAuction {
+        DutchAuction {
+            token_id: "1".to_string(),
+            seller_id: account("seller.near"),
+            start_price: start_price.into(),
+            floor_price: floor_price.into(),
+            start_at: DAY.into(),
+            end_at: (DAY + duration).into(),
+            step: None,
+            kind: DutchAuctionKind::Resale,
+            ft_contract_id: None,
+        }
+    }
+
+    #[test]
+    fn price_decays_linearly() {
+        let auction = auction(100 * NEAR, 10 * NEAR, 3 * DAY);
+        assert_eq!(auction.price_at(0), 100 * NEAR);
+        assert_eq!(auction.price_at(DAY), 100 * NEAR);
+        assert_eq!(auction.price_at(DAY + DAY), 70 * NEAR);
+        assert_eq!(auction.price_at(DAY + 3 * DAY / 2), 55 * NEAR);
+        assert_eq!(auction.price_at(4 * DAY), 10 * NEAR);
+        assert_eq!(auction.price_at(u64::MAX), 10 * NEAR);
+    }
+
+    #[test]
+    fn large_decay_over_days_does_not_overflow() {
+        // 1e6 NEAR times a week in nanoseconds is far above u128::MAX
+        let auction = auction(1_000_000 * NEAR, 0, 7 * DAY);
+        let mut previous = auction.start_price.0;
+        for hours in 1..7 * 24 {
+            let price = auction.price_at(DAY + hours * DAY / 24);
+            assert!(price < previous);
+            previous = price;
+        }
+        assert_eq!(auction.price_at(DAY + 7 * DAY / 2), 500_000 * NEAR);
+    }
+
+    #[test]
+    fn stepped_price_drops_at_each_step() {
+        let mut auction = auction(10 * NEAR, NEAR, 9 * DAY);
+        auction.step = Some(DAY.into());
+        assert_eq!(auction.price_at(DAY + DAY - 1), 10 * NEAR);
+        assert_eq!(auction.price_at(DAY + DAY), 9 * NEAR);
+        assert_eq!(auction.price_at(DAY + 5 * DAY + DAY / 2), 5 * NEAR);
+    }
+
+    #[test]
+    fn drop_mints_even_if_the_seller_storage_balance_ran_out() {
+        let mut contract = setup(&["alice.near"]);
+        call(&owner(), 1, 0);
+        contract.nft_start_dutch_drop(
+            "1".to_string(),
+            metadata(),
+            None,
+            DutchAuctionSchedule {
+                start_price: U128(10 * NEAR),
+                floor_price: U128(NEAR),
+                start_at: None,
+                duration: U64(DAY),
+                step: None,
+                ft_contract_id: None,
+            },
+        );
+        call(&owner(), 1, 0);
+        contract.storage_withdraw(None);
+        let used_bytes = contract.internal_storage_account(&owner()).used_bytes;
+
+        call(&account("alice.near"), 10 * NEAR, 0);
+        contract.nft_buy_dutch("1".to_string());
+
+        assert_eq!(owner_of(&contract, "1"), account("alice.near"));
+        assert!(contract.internal_storage_account(&owner()).used_bytes > used_bytes);
+        assert_eq!(contract.storage_balance_of(owner()).unwrap().available.0, 0);
+    }
+}
@@ -92,12 +92,14 @@ impl Contract {
         } else {
             None
         };
+        let freeze = self.internal_token_freeze(&token_id, &owner_id);
         TokenJson {
             token_id,
             owner_id,
             metadata,
             royalty,
             approved_account_ids,
+            freeze,
         }
     }
 }
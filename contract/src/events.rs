@@ -1,9 +1,28 @@
 use std::fmt;
 
+use near_sdk::env;
 use near_sdk::serde::{Deserialize, Serialize};
 
+use crate::{NFT_EVENT_VERSION, NFT_STANDARD_NAME};
+
 /// Enum that represents the data type of the EventLog.
-/// The enum can either be an NftMint or an NftTransfer.
+///
+/// Variants
+/// * tokens: mint, transfer, burn, token and contract metadata update
+/// * approvals: approve, revoke, revoke all
+/// * safe transfers: pending, accepted, cancelled, lapsed
+/// * market: listings, English and Dutch auctions, token and collection offers, failed fungible
+///   token payouts
+/// * royalties: a share changing hands
+/// * rentals: the user of a token, rental listings
+/// * staking: stake, unstake
+/// * revenue: deposits and claims
+/// * recovery: freezing, opting out, the steps of recovering a frozen token
+/// * access: blocked accounts, roles, pausing the contract
+/// * governance: proposals being created or executed, votes
+///
+/// Every state change is logged through [`EventLogVariant::emit`], so that all events share the
+/// same standard and version.
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "event", content = "data")]
 #[serde(rename_all = "snake_case")]
@@ -12,6 +31,65 @@ use near_sdk::serde::{Deserialize, Serialize};
 pub enum EventLogVariant {
     NftMint(Vec<NftMintLog>),
     NftTransfer(Vec<NftTransferLog>),
+    NftBurn(Vec<NftBurnLog>),
+    NftMetadataUpdate(Vec<NftMetadataUpdateLog>),
+    ContractMetadataUpdate(Vec<NftContractMetadataUpdateLog>),
+    NftApprove(Vec<NftApproveLog>),
+    NftRevoke(Vec<NftRevokeLog>),
+    NftRevokeAll(Vec<NftRevokeAllLog>),
+    NftTransferPending(Vec<NftPendingTransferLog>),
+    NftTransferAccepted(Vec<NftPendingTransferLog>),
+    NftTransferCancelled(Vec<NftPendingTransferLog>),
+    NftTransferLapsed(Vec<NftPendingTransferLog>),
+    NftList(Vec<NftListingLog>),
+    NftDelist(Vec<NftListingLog>),
+    NftUpdatePrice(Vec<NftListingLog>),
+    NftSale(Vec<NftListingLog>),
+    NftAuctionStart(Vec<NftAuctionLog>),
+    NftBid(Vec<NftAuctionLog>),
+    NftAuctionCancel(Vec<NftAuctionLog>),
+    NftAuctionSettle(Vec<NftAuctionLog>),
+    NftDutchAuctionStart(Vec<NftDutchAuctionLog>),
+    NftDutchAuctionCancel(Vec<NftDutchAuctionLog>),
+    NftDutchAuctionSale(Vec<NftDutchAuctionLog>),
+    NftOfferMake(Vec<NftOfferLog>),
+    NftOfferCancel(Vec<NftOfferLog>),
+    NftOfferAccept(Vec<NftOfferLog>),
+    NftCollectionOfferMake(Vec<NftCollectionOfferLog>),
+    NftCollectionOfferCancel(Vec<NftCollectionOfferLog>),
+    NftCollectionOfferFill(Vec<NftCollectionOfferLog>),
+    NftFtPayoutFailed(Vec<NftFtPayoutLog>),
+    NftRoyaltyUpdate(Vec<NftRoyaltyLog>),
+    NftUpdateUser(Vec<NftUserLog>),
+    NftRentalList(Vec<NftRentalLog>),
+    NftRentalDelist(Vec<NftRentalLog>),
+    NftStake(Vec<NftStakeLog>),
+    NftUnstake(Vec<NftStakeLog>),
+    NftFreeze(Vec<NftFreezeLog>),
+    NftUnfreeze(Vec<NftFreezeLog>),
+    NftRecoveryOptOut(Vec<NftFreezeLog>),
+    NftRecoveryOptIn(Vec<NftFreezeLog>),
+    NftRecoverySchedule(Vec<NftRecoveryLog>),
+    NftRecoveryCancel(Vec<NftRecoveryLog>),
+    NftRecover(Vec<NftRecoveryLog>),
+    NftBlock(Vec<NftBlocklistLog>),
+    NftUnblock(Vec<NftBlocklistLog>),
+    ContractPause(Vec<NftPauseLog>),
+    ContractUnpause(Vec<NftPauseLog>),
+    RoleGrant(Vec<NftRoleLog>),
+    RoleRevoke(Vec<NftRoleLog>),
+    NftProposalCreate(Vec<NftProposalLog>),
+    NftProposalExecute(Vec<NftProposalLog>),
+    NftVote(Vec<NftVoteLog>),
+    NftRevenueDeposit(Vec<NftRevenueLog>),
+    NftRevenueClaim(Vec<NftRevenueLog>),
+}
+
+impl EventLogVariant {
+    /// Logs the event with the contract's standard and version
+    pub fn emit(self) {
+        env::log_str(&EventLog::new(self).to_string());
+    }
 }
 
 /// Interface to capture data about an event
@@ -31,6 +109,16 @@ pub struct EventLog {
     pub event: EventLogVariant,
 }
 
+impl EventLog {
+    pub fn new(event: EventLogVariant) -> Self {
+        Self {
+            standard: NFT_STANDARD_NAME.to_string(),
+            version: NFT_EVENT_VERSION.to_string(),
+            event,
+        }
+    }
+}
+
 impl fmt::Display for EventLog {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_fmt(format_args!(
@@ -78,6 +166,448 @@ pub struct NftTransferLog {
     pub memo: Option<String>,
 }
 
+/// An event log to capture token burning
+///
+/// Arguments
+/// * `owner_id`: owner of the burnt tokens
+/// * `token_ids`: ["1", "abc"]
+/// * `authorized_id`: approved account that burnt the tokens
+/// * `memo`: optional message
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftBurnLog {
+    pub owner_id: String,
+    pub token_ids: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorized_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+/// An event log to capture a change to the metadata of tokens
+///
+/// Arguments
+/// * `token_ids`: ["1", "abc"]
+/// * `memo`: optional message
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftMetadataUpdateLog {
+    pub token_ids: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+/// An event log to capture a change to the contract metadata
+///
+/// Arguments
+/// * `memo`: optional message
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftContractMetadataUpdateLog {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+/// An event log to capture an approval being granted
+///
+/// Arguments
+/// * `owner_id`: "owner.near"
+/// * `token_id`: "1"
+/// * `account_id`: "market.near"
+/// * `approval_id`: ID of the approval
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftApproveLog {
+    pub owner_id: String,
+    pub token_id: String,
+    pub account_id: String,
+    pub approval_id: u64,
+}
+
+/// An event log to capture an approval being revoked by the owner or the contract
+///
+/// Arguments
+/// * `owner_id`: "owner.near"
+/// * `token_id`: "1"
+/// * `account_id`: "market.near"
+/// * `memo`: optional message
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftRevokeLog {
+    pub owner_id: String,
+    pub token_id: String,
+    pub account_id: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+/// An event log to capture all approvals on a token being revoked
+///
+/// Arguments
+/// * `owner_id`: "owner.near"
+/// * `token_id`: "1"
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftRevokeAllLog {
+    pub owner_id: String,
+    pub token_id: String,
+}
+
+/// An event log to capture a step of a safe transfer
+///
+/// Arguments
+/// * `owner_id`: "owner.near"
+/// * `receiver_id`: "receiver.near"
+/// * `token_id`: "1"
+/// * `expires_at`: nanoseconds since the epoch, as a string
+/// * `memo`: optional message
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftPendingTransferLog {
+    pub owner_id: String,
+    pub receiver_id: String,
+    pub token_id: String,
+    pub expires_at: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+/// An event log to capture a change to a market listing
+///
+/// Arguments
+/// * `seller_id`: "seller.near"
+/// * `token_id`: "1"
+/// * `price`: yoctoNEAR, or units of `ft_contract_id`, as a string
+/// * `ft_contract_id`: fungible token the price is paid in, if not NEAR
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftListingLog {
+    pub seller_id: String,
+    pub token_id: String,
+    pub price: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ft_contract_id: Option<String>,
+}
+
+/// An event log to capture a step of an auction
+///
+/// Arguments
+/// * `seller_id`: "seller.near"
+/// * `token_id`: "1"
+/// * `bidder_id`: highest bidder, if any
+/// * `amount`: highest bid in yoctoNEAR, or units of `ft_contract_id`, as a string, if any
+/// * `end_at`: nanoseconds since the epoch, as a string
+/// * `ft_contract_id`: fungible token the bids are in, if not NEAR
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftAuctionLog {
+    pub seller_id: String,
+    pub token_id: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bidder_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<String>,
+
+    pub end_at: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ft_contract_id: Option<String>,
+}
+
+/// An event log to capture a step of a Dutch auction
+///
+/// Arguments
+/// * `seller_id`: "seller.near"
+/// * `token_id`: "1"
+/// * `start_price`: yoctoNEAR, or units of `ft_contract_id`, as a string
+/// * `floor_price`: yoctoNEAR, or units of `ft_contract_id`, as a string
+/// * `end_at`: nanoseconds since the epoch at which the floor is reached, as a string
+/// * `buyer_id`: buyer of a sale
+/// * `price`: price paid in a sale, as a string
+/// * `ft_contract_id`: fungible token the prices are in, if not NEAR
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftDutchAuctionLog {
+    pub seller_id: String,
+    pub token_id: String,
+    pub start_price: String,
+    pub floor_price: String,
+    pub end_at: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buyer_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ft_contract_id: Option<String>,
+}
+
+/// An event log to capture a step of an offer
+///
+/// Arguments
+/// * `token_id`: "1"
+/// * `buyer_id`: "buyer.near"
+/// * `owner_id`: owner the offer was made to
+/// * `amount`: escrowed yoctoNEAR, or units of `ft_contract_id`, as a string
+/// * `expires_at`: nanoseconds since the epoch as a string, if the offer expires
+/// * `ft_contract_id`: fungible token the amount is in, if not NEAR
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftOfferLog {
+    pub token_id: String,
+    pub buyer_id: String,
+    pub owner_id: String,
+    pub amount: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ft_contract_id: Option<String>,
+}
+
+/// An event log to capture a step of a collection offer
+///
+/// Arguments
+/// * `offer_id`: "0"
+/// * `buyer_id`: "buyer.near"
+/// * `price_per_token`: yoctoNEAR, or units of `ft_contract_id`, as a string
+/// * `remaining`: number of tokens the offer can still buy
+/// * `series_id`: series the tokens must be from, if the offer is limited to one
+/// * `expires_at`: nanoseconds since the epoch as a string, if the offer expires
+/// * `token_id`: token sold into the offer, on fills
+/// * `seller_id`: owner who sold the token, on fills
+/// * `ft_contract_id`: fungible token the price is in, if not NEAR
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftCollectionOfferLog {
+    pub offer_id: String,
+    pub buyer_id: String,
+    pub price_per_token: String,
+    pub remaining: u32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seller_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ft_contract_id: Option<String>,
+}
+
+/// An event log to capture a payout in a fungible token that failed and was kept claimable
+///
+/// Arguments
+/// * `ft_contract_id`: "usdc.near"
+/// * `receiver_id`: "seller.near"
+/// * `amount`: units of the fungible token, as a string
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftFtPayoutLog {
+    pub ft_contract_id: String,
+    pub receiver_id: String,
+    pub amount: String,
+}
+
+/// An event log to capture a royalty share, or part of it, moving to another account
+///
+/// Arguments
+/// * `token_id`: "1"
+/// * `old_account_id`: "studio.near"
+/// * `new_account_id`: "buyer.near"
+/// * `basis_points`: share moved, out of 10000
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftRoyaltyLog {
+    pub token_id: String,
+    pub old_account_id: String,
+    pub new_account_id: String,
+    pub basis_points: u32,
+}
+
+/// An event log to capture the user of a token being set, or cleared if `user_id` is absent
+///
+/// Arguments
+/// * `token_id`: "1"
+/// * `user_id`: "gamer.near"
+/// * `expires_at`: nanoseconds since the epoch, as a string
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftUserLog {
+    pub token_id: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+}
+
+/// An event log to capture a change to a rental listing
+///
+/// Arguments
+/// * `owner_id`: "owner.near"
+/// * `token_id`: "1"
+/// * `price_per_day`: yoctoNEAR, or units of `ft_contract_id`, as a string
+/// * `max_days`: longest rental allowed
+/// * `ft_contract_id`: fungible token the price is in, if not NEAR
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftRentalLog {
+    pub owner_id: String,
+    pub token_id: String,
+    pub price_per_day: String,
+    pub max_days: u32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ft_contract_id: Option<String>,
+}
+
+/// An event log to capture a token being staked or unstaked
+///
+/// Arguments
+/// * `owner_id`: "owner.near"
+/// * `token_id`: "1"
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftStakeLog {
+    pub owner_id: String,
+    pub token_id: String,
+}
+
+/// An event log to capture revenue being deposited for the holders, or claimed by one
+///
+/// Arguments
+/// * `account_id`: account that deposited or claimed
+/// * `amount`: yoctoNEAR
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftRevenueLog {
+    pub account_id: String,
+    pub amount: String,
+}
+
+/// An event log to capture a token or an account being frozen or unfrozen, or an account opting
+/// out of recovery or back in
+///
+/// Arguments
+/// * `token_id`: "1", if a token
+/// * `account_id`: "thief.near", if an account
+/// * `reason`: why it was frozen
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftFreezeLog {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// An event log to capture a step of the recovery of a frozen token
+///
+/// Arguments
+/// * `token_id`: "1"
+/// * `owner_id`: "thief.near"
+/// * `receiver_id`: "victim.near"
+/// * `reason`: why the token is returned
+/// * `available_at`: nanoseconds since the epoch from which it can be carried out, as a string
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftRecoveryLog {
+    pub token_id: String,
+    pub owner_id: String,
+    pub receiver_id: String,
+    pub reason: String,
+    pub available_at: String,
+}
+
+/// An event log to capture accounts being added to or removed from the blocklist
+///
+/// Arguments
+/// * `account_ids`: ["thief.near", "mixer.near"]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftBlocklistLog {
+    pub account_ids: Vec<String>,
+}
+
+/// An event log to capture the contract being paused or unpaused
+///
+/// Arguments
+/// * `account_id`: account that paused or unpaused the contract
+/// * `memo`: optional message
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftPauseLog {
+    pub account_id: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+/// An event log to capture a role being granted or revoked
+///
+/// Arguments
+/// * `role`: "minter"
+/// * `account_id`: account the role was granted to or revoked from
+/// * `sender_id`: account that made the change
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftRoleLog {
+    pub role: String,
+    pub account_id: String,
+    pub sender_id: String,
+}
+
+/// An event log to capture a proposal being created or executed
+///
+/// Arguments
+/// * `proposal_id`: "0"
+/// * `proposer_id`: holder that made the proposal
+/// * `action`: "text", "update_contract_metadata" or "set_royalty_policy"
+/// * `voting_ends_at`: nanoseconds since the epoch
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftProposalLog {
+    pub proposal_id: String,
+    pub proposer_id: String,
+    pub action: String,
+    pub voting_ends_at: String,
+}
+
+/// An event log to capture a vote on a proposal
+///
+/// Arguments
+/// * `proposal_id`: "0"
+/// * `voter_id`: holder that voted
+/// * `vote`: "yes", "no" or "abstain"
+/// * `weight`: tokens the voter held in the proposal's snapshot
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftVoteLog {
+    pub proposal_id: String,
+    pub voter_id: String,
+    pub vote: String,
+    pub weight: String,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -135,4 +665,551 @@ mod tests {
         };
         assert_eq!(expected, log.to_string());
     }
+
+    type Variant<T> = fn(Vec<T>) -> EventLogVariant;
+
+    /// The line every event is expected to be logged as, `data` being its single entry
+    fn golden(event: &str, data: &str) -> String {
+        format!(
+            r#"EVENT_JSON:{{"standard":"nep171","version":"1.0.0","event":"{}","data":[{}]}}"#,
+            event, data
+        )
+    }
+
+    #[test]
+    fn standard_and_version() {
+        let log = EventLog::new(EventLogVariant::NftMint(vec![NftMintLog {
+            owner_id: "user1.near".to_string(),
+            token_ids: vec!["meme".to_string()],
+            memo: None,
+        }]));
+        assert_eq!(log.standard, "nep171");
+        assert_eq!(log.version, "1.0.0");
+        assert_eq!(
+            golden("nft_mint", r#"{"owner_id":"user1.near","token_ids":["meme"]}"#),
+            log.to_string()
+        );
+    }
+
+    #[test]
+    fn golden_burn() {
+        let log = EventLog::new(EventLogVariant::NftBurn(vec![NftBurnLog {
+            owner_id: "user1.near".to_string(),
+            token_ids: vec!["1".to_string(), "2".to_string()],
+            authorized_id: Some("market.near".to_string()),
+            memo: Some("burnt".to_string()),
+        }]));
+        assert_eq!(
+            golden(
+                "nft_burn",
+                r#"{"owner_id":"user1.near","token_ids":["1","2"],"authorized_id":"market.near","memo":"burnt"}"#
+            ),
+            log.to_string()
+        );
+    }
+
+    #[test]
+    fn golden_metadata_update() {
+        let log = EventLog::new(EventLogVariant::NftMetadataUpdate(vec![NftMetadataUpdateLog {
+            token_ids: vec!["1".to_string()],
+            memo: None,
+        }]));
+        assert_eq!(
+            golden("nft_metadata_update", r#"{"token_ids":["1"]}"#),
+            log.to_string()
+        );
+    }
+
+    #[test]
+    fn golden_contract_metadata_update() {
+        let log = EventLog::new(EventLogVariant::ContractMetadataUpdate(vec![
+            NftContractMetadataUpdateLog {
+                memo: Some("new icon".to_string()),
+            },
+        ]));
+        assert_eq!(
+            golden("contract_metadata_update", r#"{"memo":"new icon"}"#),
+            log.to_string()
+        );
+    }
+
+    #[test]
+    fn golden_approve() {
+        let log = EventLog::new(EventLogVariant::NftApprove(vec![NftApproveLog {
+            owner_id: "user1.near".to_string(),
+            token_id: "1".to_string(),
+            account_id: "market.near".to_string(),
+            approval_id: 3,
+        }]));
+        assert_eq!(
+            golden(
+                "nft_approve",
+                r#"{"owner_id":"user1.near","token_id":"1","account_id":"market.near","approval_id":3}"#
+            ),
+            log.to_string()
+        );
+    }
+
+    #[test]
+    fn golden_revoke() {
+        let log = EventLog::new(EventLogVariant::NftRevoke(vec![NftRevokeLog {
+            owner_id: "user1.near".to_string(),
+            token_id: "1".to_string(),
+            account_id: "market.near".to_string(),
+            memo: Some("nft_on_approve failed".to_string()),
+        }]));
+        assert_eq!(
+            golden(
+                "nft_revoke",
+                r#"{"owner_id":"user1.near","token_id":"1","account_id":"market.near","memo":"nft_on_approve failed"}"#
+            ),
+            log.to_string()
+        );
+    }
+
+    #[test]
+    fn golden_revoke_all() {
+        let log = EventLog::new(EventLogVariant::NftRevokeAll(vec![NftRevokeAllLog {
+            owner_id: "user1.near".to_string(),
+            token_id: "1".to_string(),
+        }]));
+        assert_eq!(
+            golden("nft_revoke_all", r#"{"owner_id":"user1.near","token_id":"1"}"#),
+            log.to_string()
+        );
+    }
+
+    #[test]
+    fn golden_safe_transfer() {
+        let variants: [(Variant<NftPendingTransferLog>, &str); 4] = [
+            (EventLogVariant::NftTransferPending, "nft_transfer_pending"),
+            (EventLogVariant::NftTransferAccepted, "nft_transfer_accepted"),
+            (EventLogVariant::NftTransferCancelled, "nft_transfer_cancelled"),
+            (EventLogVariant::NftTransferLapsed, "nft_transfer_lapsed"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftPendingTransferLog {
+                owner_id: "user1.near".to_string(),
+                receiver_id: "user2.near".to_string(),
+                token_id: "1".to_string(),
+                expires_at: "1000".to_string(),
+                memo: None,
+            }]));
+            assert_eq!(
+                golden(
+                    event,
+                    r#"{"owner_id":"user1.near","receiver_id":"user2.near","token_id":"1","expires_at":"1000"}"#
+                ),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_listing() {
+        let variants: [(Variant<NftListingLog>, &str); 4] = [
+            (EventLogVariant::NftList, "nft_list"),
+            (EventLogVariant::NftDelist, "nft_delist"),
+            (EventLogVariant::NftUpdatePrice, "nft_update_price"),
+            (EventLogVariant::NftSale, "nft_sale"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftListingLog {
+                seller_id: "user1.near".to_string(),
+                token_id: "1".to_string(),
+                price: "5000".to_string(),
+                ft_contract_id: Some("usdc.near".to_string()),
+            }]));
+            assert_eq!(
+                golden(
+                    event,
+                    r#"{"seller_id":"user1.near","token_id":"1","price":"5000","ft_contract_id":"usdc.near"}"#
+                ),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_auction() {
+        let variants: [(Variant<NftAuctionLog>, &str); 4] = [
+            (EventLogVariant::NftAuctionStart, "nft_auction_start"),
+            (EventLogVariant::NftBid, "nft_bid"),
+            (EventLogVariant::NftAuctionCancel, "nft_auction_cancel"),
+            (EventLogVariant::NftAuctionSettle, "nft_auction_settle"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftAuctionLog {
+                seller_id: "user1.near".to_string(),
+                token_id: "1".to_string(),
+                bidder_id: Some("user2.near".to_string()),
+                amount: Some("5000".to_string()),
+                end_at: "1000".to_string(),
+                ft_contract_id: None,
+            }]));
+            assert_eq!(
+                golden(
+                    event,
+                    r#"{"seller_id":"user1.near","token_id":"1","bidder_id":"user2.near","amount":"5000","end_at":"1000"}"#
+                ),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_dutch_auction() {
+        let variants: [(Variant<NftDutchAuctionLog>, &str); 3] = [
+            (EventLogVariant::NftDutchAuctionStart, "nft_dutch_auction_start"),
+            (EventLogVariant::NftDutchAuctionCancel, "nft_dutch_auction_cancel"),
+            (EventLogVariant::NftDutchAuctionSale, "nft_dutch_auction_sale"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftDutchAuctionLog {
+                seller_id: "user1.near".to_string(),
+                token_id: "1".to_string(),
+                start_price: "9000".to_string(),
+                floor_price: "1000".to_string(),
+                end_at: "1000".to_string(),
+                buyer_id: Some("user2.near".to_string()),
+                price: Some("5000".to_string()),
+                ft_contract_id: None,
+            }]));
+            assert_eq!(
+                golden(
+                    event,
+                    r#"{"seller_id":"user1.near","token_id":"1","start_price":"9000","floor_price":"1000","end_at":"1000","buyer_id":"user2.near","price":"5000"}"#
+                ),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_offer() {
+        let variants: [(Variant<NftOfferLog>, &str); 3] = [
+            (EventLogVariant::NftOfferMake, "nft_offer_make"),
+            (EventLogVariant::NftOfferCancel, "nft_offer_cancel"),
+            (EventLogVariant::NftOfferAccept, "nft_offer_accept"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftOfferLog {
+                token_id: "1".to_string(),
+                buyer_id: "user2.near".to_string(),
+                owner_id: "user1.near".to_string(),
+                amount: "5000".to_string(),
+                expires_at: Some("1000".to_string()),
+                ft_contract_id: Some("usdc.near".to_string()),
+            }]));
+            assert_eq!(
+                golden(
+                    event,
+                    r#"{"token_id":"1","buyer_id":"user2.near","owner_id":"user1.near","amount":"5000","expires_at":"1000","ft_contract_id":"usdc.near"}"#
+                ),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_collection_offer() {
+        let variants: [(Variant<NftCollectionOfferLog>, &str); 3] = [
+            (EventLogVariant::NftCollectionOfferMake, "nft_collection_offer_make"),
+            (EventLogVariant::NftCollectionOfferCancel, "nft_collection_offer_cancel"),
+            (EventLogVariant::NftCollectionOfferFill, "nft_collection_offer_fill"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftCollectionOfferLog {
+                offer_id: "0".to_string(),
+                buyer_id: "user2.near".to_string(),
+                price_per_token: "5000".to_string(),
+                remaining: 2,
+                series_id: Some("genesis".to_string()),
+                expires_at: None,
+                token_id: Some("1".to_string()),
+                seller_id: Some("user1.near".to_string()),
+                ft_contract_id: None,
+            }]));
+            assert_eq!(
+                golden(
+                    event,
+                    r#"{"offer_id":"0","buyer_id":"user2.near","price_per_token":"5000","remaining":2,"series_id":"genesis","token_id":"1","seller_id":"user1.near"}"#
+                ),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_ft_payout_failed() {
+        let log = EventLog::new(EventLogVariant::NftFtPayoutFailed(vec![NftFtPayoutLog {
+            ft_contract_id: "usdc.near".to_string(),
+            receiver_id: "user1.near".to_string(),
+            amount: "5000".to_string(),
+        }]));
+        assert_eq!(
+            golden(
+                "nft_ft_payout_failed",
+                r#"{"ft_contract_id":"usdc.near","receiver_id":"user1.near","amount":"5000"}"#
+            ),
+            log.to_string()
+        );
+    }
+
+    #[test]
+    fn golden_royalty_update() {
+        let log = EventLog::new(EventLogVariant::NftRoyaltyUpdate(vec![NftRoyaltyLog {
+            token_id: "1".to_string(),
+            old_account_id: "studio.near".to_string(),
+            new_account_id: "user1.near".to_string(),
+            basis_points: 250,
+        }]));
+        assert_eq!(
+            golden(
+                "nft_royalty_update",
+                r#"{"token_id":"1","old_account_id":"studio.near","new_account_id":"user1.near","basis_points":250}"#
+            ),
+            log.to_string()
+        );
+    }
+
+    #[test]
+    fn golden_update_user() {
+        let log = EventLog::new(EventLogVariant::NftUpdateUser(vec![NftUserLog {
+            token_id: "1".to_string(),
+            user_id: Some("gamer.near".to_string()),
+            expires_at: Some("1700000000000000000".to_string()),
+        }]));
+        assert_eq!(
+            golden(
+                "nft_update_user",
+                r#"{"token_id":"1","user_id":"gamer.near","expires_at":"1700000000000000000"}"#
+            ),
+            log.to_string()
+        );
+
+        let log = EventLog::new(EventLogVariant::NftUpdateUser(vec![NftUserLog {
+            token_id: "1".to_string(),
+            user_id: None,
+            expires_at: None,
+        }]));
+        assert_eq!(golden("nft_update_user", r#"{"token_id":"1"}"#), log.to_string());
+    }
+
+    #[test]
+    fn golden_rental() {
+        let variants: [(Variant<NftRentalLog>, &str); 2] = [
+            (EventLogVariant::NftRentalList, "nft_rental_list"),
+            (EventLogVariant::NftRentalDelist, "nft_rental_delist"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftRentalLog {
+                owner_id: "owner.near".to_string(),
+                token_id: "1".to_string(),
+                price_per_day: "1000".to_string(),
+                max_days: 30,
+                ft_contract_id: Some("usdc.near".to_string()),
+            }]));
+            assert_eq!(
+                golden(
+                    event,
+                    r#"{"owner_id":"owner.near","token_id":"1","price_per_day":"1000","max_days":30,"ft_contract_id":"usdc.near"}"#
+                ),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_stake() {
+        let variants: [(Variant<NftStakeLog>, &str); 2] = [
+            (EventLogVariant::NftStake, "nft_stake"),
+            (EventLogVariant::NftUnstake, "nft_unstake"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftStakeLog {
+                owner_id: "owner.near".to_string(),
+                token_id: "1".to_string(),
+            }]));
+            assert_eq!(
+                golden(event, r#"{"owner_id":"owner.near","token_id":"1"}"#),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_freeze() {
+        let variants: [(Variant<NftFreezeLog>, &str); 2] = [
+            (EventLogVariant::NftFreeze, "nft_freeze"),
+            (EventLogVariant::NftUnfreeze, "nft_unfreeze"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftFreezeLog {
+                token_id: Some("1".to_string()),
+                account_id: None,
+                reason: Some("phishing".to_string()),
+            }]));
+            assert_eq!(
+                golden(event, r#"{"token_id":"1","reason":"phishing"}"#),
+                log.to_string()
+            );
+        }
+
+        let variants: [(Variant<NftFreezeLog>, &str); 2] = [
+            (EventLogVariant::NftRecoveryOptOut, "nft_recovery_opt_out"),
+            (EventLogVariant::NftRecoveryOptIn, "nft_recovery_opt_in"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftFreezeLog {
+                token_id: None,
+                account_id: Some("user1.near".to_string()),
+                reason: None,
+            }]));
+            assert_eq!(
+                golden(event, r#"{"account_id":"user1.near"}"#),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_recovery() {
+        let variants: [(Variant<NftRecoveryLog>, &str); 3] = [
+            (EventLogVariant::NftRecoverySchedule, "nft_recovery_schedule"),
+            (EventLogVariant::NftRecoveryCancel, "nft_recovery_cancel"),
+            (EventLogVariant::NftRecover, "nft_recover"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftRecoveryLog {
+                token_id: "1".to_string(),
+                owner_id: "thief.near".to_string(),
+                receiver_id: "user1.near".to_string(),
+                reason: "phishing".to_string(),
+                available_at: "1700000000000000000".to_string(),
+            }]));
+            assert_eq!(
+                golden(
+                    event,
+                    r#"{"token_id":"1","owner_id":"thief.near","receiver_id":"user1.near","reason":"phishing","available_at":"1700000000000000000"}"#
+                ),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_blocklist() {
+        let variants: [(Variant<NftBlocklistLog>, &str); 2] = [
+            (EventLogVariant::NftBlock, "nft_block"),
+            (EventLogVariant::NftUnblock, "nft_unblock"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftBlocklistLog {
+                account_ids: vec!["thief.near".to_string(), "user1.near".to_string()],
+            }]));
+            assert_eq!(
+                golden(event, r#"{"account_ids":["thief.near","user1.near"]}"#),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_pause() {
+        let variants: [(Variant<NftPauseLog>, &str); 2] = [
+            (EventLogVariant::ContractPause, "contract_pause"),
+            (EventLogVariant::ContractUnpause, "contract_unpause"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftPauseLog {
+                account_id: "owner.near".to_string(),
+                memo: None,
+            }]));
+            assert_eq!(
+                golden(event, r#"{"account_id":"owner.near"}"#),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_role() {
+        let variants: [(Variant<NftRoleLog>, &str); 2] = [
+            (EventLogVariant::RoleGrant, "role_grant"),
+            (EventLogVariant::RoleRevoke, "role_revoke"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftRoleLog {
+                role: "minter".to_string(),
+                account_id: "user1.near".to_string(),
+                sender_id: "owner.near".to_string(),
+            }]));
+            assert_eq!(
+                golden(
+                    event,
+                    r#"{"role":"minter","account_id":"user1.near","sender_id":"owner.near"}"#
+                ),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_proposal() {
+        let variants: [(Variant<NftProposalLog>, &str); 2] = [
+            (EventLogVariant::NftProposalCreate, "nft_proposal_create"),
+            (EventLogVariant::NftProposalExecute, "nft_proposal_execute"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftProposalLog {
+                proposal_id: "0".to_string(),
+                proposer_id: "user1.near".to_string(),
+                action: "text".to_string(),
+                voting_ends_at: "1000".to_string(),
+            }]));
+            assert_eq!(
+                golden(
+                    event,
+                    r#"{"proposal_id":"0","proposer_id":"user1.near","action":"text","voting_ends_at":"1000"}"#
+                ),
+                log.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_vote() {
+        let log = EventLog::new(EventLogVariant::NftVote(vec![NftVoteLog {
+            proposal_id: "0".to_string(),
+            voter_id: "user1.near".to_string(),
+            vote: "yes".to_string(),
+            weight: "3".to_string(),
+        }]));
+        assert_eq!(
+            golden(
+                "nft_vote",
+                r#"{"proposal_id":"0","voter_id":"user1.near","vote":"yes","weight":"3"}"#
+            ),
+            log.to_string()
+        );
+    }
+
+    #[test]
+    fn golden_revenue() {
+        let variants: [(Variant<NftRevenueLog>, &str); 2] = [
+            (EventLogVariant::NftRevenueDeposit, "nft_revenue_deposit"),
+            (EventLogVariant::NftRevenueClaim, "nft_revenue_claim"),
+        ];
+        for (variant, event) in variants {
+            let log = EventLog::new(variant(vec![NftRevenueLog {
+                account_id: "user1.near".to_string(),
+                amount: "100".to_string(),
+            }]));
+            assert_eq!(
+                golden(event, r#"{"account_id":"user1.near","amount":"100"}"#),
+                log.to_string()
+            );
+        }
+    }
 }
@@ -0,0 +1,466 @@
+use crate::*;
+use near_sdk::json_types::U64;
+
+/// Time between scheduling the recovery of a frozen token and carrying it out, in nanoseconds:
+/// 3 days for the holder to contest it
+const RECOVERY_TIMELOCK: u64 = 3 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Why and when a token or an account was frozen by the contract owner
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Freeze {
+    pub reason: String,
+    // nanoseconds since the epoch
+    pub frozen_at: U64,
+    // the owner had opted out of recovery when it was frozen, later changes don't count
+    pub recovery_disabled: bool,
+}
+
+/// A frozen token to be returned to `receiver_id` once `available_at` has passed
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Recovery {
+    pub receiver_id: AccountId,
+    pub reason: String,
+    // nanoseconds since the epoch
+    pub available_at: U64,
+}
+
+/// The freeze state of a token, frozen itself or through its owner
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenFreeze {
+    pub token: Option<Freeze>,
+    pub owner: Option<Freeze>,
+    pub recovery: Option<Recovery>,
+    // the owner has opted out of recovery
+    pub recovery_disabled: bool,
+}
+
+fn emit_freeze_event(
+    variant: fn(Vec<NftFreezeLog>) -> EventLogVariant,
+    token_id: Option<&TokenId>,
+    account_id: Option<&AccountId>,
+    reason: Option<&str>,
+) {
+    variant(vec![NftFreezeLog {
+        token_id: token_id.cloned(),
+        account_id: account_id.map(|id| id.to_string()),
+        reason: reason.map(str::to_string),
+    }])
+    .emit();
+}
+
+fn emit_recovery_event(
+    variant: fn(Vec<NftRecoveryLog>) -> EventLogVariant,
+    token_id: &TokenId,
+    owner_id: &AccountId,
+    recovery: &Recovery,
+) {
+    variant(vec![NftRecoveryLog {
+        token_id: token_id.clone(),
+        owner_id: owner_id.to_string(),
+        receiver_id: recovery.receiver_id.to_string(),
+        reason: recovery.reason.clone(),
+        available_at: recovery.available_at.0.to_string(),
+    }])
+    .emit();
+}
+
+impl Contract {
+    fn assert_owner(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only owner can freeze and recover tokens",
+        );
+    }
+
+    /// Panics if the token or its owner is frozen
+    pub(crate) fn internal_assert_not_frozen(&self, token_id: &TokenId) {
+        require!(
+            self.token_freezes.get(token_id).is_none(),
+            "cypher: Token is frozen"
+        );
+        if let Some(owner_id) = self.owner_by_id.get(token_id) {
+            require!(
+                self.account_freezes.get(&owner_id).is_none(),
+                "cypher: Token owner is frozen"
+            );
+        }
+    }
+
+    /// Panics unless the token is frozen and its owner hadn't opted out of recovery by then
+    fn internal_assert_recoverable(&self, token_id: &TokenId, owner_id: &AccountId) {
+        let freeze = self
+            .internal_token_freeze(token_id, owner_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Only frozen tokens can be recovered"));
+        require!(
+            !freeze.recovery_disabled,
+            "cypher: Token owner has opted out of recovery"
+        );
+    }
+
+    /// Returns the freeze state of a token, if it is frozen
+    pub(crate) fn internal_token_freeze(
+        &self,
+        token_id: &TokenId,
+        owner_id: &AccountId,
+    ) -> Option<TokenFreeze> {
+        let token = self.token_freezes.get(token_id);
+        let owner = self.account_freezes.get(owner_id);
+        // a frozen token can't change hands, so the opt-out taken when it was frozen is still
+        // its owner's
+        let recovery_disabled = token.as_ref().or(owner.as_ref())?.recovery_disabled;
+        Some(TokenFreeze {
+            token,
+            owner,
+            recovery: self.recoveries.get(token_id),
+            recovery_disabled,
+        })
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Freeze a token: it can't be transferred, approved or paid out for until unfrozen.
+    /// Whether its owner opted out of recovery is fixed at this point.
+    pub fn nft_freeze_token(&mut self, token_id: TokenId, reason: String) {
+        self.assert_owner();
+        let owner_id = expect_token_found(self.owner_by_id.get(&token_id));
+        require!(
+            self.token_freezes.get(&token_id).is_none(),
+            "cypher: Token is frozen"
+        );
+
+        self.token_freezes.insert(
+            &token_id,
+            &Freeze {
+                reason: reason.clone(),
+                frozen_at: env::block_timestamp().into(),
+                recovery_disabled: self.recovery_opt_outs.contains(&owner_id),
+            },
+        );
+        emit_freeze_event(
+            EventLogVariant::NftFreeze,
+            Some(&token_id),
+            None,
+            Some(&reason),
+        );
+    }
+
+    /// Unfreeze a token, cancelling its recovery if one is scheduled
+    pub fn nft_unfreeze_token(&mut self, token_id: TokenId) {
+        self.assert_owner();
+        require!(
+            self.token_freezes.remove(&token_id).is_some(),
+            "cypher: Token is not frozen"
+        );
+        if let Some(recovery) = self.recoveries.remove(&token_id) {
+            let owner_id = expect_token_found(self.owner_by_id.get(&token_id));
+            emit_recovery_event(
+                EventLogVariant::NftRecoveryCancel,
+                &token_id,
+                &owner_id,
+                &recovery,
+            );
+        }
+        emit_freeze_event(EventLogVariant::NftUnfreeze, Some(&token_id), None, None);
+    }
+
+    /// Freeze an account: none of its tokens can be transferred, approved or paid out for
+    /// until it is unfrozen
+    pub fn nft_freeze_account(&mut self, account_id: AccountId, reason: String) {
+        self.assert_owner();
+        require!(
+            self.account_freezes.get(&account_id).is_none(),
+            "cypher: Account is frozen"
+        );
+
+        self.account_freezes.insert(
+            &account_id,
+            &Freeze {
+                reason: reason.clone(),
+                frozen_at: env::block_timestamp().into(),
+                recovery_disabled: self.recovery_opt_outs.contains(&account_id),
+            },
+        );
+        emit_freeze_event(
+            EventLogVariant::NftFreeze,
+            None,
+            Some(&account_id),
+            Some(&reason),
+        );
+    }
+
+    /// Unfreeze an account, recoveries scheduled for its tokens stay as long as they are frozen
+    pub fn nft_unfreeze_account(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        require!(
+            self.account_freezes.remove(&account_id).is_some(),
+            "cypher: Account is not frozen"
+        );
+        emit_freeze_event(EventLogVariant::NftUnfreeze, None, Some(&account_id), None);
+    }
+
+    /// Schedule a frozen token to be returned to `receiver_id`, for `reason`. It can be carried
+    /// out with `nft_recover_token` after the timelock, unless its owner opted out of recovery.
+    pub fn nft_schedule_recovery(
+        &mut self,
+        token_id: TokenId,
+        receiver_id: AccountId,
+        reason: String,
+    ) {
+        self.assert_owner();
+        let owner_id = expect_token_found(self.owner_by_id.get(&token_id));
+        self.internal_assert_recoverable(&token_id, &owner_id);
+        self.internal_assert_not_blocked(&receiver_id);
+        require!(
+            owner_id != receiver_id,
+            "cypher: Current and next owner must differ"
+        );
+
+        let recovery = Recovery {
+            receiver_id,
+            reason,
+            available_at: (env::block_timestamp() + RECOVERY_TIMELOCK).into(),
+        };
+        self.recoveries.insert(&token_id, &recovery);
+        emit_recovery_event(
+            EventLogVariant::NftRecoverySchedule,
+            &token_id,
+            &owner_id,
+            &recovery,
+        );
+    }
+
+    /// Return a frozen token to the account its recovery was scheduled for, once the timelock has
+    /// passed. The token is unfrozen, its approvals, listings, user and stake are cleared, and any
+    /// auction of it is cancelled with the highest bid refunded.
+    pub fn nft_recover_token(&mut self, token_id: TokenId) {
+        self.assert_owner();
+        let owner_id = expect_token_found(self.owner_by_id.get(&token_id));
+        let recovery = self
+            .recoveries
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: No recovery scheduled for this token"));
+        require!(
+            env::block_timestamp() >= recovery.available_at.0,
+            "cypher: Recovery is still timelocked"
+        );
+        self.internal_assert_recoverable(&token_id, &owner_id);
+        // the receiver may have been blocked while the recovery was timelocked
+        self.internal_assert_not_blocked(&recovery.receiver_id);
+        require!(
+            !self.internal_has_pending_transfer(&token_id),
+            "cypher: Token has a pending transfer"
+        );
+        self.internal_cancel_auction(&token_id);
+        self.internal_cancel_dutch_auction(&token_id);
+        self.recoveries.remove(&token_id);
+        self.token_freezes.remove(&token_id);
+        self.internal_remove_stake(&token_id);
+
+        // the recovered owner pays for what it takes without a balance check, it didn't ask for it
+        let (storage_released, storage_used) = self.internal_transfer_unguarded(
+            &token_id,
+            &owner_id,
+            &recovery.receiver_id,
+            ProvenanceKind::Recovery,
+        );
+        if let Some(approved_account_ids) = self
+            .approvals_by_id
+            .as_mut()
+            .and_then(|by_id| by_id.remove_raw(&token_id_key(&token_id)))
+        {
+            self.internal_storage_credit(
+                &owner_id,
+                bytes_for_approved_account_ids_raw(&approved_account_ids),
+            );
+        }
+        self.internal_storage_credit(&owner_id, storage_released);
+        self.internal_storage_debit_unchecked(&recovery.receiver_id, storage_used);
+
+        emit_nft_transfers(
+            &[(
+                owner_id.clone(),
+                recovery.receiver_id.clone(),
+                Some(self.owner_id.clone()),
+                token_id.clone(),
+            )],
+            Some("nft_recover_token"),
+        );
+        emit_recovery_event(EventLogVariant::NftRecover, &token_id, &owner_id, &recovery);
+    }
+
+    /// Opt out of the recovery of your tokens, or back in. Frozen accounts can't change it, and
+    /// tokens that are already frozen keep the choice made before they were.
+    #[payable]
+    pub fn nft_set_recovery_opt_out(&mut self, opt_out: bool) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        require!(
+            self.account_freezes.get(&account_id).is_none(),
+            "cypher: Account is frozen"
+        );
+
+        let initial_storage_usage = env::storage_usage();
+        let changed = if opt_out {
+            self.recovery_opt_outs.insert(&account_id)
+        } else {
+            self.recovery_opt_outs.remove(&account_id)
+        };
+        if !changed {
+            return;
+        }
+        if opt_out {
+            self.internal_storage_debit(
+                &account_id,
+                env::storage_usage().saturating_sub(initial_storage_usage),
+            );
+        } else {
+            self.internal_storage_credit(
+                &account_id,
+                initial_storage_usage.saturating_sub(env::storage_usage()),
+            );
+        }
+        let variant = if opt_out {
+            EventLogVariant::NftRecoveryOptOut
+        } else {
+            EventLogVariant::NftRecoveryOptIn
+        };
+        emit_freeze_event(variant, None, Some(&account_id), None);
+    }
+
+    /// get the freeze state of a token, if it or its owner is frozen
+    pub fn nft_token_freeze(&self, token_id: TokenId) -> Option<TokenFreeze> {
+        let owner_id = self.owner_by_id.get(&token_id)?;
+        self.internal_token_freeze(&token_id, &owner_id)
+    }
+
+    /// get why and when an account was frozen, if it is
+    pub fn nft_account_freeze(&self, account_id: AccountId) -> Option<Freeze> {
+        self.account_freezes.get(&account_id)
+    }
+
+    /// get whether an account has opted out of the recovery of its tokens
+    pub fn nft_recovery_opted_out(&self, account_id: AccountId) -> bool {
+        self.recovery_opt_outs.contains(&account_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn freeze_and_schedule(contract: &mut Contract) {
+        call(&owner(), 0, 0);
+        contract.nft_freeze_token("1".to_string(), "stolen".to_string());
+        contract.nft_schedule_recovery("1".to_string(), account("bob.near"), "stolen".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Recovery is still timelocked")]
+    fn recovery_waits_for_the_timelock() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        freeze_and_schedule(&mut contract);
+
+        call(&owner(), 0, RECOVERY_TIMELOCK - 1);
+        contract.nft_recover_token("1".to_string());
+    }
+
+    #[test]
+    fn recovery_after_the_timelock_returns_the_token() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        freeze_and_schedule(&mut contract);
+
+        call(&owner(), 0, RECOVERY_TIMELOCK);
+        contract.nft_recover_token("1".to_string());
+        assert_eq!(owner_of(&contract, "1"), account("bob.near"));
+        assert!(contract.token_freezes.get(&"1".to_string()).is_none());
+        assert!(contract.recoveries.get(&"1".to_string()).is_none());
+    }
+
+    #[test]
+    fn recovery_cancels_an_auction_with_bids() {
+        let mut contract = setup(&["alice.near", "bob.near", "carol.near"]);
+        mint(&mut contract, "1", "alice.near");
+        call(&account("alice.near"), 1, 0);
+        contract.nft_start_auction(
+            "1".to_string(),
+            NEAR.into(),
+            NEAR.into(),
+            U64(10 * RECOVERY_TIMELOCK),
+            None,
+            None,
+        );
+        call(&account("carol.near"), NEAR, 0);
+        contract.nft_bid("1".to_string());
+        freeze_and_schedule(&mut contract);
+
+        call(&owner(), 0, RECOVERY_TIMELOCK);
+        contract.nft_recover_token("1".to_string());
+        assert_eq!(owner_of(&contract, "1"), account("bob.near"));
+        assert!(!contract.internal_is_in_auction(&"1".to_string()));
+    }
+
+    #[test]
+    fn opting_out_after_the_freeze_does_not_stop_recovery() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        call(&owner(), 0, 0);
+        contract.nft_freeze_token("1".to_string(), "stolen".to_string());
+
+        call(&account("alice.near"), 1, 0);
+        contract.nft_set_recovery_opt_out(true);
+        assert!(
+            !contract
+                .nft_token_freeze("1".to_string())
+                .unwrap()
+                .recovery_disabled
+        );
+
+        call(&owner(), 0, 0);
+        contract.nft_schedule_recovery("1".to_string(), account("bob.near"), "stolen".to_string());
+        call(&owner(), 0, RECOVERY_TIMELOCK);
+        contract.nft_recover_token("1".to_string());
+        assert_eq!(owner_of(&contract, "1"), account("bob.near"));
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Token owner has opted out of recovery")]
+    fn opting_out_before_the_freeze_stops_recovery() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        call(&account("alice.near"), 1, 0);
+        contract.nft_set_recovery_opt_out(true);
+
+        freeze_and_schedule(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account bob.near is blocked")]
+    fn recovery_cannot_be_scheduled_for_a_blocked_account() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        call(&owner(), 0, 0);
+        contract.nft_block_accounts(vec![account("bob.near")]);
+
+        freeze_and_schedule(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account bob.near is blocked")]
+    fn recovery_is_not_carried_out_for_an_account_blocked_since() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        freeze_and_schedule(&mut contract);
+        contract.nft_block_accounts(vec![account("bob.near")]);
+
+        call(&owner(), 0, RECOVERY_TIMELOCK);
+        contract.nft_recover_token("1".to_string());
+    }
+}
@@ -0,0 +1,402 @@
+use crate::*;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::json_types::U64;
+
+const GAS_FOR_FT_TRANSFER: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_RESOLVE_FT_PAYOUT: Gas = Gas(5_000_000_000_000);
+
+/// The part of NEP-141 used to pay out sales made in a fungible token
+#[ext_contract(ext_ft)]
+pub trait FungibleTokenCore {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+}
+
+/// Called after each `ft_transfer` of a payout. This trait is implemented on the NFT contract.
+#[ext_contract(ext_ft_payout_resolver)]
+pub trait FungibleTokenPayoutResolver {
+    fn ft_resolve_payout(
+        &mut self,
+        ft_contract_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        payer_id: AccountId,
+    ) -> bool;
+}
+
+/// What the tokens sent with `ft_transfer_call` pay for, given as JSON in `msg`,
+/// e.g. `{"action": "buy", "token_id": "1"}`. Prices and escrows are in the sent token, which
+/// the listing, auction or offer must be in.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum FtPurchase {
+    // buy a token listed with `nft_list`
+    Buy {
+        token_id: TokenId,
+    },
+    // buy a token from a Dutch auction, minting it if the auction is a drop
+    BuyDutch {
+        token_id: TokenId,
+    },
+    // mint a token from a drop started with `nft_start_dutch_drop`
+    Mint {
+        token_id: TokenId,
+    },
+    // bid the whole amount on an auction started with `nft_start_auction`
+    Bid {
+        token_id: TokenId,
+    },
+    // escrow the whole amount as an offer on a token, as `nft_make_offer`
+    Offer {
+        token_id: TokenId,
+        expiry: Option<U64>,
+    },
+    // escrow an offer on several tokens, as `nft_make_collection_offer`
+    CollectionOffer {
+        price_per_token: U128,
+        quantity: u32,
+        expiry: Option<U64>,
+        series_id: Option<SeriesId>,
+    },
+    // rent a token listed with `nft_list_rental` for `days`
+    Rent {
+        token_id: TokenId,
+        days: u32,
+    },
+}
+
+/// A payout in a fungible token that failed to reach its receiver
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct FtUnpaid {
+    pub amount: Balance,
+    // account charged for the storage of this entry, credited back when it is claimed
+    pub payer_id: AccountId,
+}
+
+impl Contract {
+    /// Panics unless `ft_contract_id` is unset (NEAR) or an accepted fungible token
+    pub(crate) fn internal_assert_accepted_ft(&self, ft_contract_id: Option<&AccountId>) {
+        if let Some(ft_contract_id) = ft_contract_id {
+            require!(
+                self.accepted_ft_contracts.contains(ft_contract_id),
+                "cypher: Fungible token is not accepted"
+            );
+        }
+    }
+
+    /// Sends `amount` of `ft_contract_id` to `receiver_id`, keeping it claimable if the transfer
+    /// fails. `payer_id` pays for the storage of the claim.
+    fn internal_ft_transfer(
+        &self,
+        ft_contract_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        payer_id: &AccountId,
+    ) -> Promise {
+        ext_ft::ext(ft_contract_id.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(GAS_FOR_FT_TRANSFER)
+            .ft_transfer(receiver_id.clone(), amount.into(), None)
+            .then(
+                ext_ft_payout_resolver::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_FT_PAYOUT)
+                    .ft_resolve_payout(
+                        ft_contract_id.clone(),
+                        receiver_id.clone(),
+                        amount.into(),
+                        payer_id.clone(),
+                    ),
+            )
+    }
+
+    /// Sends `amount` to `receiver_id` in `ft_contract_id`, or NEAR if unset. `payer_id` pays
+    /// for the storage of the claim if a fungible token transfer fails.
+    pub(crate) fn internal_pay(
+        &self,
+        ft_contract_id: Option<&AccountId>,
+        receiver_id: &AccountId,
+        amount: Balance,
+        payer_id: &AccountId,
+    ) {
+        if amount == 0 {
+            return;
+        }
+        match ft_contract_id {
+            Some(ft_contract_id) => {
+                self.internal_ft_transfer(ft_contract_id, receiver_id, amount, payer_id);
+            }
+            None => {
+                Promise::new(receiver_id.clone()).transfer(amount);
+            }
+        }
+    }
+
+    /// pays every account in `payout` its share in `ft_contract_id`, or NEAR if unset
+    pub(crate) fn internal_distribute_payout(
+        &self,
+        ft_contract_id: Option<&AccountId>,
+        payout: Payout,
+        payer_id: &AccountId,
+    ) {
+        for (account_id, amount) in payout.payout {
+            self.internal_pay(ft_contract_id, &account_id, amount.0, payer_id);
+        }
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Contract {
+    /// Pay for the purchase in `msg` with an accepted fungible token. The price is paid out in
+    /// the same token, the unused amount is returned to the sender. The sender must be
+    /// registered for storage, it pays for what the purchase stores.
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let ft_contract_id = env::predecessor_account_id();
+        self.internal_assert_accepted_ft(Some(&ft_contract_id));
+        self.internal_storage_account(&sender_id);
+        let purchase: FtPurchase = near_sdk::serde_json::from_str(&msg)
+            .unwrap_or_else(|_| env::panic_str("cypher: Invalid purchase msg"));
+        let ft = Some(&ft_contract_id);
+
+        let (used, payout) = match purchase {
+            FtPurchase::Buy { token_id } => {
+                let (listing, payout) =
+                    self.internal_buy_listing(&sender_id, &token_id, ft, amount.0);
+                (listing.price.0, Some(payout))
+            }
+            FtPurchase::BuyDutch { token_id } => {
+                let (price, payout) = self.internal_buy_dutch(&sender_id, &token_id, ft, amount.0);
+                (price, Some(payout))
+            }
+            FtPurchase::Mint { token_id } => {
+                require!(
+                    self.internal_is_drop(&token_id),
+                    "cypher: Token is not in a drop"
+                );
+                let (price, payout) = self.internal_buy_dutch(&sender_id, &token_id, ft, amount.0);
+                (price, Some(payout))
+            }
+            FtPurchase::Bid { token_id } => {
+                self.internal_bid(&sender_id, &token_id, ft, amount.0);
+                (amount.0, None)
+            }
+            FtPurchase::Offer { token_id, expiry } => {
+                self.internal_make_offer(&sender_id, token_id, ft, amount.0, expiry);
+                (amount.0, None)
+            }
+            FtPurchase::CollectionOffer {
+                price_per_token,
+                quantity,
+                expiry,
+                series_id,
+            } => {
+                let (_, total) = self.internal_make_collection_offer(
+                    &sender_id,
+                    price_per_token,
+                    quantity,
+                    expiry,
+                    series_id,
+                    ft,
+                    amount.0,
+                );
+                (total, None)
+            }
+            FtPurchase::Rent { token_id, days } => {
+                let (price, payout) = self.internal_rent(&sender_id, &token_id, days, ft, amount.0);
+                (price, Some(payout))
+            }
+        };
+
+        if let Some(payout) = payout {
+            self.internal_distribute_payout(ft, payout, &sender_id);
+        }
+        PromiseOrValue::Value((amount.0 - used).into())
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenPayoutResolver for Contract {
+    /// Returns true if the payout was transferred. Failed payouts can be claimed with `ft_claim_unpaid`.
+    /// `payer_id` is charged for the storage of a new claim, without a balance check since the
+    /// callback must not fail.
+    #[private]
+    fn ft_resolve_payout(
+        &mut self,
+        ft_contract_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        payer_id: AccountId,
+    ) -> bool {
+        match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => return true,
+            PromiseResult::Failed => {}
+        };
+
+        let key = (ft_contract_id.clone(), receiver_id.clone());
+        let initial_storage_usage = env::storage_usage();
+        let unpaid = match self.ft_unpaid.get(&key) {
+            Some(mut unpaid) => {
+                unpaid.amount += amount.0;
+                unpaid
+            }
+            None => FtUnpaid {
+                amount: amount.0,
+                payer_id,
+            },
+        };
+        self.ft_unpaid.insert(&key, &unpaid);
+        self.internal_storage_debit_unchecked(
+            &unpaid.payer_id,
+            env::storage_usage().saturating_sub(initial_storage_usage),
+        );
+
+        EventLogVariant::NftFtPayoutFailed(vec![NftFtPayoutLog {
+            ft_contract_id: ft_contract_id.to_string(),
+            receiver_id: receiver_id.to_string(),
+            amount: amount.0.to_string(),
+        }])
+        .emit();
+        false
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Allow sales, auctions, offers and rentals priced in `ft_contract_id`
+    pub fn add_accepted_ft_contract(&mut self, ft_contract_id: AccountId) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only owner can add accepted fungible tokens",
+        );
+
+        self.accepted_ft_contracts.insert(&ft_contract_id);
+    }
+
+    /// Stop accepting `ft_contract_id`. Sales already priced in it can't complete until it is added back.
+    pub fn remove_accepted_ft_contract(&mut self, ft_contract_id: AccountId) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only owner can remove accepted fungible tokens",
+        );
+
+        self.accepted_ft_contracts.remove(&ft_contract_id);
+    }
+
+    /// Retry the payouts in `ft_contract_id` that failed to reach you
+    pub fn ft_claim_unpaid(&mut self, ft_contract_id: AccountId) -> Promise {
+        let receiver_id = env::predecessor_account_id();
+        let initial_storage_usage = env::storage_usage();
+        let unpaid = self
+            .ft_unpaid
+            .remove(&(ft_contract_id.clone(), receiver_id.clone()))
+            .unwrap_or_else(|| env::panic_str("cypher: Nothing to claim"));
+        self.internal_storage_credit(
+            &unpaid.payer_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
+        // if it fails again, the claim is kept at the receiver's expense
+        self.internal_ft_transfer(&ft_contract_id, &receiver_id, unpaid.amount, &receiver_id)
+    }
+
+    /// get the fungible tokens sales can be paid in
+    pub fn accepted_ft_contracts(&self) -> Vec<AccountId> {
+        self.accepted_ft_contracts.to_vec()
+    }
+
+    /// get the payouts in `ft_contract_id` that failed to reach `account_id`
+    pub fn ft_unpaid_of(&self, ft_contract_id: AccountId, account_id: AccountId) -> U128 {
+        self.ft_unpaid
+            .get(&(ft_contract_id, account_id))
+            .map(|unpaid| unpaid.amount)
+            .unwrap_or(0)
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn setup_usdc() -> Contract {
+        let mut contract = setup(&["alice.near", "bob.near", "carol.near"]);
+        call(&owner(), 0, 0);
+        contract.add_accepted_ft_contract(account("usdc.near"));
+        mint(&mut contract, "1", "alice.near");
+        contract
+    }
+
+    fn ft_transfer_call(
+        contract: &mut Contract,
+        sender_id: &str,
+        amount: Balance,
+        msg: &str,
+    ) -> U128 {
+        call(&account("usdc.near"), 0, 1);
+        match contract.ft_on_transfer(account(sender_id), amount.into(), msg.to_string()) {
+            PromiseOrValue::Value(unused) => unused,
+            PromiseOrValue::Promise(_) => panic!("expected a value"),
+        }
+    }
+
+    fn start_auction(contract: &mut Contract, ft_contract_id: Option<AccountId>) {
+        call(&account("alice.near"), 1, 0);
+        contract.nft_start_auction(
+            "1".to_string(),
+            100.into(),
+            10.into(),
+            U64(1_000),
+            None,
+            ft_contract_id,
+        );
+    }
+
+    #[test]
+    fn bids_in_the_auction_currency() {
+        let mut contract = setup_usdc();
+        start_auction(&mut contract, Some(account("usdc.near")));
+
+        let msg = r#"{"action":"bid","token_id":"1"}"#;
+        assert_eq!(ft_transfer_call(&mut contract, "bob.near", 100, msg).0, 0);
+        assert_eq!(ft_transfer_call(&mut contract, "carol.near", 110, msg).0, 0);
+        let bid = contract
+            .nft_auction("1".to_string())
+            .unwrap()
+            .highest_bid
+            .unwrap();
+        assert_eq!(bid.bidder_id, account("carol.near"));
+        assert_eq!(bid.amount.0, 110);
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: The auction is in another currency")]
+    fn fungible_token_bid_on_a_near_auction_fails() {
+        let mut contract = setup_usdc();
+        start_auction(&mut contract, None);
+        ft_transfer_call(
+            &mut contract,
+            "bob.near",
+            100,
+            r#"{"action":"bid","token_id":"1"}"#,
+        );
+    }
+
+    #[test]
+    fn offer_is_escrowed_in_the_fungible_token() {
+        let mut contract = setup_usdc();
+        let msg = r#"{"action":"offer","token_id":"1","expiry":null}"#;
+        assert_eq!(ft_transfer_call(&mut contract, "bob.near", 50, msg).0, 0);
+        let offer = contract
+            .nft_offer("1".to_string(), account("bob.near"))
+            .unwrap();
+        assert_eq!(offer.amount.0, 50);
+        assert_eq!(offer.ft_contract_id, Some(account("usdc.near")));
+    }
+}
@@ -0,0 +1,422 @@
+use crate::*;
+use near_sdk::json_types::U64;
+
+/// Share of the snapshot's tokens that must vote for a proposal to pass, until the owner changes it
+const DEFAULT_QUORUM_BASIS_POINTS: u32 = 2_000;
+/// Voting period of new proposals until the owner changes it: 7 days in nanoseconds
+const DEFAULT_VOTING_PERIOD: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Quorum and voting period applied to new proposals
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GovernancePolicy {
+    // share of the snapshot's tokens, in basis points
+    pub quorum_basis_points: u32,
+    // nanoseconds
+    pub voting_period: U64,
+}
+
+impl Default for GovernancePolicy {
+    fn default() -> Self {
+        Self {
+            quorum_basis_points: DEFAULT_QUORUM_BASIS_POINTS,
+            voting_period: DEFAULT_VOTING_PERIOD.into(),
+        }
+    }
+}
+
+/// What an approved proposal does when executed
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProposalAction {
+    // nothing, the vote itself is the outcome
+    Text,
+    UpdateContractMetadata { metadata: NFTContractMetadata },
+    SetRoyaltyPolicy { policy: RoyaltyPolicy },
+}
+
+impl ProposalAction {
+    /// name of the action as in its JSON `type`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProposalAction::Text => "text",
+            ProposalAction::UpdateContractMetadata { .. } => "update_contract_metadata",
+            ProposalAction::SetRoyaltyPolicy { .. } => "set_royalty_policy",
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Vote {
+    Yes,
+    No,
+    Abstain,
+}
+
+impl Vote {
+    /// name of the vote as in its JSON
+    pub fn name(&self) -> &'static str {
+        match self {
+            Vote::Yes => "yes",
+            Vote::No => "no",
+            Vote::Abstain => "abstain",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalStatus {
+    Active,
+    Approved,
+    Rejected,
+    Executed,
+}
+
+/// A proposal voted on by the holders in a snapshot, each with the number of tokens they held
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Proposal {
+    pub proposal_id: U64,
+    pub proposer_id: AccountId,
+    pub description: String,
+    pub action: ProposalAction,
+    // the latest finished snapshot when the proposal was made, its token count is the total weight
+    pub snapshot_id: U64,
+    // weight of the votes, of any kind, needed for the result to count
+    pub quorum: U64,
+    // nanoseconds since the epoch
+    pub voting_ends_at: U64,
+    pub yes: U64,
+    pub no: U64,
+    pub abstain: U64,
+    pub executed: bool,
+}
+
+impl Proposal {
+    pub fn status(&self) -> ProposalStatus {
+        if self.executed {
+            ProposalStatus::Executed
+        } else if env::block_timestamp() < self.voting_ends_at.0 {
+            ProposalStatus::Active
+        } else if self.yes.0 + self.no.0 + self.abstain.0 >= self.quorum.0 && self.yes.0 > self.no.0
+        {
+            ProposalStatus::Approved
+        } else {
+            ProposalStatus::Rejected
+        }
+    }
+}
+
+/// A proposal with its status at the time of the view
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalJson {
+    #[serde(flatten)]
+    pub proposal: Proposal,
+    pub status: ProposalStatus,
+}
+
+impl From<Proposal> for ProposalJson {
+    fn from(proposal: Proposal) -> Self {
+        let status = proposal.status();
+        Self { proposal, status }
+    }
+}
+
+/// How an account voted on a proposal
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalVote {
+    pub vote: Vote,
+    pub weight: U64,
+}
+
+/// Logs a proposal event for `proposal`
+fn emit_proposal_event(variant: fn(Vec<NftProposalLog>) -> EventLogVariant, proposal: &Proposal) {
+    variant(vec![NftProposalLog {
+        proposal_id: proposal.proposal_id.0.to_string(),
+        proposer_id: proposal.proposer_id.to_string(),
+        action: proposal.action.name().to_string(),
+        voting_ends_at: proposal.voting_ends_at.0.to_string(),
+    }])
+    .emit();
+}
+
+impl Contract {
+    fn expect_proposal(&self, proposal_id: u64) -> Proposal {
+        self.proposals
+            .get(proposal_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Proposal not found"))
+    }
+
+    /// Returns the latest snapshot that has been finished
+    fn internal_latest_finished_snapshot(&self) -> Option<Snapshot> {
+        (0..self.snapshots.len())
+            .rev()
+            .filter_map(|snapshot_id| self.snapshots.get(snapshot_id))
+            .find(|snapshot| snapshot.finished)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Propose an action to the holders in the latest finished snapshot, as one of them.
+    /// The attached deposit is added to the proposer's storage balance, which pays for the proposal.
+    #[payable]
+    pub fn nft_create_proposal(&mut self, description: String, action: ProposalAction) -> U64 {
+        let proposer_id = env::predecessor_account_id();
+        let snapshot = self
+            .internal_latest_finished_snapshot()
+            .unwrap_or_else(|| env::panic_str("cypher: No finished snapshot to vote with"));
+        require!(
+            self.snapshot_balances
+                .get(&(snapshot.snapshot_id.0, proposer_id.clone()))
+                .unwrap_or(0)
+                > 0,
+            "cypher: Only holders in the latest snapshot can make proposals"
+        );
+        if let ProposalAction::SetRoyaltyPolicy { policy } = &action {
+            policy.assert_valid();
+        }
+        self.internal_storage_deposit(&proposer_id, env::attached_deposit());
+
+        let initial_storage_usage = env::storage_usage();
+        let proposal_id = self.proposals.len();
+        // rounded up, so that a quorum of any share above 0 needs at least one vote
+        let quorum_weight =
+            snapshot.token_count.0 as u128 * self.governance_policy.quorum_basis_points as u128;
+        let mut quorum = quorum_weight / 10_000;
+        if quorum * 10_000 < quorum_weight {
+            quorum += 1;
+        }
+        let proposal = Proposal {
+            proposal_id: proposal_id.into(),
+            proposer_id: proposer_id.clone(),
+            description,
+            action,
+            snapshot_id: snapshot.snapshot_id,
+            quorum: (quorum as u64).into(),
+            voting_ends_at: (env::block_timestamp() + self.governance_policy.voting_period.0)
+                .into(),
+            yes: 0.into(),
+            no: 0.into(),
+            abstain: 0.into(),
+            executed: false,
+        };
+        self.proposals.push(&proposal);
+        self.internal_storage_debit(&proposer_id, env::storage_usage() - initial_storage_usage);
+        emit_proposal_event(EventLogVariant::NftProposalCreate, &proposal);
+        proposal_id.into()
+    }
+
+    /// Vote on an active proposal with the tokens you held in its snapshot, once.
+    /// The attached deposit is added to the voter's storage balance, which pays for the vote.
+    #[payable]
+    pub fn nft_vote(&mut self, proposal_id: U64, vote: Vote) {
+        let voter_id = env::predecessor_account_id();
+        let mut proposal = self.expect_proposal(proposal_id.0);
+        require!(
+            proposal.status() == ProposalStatus::Active,
+            "cypher: Voting on this proposal has ended"
+        );
+        let key = (proposal_id.0, voter_id.clone());
+        require!(
+            self.proposal_votes.get(&key).is_none(),
+            "cypher: Already voted on this proposal"
+        );
+        let weight = self
+            .snapshot_balances
+            .get(&(proposal.snapshot_id.0, voter_id.clone()))
+            .unwrap_or(0);
+        require!(
+            weight > 0,
+            "cypher: No tokens held in the proposal's snapshot"
+        );
+        self.internal_storage_deposit(&voter_id, env::attached_deposit());
+
+        let initial_storage_usage = env::storage_usage();
+        let tally = match vote {
+            Vote::Yes => &mut proposal.yes,
+            Vote::No => &mut proposal.no,
+            Vote::Abstain => &mut proposal.abstain,
+        };
+        tally.0 += weight;
+        self.proposals.replace(proposal_id.0, &proposal);
+        self.proposal_votes.insert(
+            &key,
+            &ProposalVote {
+                vote,
+                weight: weight.into(),
+            },
+        );
+        self.internal_storage_debit(&voter_id, env::storage_usage() - initial_storage_usage);
+        EventLogVariant::NftVote(vec![NftVoteLog {
+            proposal_id: proposal_id.0.to_string(),
+            voter_id: voter_id.to_string(),
+            vote: vote.name().to_string(),
+            weight: weight.to_string(),
+        }])
+        .emit();
+    }
+
+    /// Carry out the action of an approved proposal once its voting period is over
+    #[payable]
+    pub fn nft_execute_proposal(&mut self, proposal_id: U64) {
+        assert_one_yocto();
+        let mut proposal = self.expect_proposal(proposal_id.0);
+        require!(
+            proposal.status() == ProposalStatus::Approved,
+            "cypher: Proposal is not approved"
+        );
+        proposal.executed = true;
+
+        match &proposal.action {
+            ProposalAction::Text => {}
+            ProposalAction::UpdateContractMetadata { metadata } => {
+                metadata.assert_valid();
+                self.metadata.set(metadata);
+                EventLogVariant::ContractMetadataUpdate(vec![NftContractMetadataUpdateLog {
+                    memo: Some(format!("proposal {}", proposal_id.0)),
+                }])
+                .emit();
+            }
+            ProposalAction::SetRoyaltyPolicy { policy } => {
+                policy.assert_valid();
+                self.royalty_policy = policy.clone();
+            }
+        }
+        self.proposals.replace(proposal_id.0, &proposal);
+        emit_proposal_event(EventLogVariant::NftProposalExecute, &proposal);
+    }
+
+    /// Set the quorum, in basis points of the snapshot's tokens, and the voting period, in
+    /// nanoseconds, of new proposals
+    pub fn set_governance_policy(&mut self, policy: GovernancePolicy) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only owner can set the governance policy",
+        );
+        require!(
+            policy.quorum_basis_points <= 10_000,
+            "cypher: Quorum cannot be more than 100%"
+        );
+        require!(
+            policy.voting_period.0 > 0,
+            "cypher: Voting period must be positive"
+        );
+
+        self.governance_policy = policy;
+    }
+
+    /// get the quorum and voting period of new proposals
+    pub fn governance_policy(&self) -> GovernancePolicy {
+        self.governance_policy.clone()
+    }
+
+    /// get a proposal and its status
+    pub fn nft_proposal(&self, proposal_id: U64) -> Option<ProposalJson> {
+        self.proposals.get(proposal_id.0).map(ProposalJson::from)
+    }
+
+    /// Query for the proposals, oldest first, using pagination
+    pub fn nft_proposals(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<ProposalJson> {
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+        self.proposals
+            .iter()
+            .skip(start_index as usize)
+            .take(limit)
+            .map(ProposalJson::from)
+            .collect()
+    }
+
+    /// get how an account voted on a proposal, if it did
+    pub fn nft_proposal_vote_of(
+        &self,
+        proposal_id: U64,
+        account_id: AccountId,
+    ) -> Option<ProposalVote> {
+        self.proposal_votes.get(&(proposal_id.0, account_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    /// alice.near holds two tokens and bob.near one in a finished snapshot
+    fn setup_snapshot() -> Contract {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        mint(&mut contract, "2", "alice.near");
+        mint(&mut contract, "3", "bob.near");
+        call(&owner(), 0, 0);
+        let snapshot_id = contract.nft_snapshot_start();
+        assert!(contract.nft_snapshot_step(snapshot_id, None));
+        contract
+    }
+
+    fn propose(contract: &mut Contract) -> U64 {
+        call(&account("bob.near"), 0, 0);
+        contract.nft_create_proposal("hello".to_string(), ProposalAction::Text)
+    }
+
+    fn vote(contract: &mut Contract, voter_id: &str, proposal_id: U64, vote: Vote, timestamp: u64) {
+        call(&account(voter_id), 0, timestamp);
+        contract.nft_vote(proposal_id, vote);
+    }
+
+    fn status(contract: &Contract, proposal_id: U64, timestamp: u64) -> ProposalStatus {
+        call(&owner(), 0, timestamp);
+        contract.nft_proposal(proposal_id).unwrap().status
+    }
+
+    #[test]
+    fn votes_are_weighted_by_snapshot_balance() {
+        let mut contract = setup_snapshot();
+        let proposal_id = propose(&mut contract);
+        vote(&mut contract, "alice.near", proposal_id, Vote::Yes, 1);
+        vote(&mut contract, "bob.near", proposal_id, Vote::No, 1);
+
+        assert_eq!(status(&contract, proposal_id, 1), ProposalStatus::Active);
+        assert_eq!(
+            status(&contract, proposal_id, DEFAULT_VOTING_PERIOD),
+            ProposalStatus::Approved
+        );
+        call(&account("bob.near"), 1, DEFAULT_VOTING_PERIOD);
+        contract.nft_execute_proposal(proposal_id);
+        assert_eq!(
+            status(&contract, proposal_id, DEFAULT_VOTING_PERIOD),
+            ProposalStatus::Executed
+        );
+    }
+
+    #[test]
+    fn outvoted_proposal_is_rejected() {
+        let mut contract = setup_snapshot();
+        let proposal_id = propose(&mut contract);
+        vote(&mut contract, "alice.near", proposal_id, Vote::No, 1);
+        vote(&mut contract, "bob.near", proposal_id, Vote::Yes, 1);
+        assert_eq!(
+            status(&contract, proposal_id, DEFAULT_VOTING_PERIOD),
+            ProposalStatus::Rejected
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Already voted on this proposal")]
+    fn voting_twice_fails() {
+        let mut contract = setup_snapshot();
+        let proposal_id = propose(&mut contract);
+        vote(&mut contract, "bob.near", proposal_id, Vote::Yes, 1);
+        vote(&mut contract, "bob.near", proposal_id, Vote::Yes, 2);
+    }
+}
@@ -1,5 +1,4 @@
 use crate::*;
-use near_contract_standards::non_fungible_token::events::{NftMint, NftTransfer};
 use near_sdk::require;
 
 /// Ensures the attached_deposit is one yoctoNear
@@ -24,6 +23,76 @@ pub(crate) fn royalty_to_payout(royalty_percentage: u32, amount_to_pay: Balance)
     U128(royalty_percentage as u128 * amount_to_pay / 10_000u128)
 }
 
+/// key under which a token's entry is stored in a `LookupMap<TokenId, _>`
+pub(crate) fn token_id_key(token_id: &TokenId) -> Vec<u8> {
+    token_id.try_to_vec().unwrap()
+}
+
+/// Emits a single transfer event for many `(old_owner_id, new_owner_id, authorized_id, token_id)`
+/// transfers, grouping the tokens that share owners and authorized account into one entry
+pub(crate) fn emit_nft_transfers(
+    transfers: &[(AccountId, AccountId, Option<AccountId>, TokenId)],
+    memo: Option<&str>,
+) {
+    let mut groups: Vec<(&AccountId, &AccountId, Option<&AccountId>, Vec<String>)> = Vec::new();
+    for (old_owner_id, new_owner_id, authorized_id, token_id) in transfers {
+        let group = groups.iter_mut().find(|(old, new, authorized, _)| {
+            *old == old_owner_id && *new == new_owner_id && *authorized == authorized_id.as_ref()
+        });
+        match group {
+            Some((_, _, _, token_ids)) => token_ids.push(token_id.clone()),
+            None => groups.push((
+                old_owner_id,
+                new_owner_id,
+                authorized_id.as_ref(),
+                vec![token_id.clone()],
+            )),
+        }
+    }
+
+    let logs: Vec<NftTransferLog> = groups
+        .into_iter()
+        .map(|(old_owner_id, new_owner_id, authorized_id, token_ids)| NftTransferLog {
+            authorized_id: authorized_id.map(|id| id.to_string()),
+            old_owner_id: old_owner_id.to_string(),
+            new_owner_id: new_owner_id.to_string(),
+            token_ids,
+            memo: memo.map(|memo| memo.to_string()),
+        })
+        .collect();
+    EventLogVariant::NftTransfer(logs).emit();
+}
+
+/// Adds `token_id` to the set of `account_id` in a `LookupMap<AccountId, UnorderedSet<TokenId>>` index
+pub(crate) fn internal_add_to_token_set(
+    by_account: &mut LookupMap<AccountId, UnorderedSet<TokenId>>,
+    account_id: &AccountId,
+    token_id: &TokenId,
+    inner_key: impl FnOnce(Vec<u8>) -> StorageKey,
+) {
+    let mut token_set = by_account
+        .get(account_id)
+        .unwrap_or_else(|| UnorderedSet::new(inner_key(env::sha256(account_id.as_bytes()))));
+    token_set.insert(token_id);
+    by_account.insert(account_id, &token_set);
+}
+
+/// Removes `token_id` from the set of `account_id` in a `LookupMap<AccountId, UnorderedSet<TokenId>>` index
+pub(crate) fn internal_remove_from_token_set(
+    by_account: &mut LookupMap<AccountId, UnorderedSet<TokenId>>,
+    account_id: &AccountId,
+    token_id: &TokenId,
+) {
+    if let Some(mut token_set) = by_account.get(account_id) {
+        token_set.remove(token_id);
+        if token_set.is_empty() {
+            by_account.remove(account_id);
+        } else {
+            by_account.insert(account_id, &token_set);
+        }
+    }
+}
+
 impl Contract {
     /// Internal function to handle assemblying and updating the contract with the new NFT
     pub(crate) fn internal_mint(
@@ -33,30 +102,21 @@ impl Contract {
         perpetual_royalties: Option<HashMap<AccountId, u32>>,
         token_metadata: TokenMetadata,
     ) -> TokenJson {
+        self.internal_assert_not_blocked(&env::predecessor_account_id());
+        self.internal_assert_not_blocked(&token_owner_id);
         // set royalty for contract owner on every mint
-        let mut royalty = HashMap::from([(self.owner_id.clone(), 150)]);
+        let policy = &self.royalty_policy;
+        let mut royalty = HashMap::new();
+        if policy.owner_basis_points > 0 {
+            royalty.insert(self.owner_id.clone(), policy.owner_basis_points);
+        }
 
-        let mut total_perpetual = 150u32;
         // if we have perpetual royaties
         if let Some(perpetual_royalties) = perpetual_royalties {
-            // check bleow gas limit of 5 royalties
-            assert!(
-                perpetual_royalties.len() <= 7,
-                " Cannot add more than 7 perpetual royalties"
-            );
+            policy.assert_valid_royalties(&perpetual_royalties);
             // loop through and add acounts t the list
             for (account, amount) in perpetual_royalties {
-                assert!(
-                    amount < MINTER_ROYALTY_CAP,
-                    " The threshold royalty is capped at 70% for an accountId"
-                );
                 royalty.insert(account, amount);
-
-                total_perpetual += amount;
-                assert!(
-                    total_perpetual <= MINTER_ROYALTY_CAP,
-                    " The sum of all the perpetual royalties cannot be more than 70%"
-                );
             }
         }
         // royalty limit for minter capped at 20%
@@ -71,6 +131,15 @@ impl Contract {
         self.royalty_by_id
             .as_mut()
             .and_then(|by_id| by_id.insert(&token_id, token_royalty));
+        for account_id in royalty.keys() {
+            internal_add_to_token_set(
+                &mut self.royalty_tokens_by_account,
+                account_id,
+                &token_id,
+                |account_hash| StorageKey::RoyaltyTokensByAccountInner { account_hash },
+            );
+        }
+        self.internal_settle_revenue(&token_owner_id);
         if let Some(tokens_per_owner) = &mut self.tokens_per_owner {
             let mut token_ids = tokens_per_owner.get(&token_owner_id).unwrap_or_else(|| {
                 UnorderedSet::new(StorageKey::TokensPerOwnerInner {
@@ -92,11 +161,13 @@ impl Contract {
             next_approval_id_by_id.insert(&token_id, &1u64);
         }
 
-        NftMint {
-            owner_id: &token_owner_id,
-            token_ids: &[&token_id],
+        self.internal_record_provenance(&token_id, &token_owner_id, ProvenanceKind::Mint);
+
+        EventLogVariant::NftMint(vec![NftMintLog {
+            owner_id: token_owner_id.to_string(),
+            token_ids: vec![token_id.clone()],
             memo: None,
-        }
+        }])
         .emit();
 
         TokenJson {
@@ -105,12 +176,14 @@ impl Contract {
             metadata: Some(token_metadata),
             royalty: Some(royalty),
             approved_account_ids,
+            freeze: None,
         }
     }
 
     /// Transfer token_id from `sender_id` to `receiver_id`
     ///
-    /// Performs safety checks and logging
+    /// Performs safety checks and logging. The cleared approvals are returned raw,
+    /// they are only deserialized when an approved account is the sender.
     pub(crate) fn internal_transfer(
         &mut self,
         sender_id: &AccountId,
@@ -118,20 +191,47 @@ impl Contract {
         token_id: &TokenId,
         approval_id: Option<u64>,
         memo: &Option<String>,
-    ) -> (AccountId, Option<HashMap<AccountId, u64>>) {
+    ) -> (AccountId, Option<RawApprovals>) {
+        let (owner_id, approved_account_ids) =
+            self.internal_transfer_unlogged(sender_id, receiver_id, token_id, approval_id);
+
+        let authorized_id = Some(sender_id.clone()).filter(|sender_id| sender_id != &owner_id);
+        emit_nft_transfers(
+            &[(owner_id.clone(), receiver_id.clone(), authorized_id, token_id.clone())],
+            memo.as_deref(),
+        );
+
+        //return the preivous token object that was transferred.
+        (owner_id, approved_account_ids)
+    }
+
+    /// Transfer token_id from `sender_id` to `receiver_id`
+    ///
+    /// Performs the same safety checks as `internal_transfer` but no logging,
+    /// so that callers moving many tokens can emit a single event.
+    pub(crate) fn internal_transfer_unlogged(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        token_id: &TokenId,
+        approval_id: Option<u64>,
+    ) -> (AccountId, Option<RawApprovals>) {
         let owner_id = self
             .owner_by_id
             .get(token_id)
             .expect(" Token doesn't exists!");
+        self.internal_assert_token_unlocked(token_id);
+        self.internal_assert_transfer_accounts(sender_id, &owner_id, receiver_id);
 
         let approved_account_ids = self
             .approvals_by_id
             .as_mut()
-            .and_then(|by_id| by_id.remove(token_id));
+            .and_then(|by_id| by_id.remove_raw(&token_id_key(token_id)));
 
-        let sender_id = if sender_id != &owner_id {
+        if sender_id != &owner_id {
             let app_acc_ids = approved_account_ids
                 .as_ref()
+                .and_then(|raw| HashMap::<AccountId, u64>::try_from_slice(raw).ok())
                 .unwrap_or_else(|| env::panic_str(" Unauthorized"));
             let actual_approval_id = app_acc_ids.get(sender_id);
             if actual_approval_id.is_none() {
@@ -145,43 +245,214 @@ impl Contract {
                     actual_approval_id, approval_id
                 )
             );
-            Some(sender_id)
-        } else {
-            None
-        };
+        }
+
+        // the sender gets the storage they release back, the receiver pays for what they take
+        let (storage_released, storage_used) = self.internal_transfer_unguarded(
+            token_id,
+            &owner_id,
+            receiver_id,
+            ProvenanceKind::Transfer,
+        );
+        self.internal_storage_credit(&owner_id, storage_released);
+        self.internal_storage_debit(receiver_id, storage_used);
+
+        (owner_id, approved_account_ids)
+    }
+
+    /// Returns true if `account_id` can mint: the contract owner and accounts with minting access
+    pub(crate) fn internal_is_minter(&self, account_id: &AccountId) -> bool {
+        account_id == &self.owner_id || self.allow_list.contains(account_id)
+    }
 
+    /// Panics unless `sender_id` may move tokens of `owner_id` to `receiver_id`.
+    /// Every transfer path calls it, so rules about who can send or receive apply to all of them.
+    pub(crate) fn internal_assert_transfer_accounts(
+        &self,
+        sender_id: &AccountId,
+        owner_id: &AccountId,
+        receiver_id: &AccountId,
+    ) {
+        require!(
+            owner_id != receiver_id,
+            "cypher: Current and next owner must differ"
+        );
+        self.internal_assert_not_blocked(sender_id);
+        self.internal_assert_not_blocked(owner_id);
+        self.internal_assert_not_blocked(receiver_id);
+    }
+
+    /// Panics if the token is locked in place with its owner
+    pub(crate) fn internal_assert_token_unlocked(&mut self, token_id: &TokenId) {
+        self.internal_assert_not_frozen(token_id);
+        require!(
+            !self.internal_has_pending_transfer(token_id),
+            "cypher: Token has a pending transfer"
+        );
+        require!(
+            !self.internal_is_in_auction(token_id),
+            "cypher: Token is in an auction"
+        );
+        require!(
+            !self.internal_is_in_dutch_auction(token_id),
+            "cypher: Token is in a Dutch auction"
+        );
+        require!(
+            !self.internal_is_rented(token_id),
+            "cypher: Token is rented out"
+        );
         require!(
-            &owner_id != receiver_id,
-            " Current and next owner must differ"
+            !self.internal_is_staked(token_id),
+            "cypher: Token is staked"
         );
-        self.internal_transfer_unguarded(token_id, &owner_id, receiver_id);
-
-        NftTransfer {
-            old_owner_id: &owner_id,
-            new_owner_id: receiver_id,
-            token_ids: &[token_id],
-            authorized_id: sender_id.filter(|sender_id| *sender_id == &owner_id),
-            memo: memo.as_deref(),
+    }
+
+    /// Returns true if the token itself is locked in place with its owner, as checked by
+    /// `internal_assert_token_unlocked`. Freezes of its owner's account aren't looked at.
+    pub(crate) fn internal_is_token_locked(&mut self, token_id: &TokenId) -> bool {
+        self.token_freezes.get(token_id).is_some()
+            || self.internal_has_pending_transfer(token_id)
+            || self.internal_is_in_auction(token_id)
+            || self.internal_is_in_dutch_auction(token_id)
+            || self.internal_is_rented(token_id)
+            || self.internal_is_staked(token_id)
+    }
+
+    /// Keeps the previous owner's approvals aside while an `nft_transfer_call` resolves,
+    /// so the callback arguments don't grow with the number of approvals
+    pub(crate) fn internal_park_approvals(
+        &mut self,
+        token_id: &TokenId,
+        previous_owner_id: &AccountId,
+        approved_account_ids: &RawApprovals,
+    ) {
+        let key = (token_id.clone(), previous_owner_id.clone());
+        if let Some(replaced) = self.pending_approvals.insert(&key, approved_account_ids) {
+            self.internal_storage_credit(
+                previous_owner_id,
+                bytes_for_approved_account_ids_raw(&replaced),
+            );
         }
-        .emit();
+    }
 
-        //return the preivous token object that was transferred.
-        (owner_id, approved_account_ids)
+    /// Approve logs for the approvals a token has now, oldest first. Used after a refused
+    /// transfer call, to tell indexers about the approvals it got back.
+    pub(crate) fn internal_approve_logs(
+        &self,
+        owner_id: &AccountId,
+        token_id: &TokenId,
+    ) -> Vec<NftApproveLog> {
+        let mut approvals: Vec<(AccountId, u64)> = self
+            .approvals_by_id
+            .as_ref()
+            .and_then(|by_id| by_id.get(token_id))
+            .map(|approvals| approvals.into_iter().collect())
+            .unwrap_or_default();
+        approvals.sort_by_key(|(_, approval_id)| *approval_id);
+        approvals
+            .into_iter()
+            .map(|(account_id, approval_id)| NftApproveLog {
+                owner_id: owner_id.to_string(),
+                token_id: token_id.clone(),
+                account_id: account_id.to_string(),
+                approval_id,
+            })
+            .collect()
     }
 
-    /// Transfer token_id from `from` to `to`
+    /// Settles a token sent through a transfer call once the receiver has answered
     ///
-    /// Do not perform any safety checks or do any logging
+    /// Returns true if the token stays with `receiver_id`, false if it was returned to
+    /// `previous_owner_id` together with the approvals it had before. Does not do any logging,
+    /// callers log the transfer back and then the approvals, see `internal_approve_logs`.
+    pub(crate) fn internal_resolve_transfer(
+        &mut self,
+        previous_owner_id: &AccountId,
+        receiver_id: &AccountId,
+        token_id: &TokenId,
+        must_revert: bool,
+    ) -> bool {
+        // approvals the previous owner had set before the transfer call
+        let approved_account_ids = self
+            .pending_approvals
+            .remove(&(token_id.clone(), previous_owner_id.clone()));
+
+        // If the call succeeded, or the receiver already transferred the token away or burned it,
+        // the token can't be returned. Credit storage cost for storing approvals to original owner
+        // and return early.
+        if !must_revert || self.owner_by_id.get(token_id).as_ref() != Some(receiver_id) {
+            if let Some(approved_account_ids) = approved_account_ids {
+                self.internal_storage_credit(
+                    previous_owner_id,
+                    bytes_for_approved_account_ids_raw(&approved_account_ids),
+                );
+            }
+            return true;
+        }
+
+        // OTHERWISE, set owner back to previous_owner_id and restore approved_account_ids.
+        // The previous owner is charged again without a balance check, the callback must not fail.
+        // The blocklist isn't checked either, a refused token always goes back to where it came from.
+        let (storage_released, storage_used) =
+            self.internal_transfer_unguarded(
+                token_id,
+                receiver_id,
+                previous_owner_id,
+                ProvenanceKind::TransferCallRevert,
+            );
+        self.internal_storage_credit(receiver_id, storage_released);
+        self.internal_storage_debit_unchecked(previous_owner_id, storage_used);
+
+        // If using Approval Management extension,
+        // 1. revert any approvals receiver already set, crediting storage costs
+        // 2. reset approvals to what previous owner had set before the transfer call
+        let mut receiver_storage_released = 0;
+        if let Some(by_id) = &mut self.approvals_by_id {
+            let token_key = token_id_key(token_id);
+            if let Some(receiver_approvals) = by_id.remove_raw(&token_key) {
+                receiver_storage_released = bytes_for_approved_account_ids_raw(&receiver_approvals);
+            }
+            if let Some(previous_owner_approvals) = approved_account_ids {
+                by_id.insert_raw(&token_key, &previous_owner_approvals);
+            }
+        }
+        self.internal_storage_credit(receiver_id, receiver_storage_released);
+        false
+    }
+
+    /// Transfer token_id from `from` to `to`, recording `kind` in the token's history
+    ///
+    /// Do not perform any safety checks or do any logging. Returns the bytes of storage released
+    /// by `from` and taken by `to` in `tokens_per_owner` and the token's history. The revenue
+    /// account settled for `from` is charged to it here, without a balance check.
     pub(crate) fn internal_transfer_unguarded(
         &mut self,
         #[allow(clippy::ptr_arg)] token_id: &TokenId,
         from: &AccountId,
         to: &AccountId,
-    ) {
-        // update owner
+        kind: ProvenanceKind,
+    ) -> (u64, u64) {
+        // update owner, listings and users don't survive a change of owner
         self.owner_by_id.insert(token_id, to);
+        self.internal_remove_listing(token_id);
+        self.internal_remove_rental_listing(token_id);
+        self.internal_clear_user(token_id, from);
+
+        let mut storage_released = 0;
+        let mut storage_used = 0;
+        // revenue is earned per token held, settle both sides before their holdings change
+        let initial_storage_usage = env::storage_usage();
+        self.internal_settle_revenue(from);
+        self.internal_storage_debit_unchecked(
+            from,
+            env::storage_usage().saturating_sub(initial_storage_usage),
+        );
+        let initial_storage_usage = env::storage_usage();
+        self.internal_settle_revenue(to);
+        storage_used += env::storage_usage().saturating_sub(initial_storage_usage);
 
         if let Some(tokens_per_owner) = &mut self.tokens_per_owner {
+            let initial_storage_usage = env::storage_usage();
             let mut owner_tokens = tokens_per_owner.get(from).unwrap_or_else(|| {
                 env::panic_str(" Unable to access tokens per owner in unguarded call.");
             });
@@ -191,7 +462,9 @@ impl Contract {
             } else {
                 tokens_per_owner.insert(from, &owner_tokens);
             }
+            storage_released = initial_storage_usage.saturating_sub(env::storage_usage());
 
+            let initial_storage_usage = env::storage_usage();
             let mut receiver_tokens = tokens_per_owner.get(to).unwrap_or_else(|| {
                 UnorderedSet::new(StorageKey::TokensPerOwnerInner {
                     account_hash: env::sha256(to.as_bytes()),
@@ -199,6 +472,19 @@ impl Contract {
             });
             receiver_tokens.insert(token_id);
             tokens_per_owner.insert(to, &receiver_tokens);
+            storage_used += env::storage_usage().saturating_sub(initial_storage_usage);
         }
+
+        let initial_storage_usage = env::storage_usage();
+        self.internal_record_provenance(token_id, to, kind);
+        self.transfer_counts
+            .insert(token_id, &(self.internal_transfer_count(token_id) + 1));
+        storage_used += env::storage_usage().saturating_sub(initial_storage_usage);
+        (storage_released, storage_used)
+    }
+
+    /// Number of times the token has changed hands since it was minted
+    pub(crate) fn internal_transfer_count(&self, token_id: &TokenId) -> u64 {
+        self.transfer_counts.get(token_id).unwrap_or(0)
     }
 }
@@ -0,0 +1,188 @@
+use crate::*;
+use near_sdk::json_types::U64;
+
+/// Sales kept per token, older ones are dropped so the storage the buyer is charged for
+/// without a balance check stays bounded
+pub const MAX_SALES_PER_TOKEN: u64 = 20;
+
+/// A sale of a token, recorded whenever its price is paid out
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Sale {
+    // position among all the sales of the token, the first is 0
+    pub index: U64,
+    pub seller_id: AccountId,
+    pub buyer_id: AccountId,
+    // in yoctoNEAR, or in the smallest unit of `ft_contract_id`
+    pub price: U128,
+    pub ft_contract_id: Option<AccountId>,
+    // nanoseconds since the epoch
+    pub sold_at: U64,
+}
+
+/// Summary of a token's sales
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenSaleStats {
+    pub sale_count: U64,
+    pub last_sale: Sale,
+}
+
+/// Total sales volume of the collection in one currency
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionVolume {
+    // NEAR if unset
+    pub ft_contract_id: Option<AccountId>,
+    pub volume: U128,
+    pub sale_count: U64,
+}
+
+impl Contract {
+    /// Records a sale of `token_id` for `price` and adds each share of `payout` to its recipient's
+    /// earnings. Only a constant number of entries is touched, so payouts stay bounded in gas.
+    pub(crate) fn internal_record_sale(
+        &mut self,
+        token_id: &TokenId,
+        seller_id: &AccountId,
+        buyer_id: &AccountId,
+        price: Balance,
+        ft_contract_id: Option<&AccountId>,
+        payout: &Payout,
+    ) {
+        let initial_storage_usage = env::storage_usage();
+        for (account_id, amount) in payout.payout.iter() {
+            if amount.0 > 0 {
+                let key = (account_id.clone(), ft_contract_id.cloned());
+                let earned = self.earnings.get(&key).unwrap_or(0);
+                self.earnings.insert(&key, &(earned + amount.0));
+            }
+        }
+
+        let (mut first, next) = self.sale_windows.get(token_id).unwrap_or((0, 0));
+        self.sales.insert(
+            &(token_id.clone(), next),
+            &Sale {
+                index: next.into(),
+                seller_id: seller_id.clone(),
+                buyer_id: buyer_id.clone(),
+                price: price.into(),
+                ft_contract_id: ft_contract_id.cloned(),
+                sold_at: env::block_timestamp().into(),
+            },
+        );
+        let next = next + 1;
+        if next - first > MAX_SALES_PER_TOKEN {
+            self.sales.remove(&(token_id.clone(), first));
+            first += 1;
+        }
+        self.sale_windows.insert(token_id, &(first, next));
+
+        self.internal_mark_provenance_sale(token_id, buyer_id, price, ft_contract_id);
+
+        let currency = ft_contract_id.cloned();
+        let (volume, sale_count) = self.volume_by_currency.get(&currency).unwrap_or((0, 0));
+        self.volume_by_currency
+            .insert(&currency, &(volume + price, sale_count + 1));
+
+        // The sale is already paid for so recording it must not fail, the buyer is charged for
+        // the storage without a balance check. Sales are capped so the charge stays bounded.
+        self.internal_storage_debit_unchecked(
+            buyer_id,
+            env::storage_usage() - initial_storage_usage,
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// get the total an account has earned from sales, as royalties or as seller, in NEAR or in
+    /// `ft_contract_id`. Balances passed to `nft_transfer_payout` are counted as NEAR.
+    pub fn nft_earnings_of(
+        &self,
+        account_id: AccountId,
+        ft_contract_id: Option<AccountId>,
+    ) -> U128 {
+        self.earnings
+            .get(&(account_id, ft_contract_id))
+            .unwrap_or(0)
+            .into()
+    }
+
+    /// get the number of sales of a token and the last one, if it was ever sold
+    pub fn nft_sale_stats(&self, token_id: TokenId) -> Option<TokenSaleStats> {
+        let (_, next) = self.sale_windows.get(&token_id)?;
+        Some(TokenSaleStats {
+            sale_count: next.into(),
+            last_sale: self.sales.get(&(token_id, next - 1))?,
+        })
+    }
+
+    /// Query for the sales of a token, oldest first, using pagination.
+    /// Only the last `MAX_SALES_PER_TOKEN` are kept, `from_index` counts from the oldest of them.
+    pub fn nft_sales(
+        &self,
+        token_id: TokenId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Sale> {
+        let (first, next) = if let Some(window) = self.sale_windows.get(&token_id) {
+            window
+        } else {
+            return vec![];
+        };
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        let limit = limit.unwrap_or(u64::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+
+        let start = first.saturating_add(start_index.min(u64::MAX as u128) as u64);
+        let end = start.saturating_add(limit).min(next);
+        (start..end)
+            .filter_map(|index| self.sales.get(&(token_id.clone(), index)))
+            .collect()
+    }
+
+    /// get the sales volume of the collection in each currency it was sold in
+    pub fn nft_collection_volume(&self) -> Vec<CollectionVolume> {
+        self.volume_by_currency
+            .iter()
+            .map(|(ft_contract_id, (volume, sale_count))| CollectionVolume {
+                ft_contract_id,
+                volume: volume.into(),
+                sale_count: sale_count.into(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn sales_beyond_the_cap_drop_the_oldest() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        let sales = MAX_SALES_PER_TOKEN + 5;
+        for sale in 0..sales {
+            let (seller, buyer) = if sale % 2 == 0 {
+                ("alice.near", "bob.near")
+            } else {
+                ("bob.near", "alice.near")
+            };
+            call(&account(seller), 1, sale);
+            contract.nft_list("1".to_string(), NEAR.into(), None);
+            call(&account(buyer), NEAR, sale);
+            contract.nft_buy("1".to_string());
+            assert_eq!(owner_of(&contract, "1"), account(buyer));
+        }
+
+        let stats = contract.nft_sale_stats("1".to_string()).unwrap();
+        assert_eq!(stats.sale_count.0, sales);
+        assert_eq!(stats.last_sale.index.0, sales - 1);
+        let kept = contract.nft_sales("1".to_string(), None, None);
+        assert_eq!(kept.len() as u64, MAX_SALES_PER_TOKEN);
+        assert_eq!(kept[0].index.0, 5);
+    }
+}
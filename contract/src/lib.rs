@@ -3,39 +3,82 @@ use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_contract_standards::non_fungible_token::metadata::{
     NFTContractMetadata, NonFungibleTokenMetadataProvider, TokenMetadata,
 };
-use near_sdk::collections::{LazyOption, LookupMap, LookupSet, TreeMap, UnorderedSet};
+use near_sdk::collections::{
+    LazyOption, LookupMap, LookupSet, TreeMap, UnorderedMap, UnorderedSet, Vector,
+};
 use near_sdk::json_types::U128;
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
-    env, ext_contract, near_bindgen, require, AccountId, Balance,
+    env, ext_contract, near_bindgen, require, AccountId, Balance, Promise,
     BorshStorageKey, Gas, IntoStorageKey, PanicOnDefault, PromiseOrValue, PromiseResult,
 };
 
 use crate::internal::*;
+pub use crate::market::*;
 pub use crate::metadata::*;
 pub use crate::mint::*;
 pub use crate::nft_core::*;
+pub use crate::offer::*;
+pub use crate::provenance::*;
+pub use crate::rental::*;
+pub use crate::revenue::*;
 pub use crate::approval::*;
+pub use crate::auction::*;
+pub use crate::batch::*;
+pub use crate::blocklist::*;
+pub use crate::collection_offer::*;
+pub use crate::dutch_auction::*;
+pub use crate::freeze::*;
+pub use crate::ft_payment::*;
+pub use crate::governance::*;
+pub use crate::ledger::*;
 pub use crate::royalty::*;
+pub use crate::safe_transfer::*;
+pub use crate::snapshot::*;
+pub use crate::staking::*;
+pub use crate::storage::*;
 pub use crate::events::*;
 pub use crate::utils::*;
 
 mod internal;
 mod approval; 
+mod auction;
+mod batch;
+mod blocklist;
+mod collection_offer;
+mod dutch_auction;
 mod enumeration; 
+mod freeze;
+mod ft_payment;
+mod governance;
+mod ledger;
+mod market;
 mod metadata; 
 mod mint; 
 mod nft_core; 
+mod offer;
+mod provenance;
+mod rental;
+mod revenue;
 mod royalty; 
+mod safe_transfer;
+mod snapshot;
+mod staking;
+mod storage;
 mod events;
 mod utils;
 
-/// This spec can be treated like a version of the standard.
-pub const NFT_METADATA_SPEC: &str = "1.0.0";
+#[cfg(test)]
+mod test_utils;
+
+/// Version of the event format every event is logged with
+pub const NFT_EVENT_VERSION: &str = "1.0.0";
 /// This is the name of the NFT standard we're using
-pub const NFT_STANDARD_NAME: &str = "nep178";
+pub const NFT_STANDARD_NAME: &str = "nep171";
 // Total royalty on a particular NFT
 pub const MINTER_ROYALTY_CAP: u32 = 7000;
+// Approvals a token can hold until the owner changes the limit
+pub const DEFAULT_MAX_APPROVALS_PER_TOKEN: u32 = 32;
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Contract {
@@ -57,12 +100,117 @@ pub struct Contract {
     // Approval managemeent
     pub approvals_by_id: Option<LookupMap<TokenId, HashMap<AccountId, u64>>>,
     pub next_approval_id_by_id: Option<LookupMap<TokenId, u64>>,
+    pub max_approvals_per_token: u32,
+    // approvals of the previous owner while an nft_transfer_call is resolving
+    pub pending_approvals: LookupMap<(TokenId, AccountId), RawApprovals>,
 
     // Royalty
     pub royalty_by_id: Option<LookupMap<TokenId, TokenRoyalty>>,
 
      //keep track of accounts that can mint NFTs
      pub allow_list: LookupSet<AccountId>,
+
+    // NEP-145 storage paid for by each registered account
+    pub storage_accounts: LookupMap<AccountId, AccountStorage>,
+    pub storage_registration_bytes: u64,
+
+    // Safe transfers waiting for the receiver to accept them
+    pub pending_transfers: LookupMap<TokenId, PendingTransfer>,
+    pub pending_transfers_by_owner: LookupMap<AccountId, UnorderedSet<TokenId>>,
+    pub pending_transfers_by_receiver: LookupMap<AccountId, UnorderedSet<TokenId>>,
+
+    // Fixed-price listings of the built-in market
+    pub listings: UnorderedMap<TokenId, Listing>,
+    pub listings_by_seller: LookupMap<AccountId, UnorderedSet<TokenId>>,
+
+    // English auctions, the tokens are locked until settled
+    pub auctions: UnorderedMap<TokenId, Auction>,
+
+    // Dutch auctions, of existing tokens or of token IDs minted on sale
+    pub dutch_auctions: UnorderedMap<TokenId, DutchAuction>,
+
+    // Escrowed offers, keyed by token and buyer
+    pub offers: LookupMap<(TokenId, AccountId), Offer>,
+    pub offers_by_token: LookupMap<TokenId, UnorderedSet<AccountId>>,
+    pub offers_by_buyer: LookupMap<AccountId, UnorderedSet<TokenId>>,
+
+    // Escrowed offers for any tokens of the collection, keyed by offer ID
+    pub collection_offers: UnorderedMap<u64, CollectionOffer>,
+    pub collection_offers_by_buyer: LookupMap<AccountId, UnorderedSet<u64>>,
+    pub next_collection_offer_id: u64,
+
+    // NEP-141 contracts sales can be priced and paid in
+    pub accepted_ft_contracts: UnorderedSet<AccountId>,
+    // Payouts that failed to reach their receiver, keyed by FT contract and receiver
+    pub ft_unpaid: LookupMap<(AccountId, AccountId), FtUnpaid>,
+
+    // Tokens on which each account earns royalties
+    pub royalty_tokens_by_account: LookupMap<AccountId, UnorderedSet<TokenId>>,
+
+    // Sales ledger: earnings per account and currency (NEAR if unset), the window of sale
+    // indexes still kept for each token, the sales, and the collection's volume and sale count
+    // per currency
+    pub earnings: LookupMap<(AccountId, Option<AccountId>), Balance>,
+    pub sale_windows: LookupMap<TokenId, (u64, u64)>,
+    pub sales: LookupMap<(TokenId, u64), Sale>,
+    pub volume_by_currency: UnorderedMap<Option<AccountId>, (Balance, u64)>,
+
+    // Ownership history of each token: the window of entry indexes still kept, the entries,
+    // and how many entries a token keeps (0 stops recording)
+    pub provenance_windows: LookupMap<TokenId, (u64, u64)>,
+    pub provenance: LookupMap<(TokenId, u64), ProvenanceEntry>,
+    pub provenance_cap: u64,
+
+    // Holder snapshots by ID, the progress of unfinished ones, each holder's count and
+    // the holders of each snapshot
+    pub snapshots: Vector<Snapshot>,
+    pub snapshot_progress: LookupMap<u64, SnapshotProgress>,
+    pub snapshot_balances: LookupMap<(u64, AccountId), u64>,
+    pub snapshot_holders: LookupMap<u64, Vector<AccountId>>,
+
+    // Royalties set on every mint
+    pub royalty_policy: RoyaltyPolicy,
+
+    // Governance: quorum and voting period of new proposals, proposals by ID and their votes
+    pub governance_policy: GovernancePolicy,
+    pub proposals: Vector<Proposal>,
+    pub proposal_votes: LookupMap<(u64, AccountId), ProposalVote>,
+
+    // Revenue shared between all tokens: earned per token since the first deposit, deposited
+    // but not yet shared, deposited in total, and each holder's standing
+    pub revenue_per_token: Balance,
+    pub revenue_remainder: Balance,
+    pub revenue_deposited: Balance,
+    pub revenue_accounts: LookupMap<AccountId, RevenueAccount>,
+
+    // Rentals: the user of each token that has one, and the tokens offered for rent
+    pub token_users: LookupMap<TokenId, TokenUser>,
+    pub rental_listings: UnorderedMap<TokenId, RentalListing>,
+
+    // Staking: stakes by token and by owner, each staker's points, the rate and lock period,
+    // and the points a token staked since the start would have earned as of `staking_updated_at`
+    pub stakes: LookupMap<TokenId, Stake>,
+    pub stakes_by_owner: LookupMap<AccountId, UnorderedSet<TokenId>>,
+    pub staking_accounts: LookupMap<AccountId, StakingAccount>,
+    pub staking_policy: StakingPolicy,
+    pub staking_accumulator: u128,
+    pub staking_updated_at: u64,
+
+    // Frozen tokens and accounts, recoveries scheduled for frozen tokens,
+    // and the accounts that opted out of recovery
+    pub token_freezes: LookupMap<TokenId, Freeze>,
+    pub account_freezes: LookupMap<AccountId, Freeze>,
+    pub recoveries: LookupMap<TokenId, Recovery>,
+    pub recovery_opt_outs: LookupSet<AccountId>,
+
+    // Accounts that can't mint, send, receive or be approved for tokens
+    pub blocklist: UnorderedSet<AccountId>,
+
+    // Number of times each token has changed hands
+    pub transfer_counts: LookupMap<TokenId, u64>,
+
+    // Series each token was minted in, if any
+    pub series_by_token: LookupMap<TokenId, SeriesId>,
 }
 
 /// Helper structure for keys of the persistent collections.
@@ -75,7 +223,58 @@ pub enum StorageKey {
     TokenTypesLocked,
     ApprovalPrefix,
     TokenById,
-    AllowList
+    AllowList,
+    PendingApprovals,
+    StorageAccounts,
+    PendingTransfers,
+    PendingTransfersByOwner,
+    PendingTransfersByOwnerInner { account_hash: Vec<u8> },
+    PendingTransfersByReceiver,
+    PendingTransfersByReceiverInner { account_hash: Vec<u8> },
+    Listings,
+    ListingsBySeller,
+    ListingsBySellerInner { account_hash: Vec<u8> },
+    Auctions,
+    DutchAuctions,
+    Offers,
+    OffersByToken,
+    OffersByTokenInner { token_hash: Vec<u8> },
+    OffersByBuyer,
+    OffersByBuyerInner { account_hash: Vec<u8> },
+    CollectionOffers,
+    CollectionOffersByBuyer,
+    CollectionOffersByBuyerInner { account_hash: Vec<u8> },
+    AcceptedFtContracts,
+    FtUnpaid,
+    RoyaltyTokensByAccount,
+    RoyaltyTokensByAccountInner { account_hash: Vec<u8> },
+    Earnings,
+    SaleWindows,
+    Sales,
+    VolumeByCurrency,
+    ProvenanceWindows,
+    Provenance,
+    Snapshots,
+    SnapshotProgress,
+    SnapshotBalances,
+    SnapshotHolders,
+    SnapshotHoldersInner { snapshot_id: u64 },
+    Proposals,
+    ProposalVotes,
+    RevenueAccounts,
+    TokenUsers,
+    RentalListings,
+    Stakes,
+    StakesByOwner,
+    StakesByOwnerInner { account_hash: Vec<u8> },
+    StakingAccounts,
+    TokenFreezes,
+    AccountFreezes,
+    Recoveries,
+    RecoveryOptOuts,
+    Blocklist,
+    TransferCounts,
+    SeriesByToken,
 }
 
 #[near_bindgen]
@@ -117,7 +316,7 @@ impl Contract {
             )
         };
         //create a variable of type Self with all the fields initialized. 
-        Self {
+        let mut this = Self {
             //Storage keys are simply the prefixes used for the collections. This helps avoid data collision
             tokens_per_owner: Some(LookupMap::new(
                 StorageKey::TokensPerOwner.into_storage_key(),
@@ -131,12 +330,78 @@ impl Contract {
             approvals_by_id,
             royalty_by_id: Some(LookupMap::new(StorageKey::TokenById.into_storage_key())),
             next_approval_id_by_id,
+            max_approvals_per_token: DEFAULT_MAX_APPROVALS_PER_TOKEN,
+            pending_approvals: LookupMap::new(StorageKey::PendingApprovals.into_storage_key()),
             metadata: LazyOption::new(
                 StorageKey::NFTContractMetadata.into_storage_key(),
                 Some(&metadata),
             ),
-            allow_list: LookupSet::new(StorageKey::AllowList.try_to_vec().unwrap())
-        }
+            allow_list: LookupSet::new(StorageKey::AllowList.try_to_vec().unwrap()),
+            storage_accounts: LookupMap::new(StorageKey::StorageAccounts.into_storage_key()),
+            storage_registration_bytes: 0,
+            pending_transfers: LookupMap::new(StorageKey::PendingTransfers.into_storage_key()),
+            pending_transfers_by_owner: LookupMap::new(
+                StorageKey::PendingTransfersByOwner.into_storage_key(),
+            ),
+            pending_transfers_by_receiver: LookupMap::new(
+                StorageKey::PendingTransfersByReceiver.into_storage_key(),
+            ),
+            listings: UnorderedMap::new(StorageKey::Listings.into_storage_key()),
+            listings_by_seller: LookupMap::new(StorageKey::ListingsBySeller.into_storage_key()),
+            auctions: UnorderedMap::new(StorageKey::Auctions.into_storage_key()),
+            dutch_auctions: UnorderedMap::new(StorageKey::DutchAuctions.into_storage_key()),
+            offers: LookupMap::new(StorageKey::Offers.into_storage_key()),
+            offers_by_token: LookupMap::new(StorageKey::OffersByToken.into_storage_key()),
+            offers_by_buyer: LookupMap::new(StorageKey::OffersByBuyer.into_storage_key()),
+            collection_offers: UnorderedMap::new(StorageKey::CollectionOffers.into_storage_key()),
+            collection_offers_by_buyer: LookupMap::new(
+                StorageKey::CollectionOffersByBuyer.into_storage_key(),
+            ),
+            next_collection_offer_id: 0,
+            accepted_ft_contracts: UnorderedSet::new(
+                StorageKey::AcceptedFtContracts.into_storage_key(),
+            ),
+            ft_unpaid: LookupMap::new(StorageKey::FtUnpaid.into_storage_key()),
+            royalty_tokens_by_account: LookupMap::new(
+                StorageKey::RoyaltyTokensByAccount.into_storage_key(),
+            ),
+            earnings: LookupMap::new(StorageKey::Earnings.into_storage_key()),
+            sale_windows: LookupMap::new(StorageKey::SaleWindows.into_storage_key()),
+            sales: LookupMap::new(StorageKey::Sales.into_storage_key()),
+            volume_by_currency: UnorderedMap::new(StorageKey::VolumeByCurrency.into_storage_key()),
+            provenance_windows: LookupMap::new(StorageKey::ProvenanceWindows.into_storage_key()),
+            provenance: LookupMap::new(StorageKey::Provenance.into_storage_key()),
+            provenance_cap: 0,
+            snapshots: Vector::new(StorageKey::Snapshots.into_storage_key()),
+            snapshot_progress: LookupMap::new(StorageKey::SnapshotProgress.into_storage_key()),
+            snapshot_balances: LookupMap::new(StorageKey::SnapshotBalances.into_storage_key()),
+            snapshot_holders: LookupMap::new(StorageKey::SnapshotHolders.into_storage_key()),
+            royalty_policy: RoyaltyPolicy::default(),
+            governance_policy: GovernancePolicy::default(),
+            proposals: Vector::new(StorageKey::Proposals.into_storage_key()),
+            proposal_votes: LookupMap::new(StorageKey::ProposalVotes.into_storage_key()),
+            revenue_per_token: 0,
+            revenue_remainder: 0,
+            revenue_deposited: 0,
+            revenue_accounts: LookupMap::new(StorageKey::RevenueAccounts.into_storage_key()),
+            token_users: LookupMap::new(StorageKey::TokenUsers.into_storage_key()),
+            rental_listings: UnorderedMap::new(StorageKey::RentalListings.into_storage_key()),
+            stakes: LookupMap::new(StorageKey::Stakes.into_storage_key()),
+            stakes_by_owner: LookupMap::new(StorageKey::StakesByOwner.into_storage_key()),
+            staking_accounts: LookupMap::new(StorageKey::StakingAccounts.into_storage_key()),
+            staking_policy: StakingPolicy::default(),
+            staking_accumulator: 0,
+            staking_updated_at: env::block_timestamp(),
+            token_freezes: LookupMap::new(StorageKey::TokenFreezes.into_storage_key()),
+            account_freezes: LookupMap::new(StorageKey::AccountFreezes.into_storage_key()),
+            recoveries: LookupMap::new(StorageKey::Recoveries.into_storage_key()),
+            recovery_opt_outs: LookupSet::new(StorageKey::RecoveryOptOuts.into_storage_key()),
+            blocklist: UnorderedSet::new(StorageKey::Blocklist.into_storage_key()),
+            transfer_counts: LookupMap::new(StorageKey::TransferCounts.into_storage_key()),
+            series_by_token: LookupMap::new(StorageKey::SeriesByToken.into_storage_key()),
+        };
+        this.measure_storage_registration_bytes();
+        this
     }
 
     pub fn allow_minting_access(&mut self, account_id: AccountId) {
@@ -146,7 +411,14 @@ impl Contract {
             "Only owner can allow minting access",
         );
 
-        self.allow_list.insert(&account_id);
+        if self.allow_list.insert(&account_id) {
+            EventLogVariant::RoleGrant(vec![NftRoleLog {
+                role: "minter".to_string(),
+                account_id: account_id.to_string(),
+                sender_id: self.owner_id.to_string(),
+            }])
+            .emit();
+        }
     }
 
     pub fn revoke_minting_access(&mut self, account_id: AccountId) {
@@ -156,6 +428,27 @@ impl Contract {
             "Only owner can revoke minting access",
         );
 
-        self.allow_list.remove(&account_id);
+        if self.allow_list.remove(&account_id) {
+            EventLogVariant::RoleRevoke(vec![NftRoleLog {
+                role: "minter".to_string(),
+                account_id: account_id.to_string(),
+                sender_id: self.owner_id.to_string(),
+            }])
+            .emit();
+        }
+    }
+
+    pub fn set_max_approvals_per_token(&mut self, max_approvals_per_token: u32) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only owner can set the maximum approvals per token",
+        );
+        require!(
+            max_approvals_per_token > 0,
+            "cypher: Maximum approvals per token must be positive"
+        );
+
+        self.max_approvals_per_token = max_approvals_per_token;
     }
 }
\ No newline at end of file
@@ -0,0 +1,290 @@
+use crate::*;
+
+/// A token offered for sale at a fixed price through `nft_list`
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Listing {
+    pub token_id: TokenId,
+    pub seller_id: AccountId,
+    // price in yoctoNEAR, or in the smallest unit of `ft_contract_id`
+    pub price: U128,
+    // NEP-141 contract the price is paid in through `ft_transfer_call`, NEAR if unset
+    pub ft_contract_id: Option<AccountId>,
+}
+
+/// Logs a market event for `listing`
+pub(crate) fn emit_listing_event(variant: fn(Vec<NftListingLog>) -> EventLogVariant, listing: &Listing) {
+    variant(vec![NftListingLog {
+        seller_id: listing.seller_id.to_string(),
+        token_id: listing.token_id.clone(),
+        price: listing.price.0.to_string(),
+        ft_contract_id: listing.ft_contract_id.as_ref().map(|id| id.to_string()),
+    }])
+    .emit();
+}
+
+impl Contract {
+    /// Returns the listing of a token and panics unless `account_id` owns the token
+    fn expect_own_listing(&self, token_id: &TokenId, account_id: &AccountId) -> Listing {
+        let listing = self
+            .listings
+            .get(token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token is not listed"));
+        require!(
+            &listing.seller_id == account_id,
+            "cypher: Predecessor must be token owner"
+        );
+        listing
+    }
+
+    /// Stores a listing and indexes it by seller, charging the seller for the storage
+    fn internal_add_listing(&mut self, listing: &Listing) {
+        let initial_storage_usage = env::storage_usage();
+        self.listings.insert(&listing.token_id, listing);
+        internal_add_to_token_set(
+            &mut self.listings_by_seller,
+            &listing.seller_id,
+            &listing.token_id,
+            |account_hash| StorageKey::ListingsBySellerInner { account_hash },
+        );
+        self.internal_storage_debit(
+            &listing.seller_id,
+            env::storage_usage() - initial_storage_usage,
+        );
+    }
+
+    /// Removes the listing of a token, crediting the storage back to the seller.
+    /// Called on every change of ownership so that listings never outlive their seller's ownership.
+    pub(crate) fn internal_remove_listing(&mut self, token_id: &TokenId) -> Option<Listing> {
+        let initial_storage_usage = env::storage_usage();
+        let listing = self.listings.remove(token_id)?;
+        internal_remove_from_token_set(&mut self.listings_by_seller, &listing.seller_id, token_id);
+        self.internal_storage_credit(
+            &listing.seller_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
+        Some(listing)
+    }
+
+    /// Sells a listed token to `buyer_id` for `amount` paid in `ft_contract_id`, or NEAR if
+    /// unset. Returns the listing and the split of its price, for the caller to pay out in
+    /// the same currency and to refund the rest of `amount`.
+    pub(crate) fn internal_buy_listing(
+        &mut self,
+        buyer_id: &AccountId,
+        token_id: &TokenId,
+        ft_contract_id: Option<&AccountId>,
+        amount: Balance,
+    ) -> (Listing, Payout) {
+        let listing = self
+            .listings
+            .get(token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token is not listed"));
+        require!(
+            listing.ft_contract_id.as_ref() == ft_contract_id,
+            "cypher: Token is listed in another currency"
+        );
+        let price = listing.price.0;
+        require!(
+            amount >= price,
+            format!("cypher: Must pay at least {}", price)
+        );
+
+        // split the price before the token changes hands, the payout depends on the seller
+        let payout = self.internal_payout(&listing.seller_id, token_id, price, u32::MAX);
+        let (_, approved_account_ids) = self.internal_transfer(
+            &listing.seller_id,
+            buyer_id,
+            token_id,
+            None,
+            &Some("nft_buy".to_string()),
+        );
+
+        // credit the seller for the storage of the cleared approvals
+        if let Some(approved_account_ids) = approved_account_ids {
+            self.internal_storage_credit(
+                &listing.seller_id,
+                bytes_for_approved_account_ids_raw(&approved_account_ids),
+            );
+        }
+        self.internal_record_sale(
+            token_id,
+            &listing.seller_id,
+            buyer_id,
+            price,
+            ft_contract_id,
+            &payout,
+        );
+        emit_listing_event(EventLogVariant::NftSale, &listing);
+        (listing, payout)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// List a token you own for sale at `price` yoctoNEAR, or at `price` of an accepted
+    /// fungible token if `ft_contract_id` is set
+    #[payable]
+    pub fn nft_list(
+        &mut self,
+        token_id: TokenId,
+        price: U128,
+        ft_contract_id: Option<AccountId>,
+    ) {
+        assert_one_yocto();
+        let seller_id = env::predecessor_account_id();
+        let owner_id = self
+            .owner_by_id
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token not found"));
+        require!(
+            seller_id == owner_id,
+            "cypher: Predecessor must be token owner"
+        );
+        require!(price.0 > 0, "cypher: Price must be positive");
+        self.internal_assert_accepted_ft(ft_contract_id.as_ref());
+        self.internal_assert_token_unlocked(&token_id);
+        require!(
+            self.listings.get(&token_id).is_none(),
+            "cypher: Token is already listed, use nft_update_price"
+        );
+
+        let listing = Listing {
+            token_id,
+            seller_id,
+            price,
+            ft_contract_id,
+        };
+        self.internal_add_listing(&listing);
+        emit_listing_event(EventLogVariant::NftList, &listing);
+    }
+
+    /// Take a token you listed off the market
+    #[payable]
+    pub fn nft_delist(&mut self, token_id: TokenId) {
+        assert_one_yocto();
+        let listing = self.expect_own_listing(&token_id, &env::predecessor_account_id());
+        self.internal_remove_listing(&token_id);
+        emit_listing_event(EventLogVariant::NftDelist, &listing);
+    }
+
+    /// Change the price of a token you listed, in the currency it was listed in
+    #[payable]
+    pub fn nft_update_price(&mut self, token_id: TokenId, price: U128) {
+        assert_one_yocto();
+        let mut listing = self.expect_own_listing(&token_id, &env::predecessor_account_id());
+        require!(price.0 > 0, "cypher: Price must be positive");
+
+        listing.price = price;
+        self.listings.insert(&token_id, &listing);
+        emit_listing_event(EventLogVariant::NftUpdatePrice, &listing);
+    }
+
+    /// Buy a listed token with the attached deposit. The price is split between the royalty
+    /// recipients and the seller as in `nft_payout`, any overpayment is refunded.
+    #[payable]
+    pub fn nft_buy(&mut self, token_id: TokenId) {
+        let buyer_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        let (listing, payout) = self.internal_buy_listing(&buyer_id, &token_id, None, deposit);
+
+        distribute_payout(payout);
+        if deposit > listing.price.0 {
+            Promise::new(buyer_id).transfer(deposit - listing.price.0);
+        }
+    }
+
+    /// get the listing of a token, if it is for sale
+    pub fn nft_listing(&self, token_id: TokenId) -> Option<Listing> {
+        self.listings.get(&token_id)
+    }
+
+    /// get the number of tokens for sale
+    pub fn nft_listings_supply(&self) -> U128 {
+        (self.listings.len() as u128).into()
+    }
+
+    /// Query for the tokens for sale using pagination
+    pub fn nft_listings(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Listing> {
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        require!(
+            (self.listings.len() as u128) >= start_index,
+            "cypher: Out of bounds, please use a smaller from_index."
+        );
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+
+        self.listings
+            .values()
+            .skip(start_index as usize)
+            .take(limit)
+            .collect()
+    }
+
+    /// Query for the tokens a seller has for sale using pagination
+    pub fn nft_listings_by_seller(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Listing> {
+        let token_set = if let Some(token_set) = self.listings_by_seller.get(&account_id) {
+            token_set
+        } else {
+            return vec![];
+        };
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+        token_set
+            .iter()
+            .skip(start_index as usize)
+            .take(limit)
+            .filter_map(|token_id| self.listings.get(&token_id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn list(contract: &mut Contract, price: Balance) {
+        call(&account("alice.near"), 1, 0);
+        contract.nft_list("1".to_string(), price.into(), None);
+    }
+
+    #[test]
+    fn buying_moves_the_token_and_clears_the_listing() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        list(&mut contract, NEAR);
+
+        call(&account("bob.near"), 2 * NEAR, 1);
+        contract.nft_buy("1".to_string());
+        assert_eq!(owner_of(&contract, "1"), account("bob.near"));
+        assert!(contract.nft_listing("1".to_string()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Must pay at least 1000000000000000000000000")]
+    fn buying_below_the_price_fails() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        list(&mut contract, NEAR);
+
+        call(&account("bob.near"), NEAR - 1, 1);
+        contract.nft_buy("1".to_string());
+    }
+
+    #[test]
+    fn listing_does_not_survive_a_transfer() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        list(&mut contract, NEAR);
+
+        transfer(&mut contract, "1", "bob.near", 1);
+        assert!(contract.nft_listing("1".to_string()).is_none());
+    }
+}
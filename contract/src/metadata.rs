@@ -1,5 +1,10 @@
 use crate::*;
 pub type TokenId = String;
+//a series groups tokens minted as editions of the same work
+pub type SeriesId = String;
+/// Borsh-serialized `HashMap<AccountId, u64>` of a token's approvals, kept raw so that
+/// moving them around on transfer doesn't cost more with every approval
+pub type RawApprovals = Vec<u8>;
 //defines the payout type we'll be returning as a part of the royalty standards.
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
@@ -22,6 +27,9 @@ pub struct TokenJson {
     pub metadata: Option<TokenMetadata>,
     pub royalty: Option<HashMap<AccountId, u32>>,
     pub approved_account_ids: Option<HashMap<AccountId, u64>>,
+    // set if the token or its owner is frozen
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub freeze: Option<TokenFreeze>,
 }
 
 #[near_bindgen]
@@ -2,6 +2,8 @@ use crate::*;
 
 #[near_bindgen]
 impl Contract {
+    /// Mint a token to `receiver_id`. Only the contract owner and accounts with minting access
+    /// can mint, so holding a token, or one of a series, means it was issued by the collection.
     #[payable]
     pub fn nft_mint(
         &mut self,
@@ -9,16 +11,35 @@ impl Contract {
         token_metadata: TokenMetadata,
         receiver_id: AccountId,
         perpetual_royalties: Option<HashMap<AccountId, u32>>,
+        series_id: Option<SeriesId>,
     ) {
-        if self.owner_by_id.get(&token_id).is_some() {
+        let minter_id = env::predecessor_account_id();
+        require!(
+            self.internal_is_minter(&minter_id),
+            "cypher: Predecessor is not allowed to mint"
+        );
+        if self.owner_by_id.get(&token_id).is_some() || self.internal_is_in_dutch_auction(&token_id) {
             env::panic_str("cypher: token_id must be unique");
         }
+        let initial_storage_usage = env::storage_usage();
         // abstracts the minting procedure
         self.internal_mint(
             receiver_id,
-            token_id,
+            token_id.clone(),
             perpetual_royalties,
             token_metadata,
         );
+        if let Some(series_id) = series_id {
+            self.series_by_token.insert(&token_id, &series_id);
+        }
+
+        // the minter pays for the new token out of their storage balance, topped up by the deposit
+        self.internal_storage_deposit(&minter_id, env::attached_deposit());
+        self.internal_storage_debit(&minter_id, env::storage_usage() - initial_storage_usage);
+    }
+
+    /// get the series a token was minted in, if any
+    pub fn nft_series_of(&self, token_id: TokenId) -> Option<SeriesId> {
+        self.series_by_token.get(&token_id)
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,4 @@
 use crate::*;
-use near_contract_standards::non_fungible_token::events::NftTransfer;
 use near_contract_standards::non_fungible_token::Token;
 use std::collections::HashMap;
 
@@ -57,7 +56,6 @@ pub trait NonFungibleTokenResolver {
         previous_owner_id: AccountId,
         receiver_id: AccountId,
         token_id: TokenId,
-        approvals: Option<HashMap<AccountId, u64>>,
     ) -> bool;
 }
 
@@ -85,7 +83,16 @@ impl NonFungibleTokenCore for Contract {
         assert_one_yocto();
         let sender_id = env::predecessor_account_id();
 
-        self.internal_transfer(&sender_id, &receiver_id, &token_id, approval_id, &memo);
+        let (previous_owner_id, approved_account_ids) =
+            self.internal_transfer(&sender_id, &receiver_id, &token_id, approval_id, &memo);
+
+        // credit the previous owner for the storage of the cleared approvals
+        if let Some(approved_account_ids) = approved_account_ids {
+            self.internal_storage_credit(
+                &previous_owner_id,
+                bytes_for_approved_account_ids_raw(&approved_account_ids),
+            );
+        }
     }
 
     #[payable]
@@ -106,6 +113,11 @@ impl NonFungibleTokenCore for Contract {
         let (old_owner, old_approvals) =
             self.internal_transfer(&sender_id, &receiver_id, &token_id, approval_id, &memo);
 
+        // keep the previous owner's approvals aside until we know whether the token comes back
+        if let Some(old_approvals) = old_approvals {
+            self.internal_park_approvals(&token_id, &old_owner, &old_approvals);
+        }
+
         // Initiating receiver's call and the callback
         ext_nft_receiver::ext(receiver_id.clone())
             .with_static_gas(env::prepaid_gas() - GAS_FOR_NFT_TRANSFER_CALL)
@@ -113,7 +125,7 @@ impl NonFungibleTokenCore for Contract {
             .then(
                 ext_nft_resolver::ext(env::current_account_id())
                     .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
-                    .nft_resolve_transfer(old_owner, receiver_id, token_id, old_approvals),
+                    .nft_resolve_transfer(old_owner, receiver_id, token_id),
             )
             .into()
     }
@@ -146,7 +158,6 @@ impl NonFungibleTokenResolver for Contract {
         previous_owner_id: AccountId,
         receiver_id: AccountId,
         token_id: TokenId,
-        approved_account_ids: Option<HashMap<AccountId, u64>>,
     ) -> bool {
         // Get whether token should be returned
         let must_revert = match env::promise_result(0) {
@@ -161,49 +172,129 @@ impl NonFungibleTokenResolver for Contract {
             PromiseResult::Failed => true,
         };
 
-        // if call succeeded, return early
-        if !must_revert {
+        if self.internal_resolve_transfer(&previous_owner_id, &receiver_id, &token_id, must_revert) {
             return true;
         }
 
-        // OTHERWISE, try to set owner back to previous_owner_id and restore approved_account_ids
+        let approve_logs = self.internal_approve_logs(&previous_owner_id, &token_id);
+        emit_nft_transfers(&[(receiver_id, previous_owner_id, None, token_id)], None);
+        if !approve_logs.is_empty() {
+            EventLogVariant::NftApprove(approve_logs).emit();
+        }
+        false
+    }
+}
 
-        // Check that receiver didn't already transfer it away or burn it.
-        if let Some(current_owner) = self.owner_by_id.get(&token_id) {
-            if current_owner != receiver_id {
-                // The token is not owned by the receiver anymore. Can't return it.
-                return true;
-            }
-        } else {
-            // The token was burned and doesn't exist anymore.
-            // Refund storage cost for storing approvals to original owner and return early.
-            if let Some(approved_account_ids) = approved_account_ids {
-                refund_approved_account_ids(previous_owner_id, &approved_account_ids);
-            }
-            return true;
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
 
-        self.internal_transfer_unguarded(&token_id, &receiver_id, &previous_owner_id);
+    fn used_bytes(contract: &Contract, account_id: &str) -> u64 {
+        contract
+            .internal_storage_account(&account(account_id))
+            .used_bytes
+    }
 
-        // If using Approval Management extension,
-        // 1. revert any approvals receiver already set, refunding storage costs
-        // 2. reset approvals to what previous owner had set before call to nft_transfer_call
-        if let Some(by_id) = &mut self.approvals_by_id {
-            if let Some(receiver_approvals) = by_id.get(&token_id) {
-                refund_approved_account_ids(receiver_id.clone(), &receiver_approvals);
-            }
-            if let Some(previous_owner_approvals) = approved_account_ids {
-                by_id.insert(&token_id, &previous_owner_approvals);
-            }
-        }
-        NftTransfer {
-            old_owner_id: &receiver_id,
-            new_owner_id: &previous_owner_id,
-            token_ids: &[&token_id],
-            authorized_id: None,
-            memo: None,
+    /// Mints "1" to alice, approves market and other, and sends the token to bob with a transfer call
+    fn transfer_call_with_approvals(contract: &mut Contract) {
+        mint(contract, "1", "alice.near");
+        for account_id in ["market.near", "other.near"] {
+            call(&account("alice.near"), NEAR / 100, 0);
+            contract.nft_approve("1".to_string(), account(account_id), None, None);
         }
-        .emit();
-        false
+        call(&account("alice.near"), 1, 0);
+        let _ = contract.nft_transfer_call(
+            account("bob.near"),
+            "1".to_string(),
+            None,
+            None,
+            "".to_string(),
+        );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn transfer_clears_approvals_and_credits_their_storage() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        call(&account("alice.near"), NEAR / 100, 0);
+        contract.nft_approve("1".to_string(), account("market.near"), None, None);
+        assert!(used_bytes(&contract, "alice.near") > contract.storage_registration_bytes);
+
+        transfer(&mut contract, "1", "bob.near", 0);
+
+        let token = contract.nft_token("1".to_string()).unwrap();
+        assert!(token.approved_account_ids.unwrap().is_empty());
+        assert_eq!(
+            used_bytes(&contract, "alice.near"),
+            contract.storage_registration_bytes
+        );
+    }
+
+    #[test]
+    fn refused_transfer_call_restores_the_approvals() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        transfer_call_with_approvals(&mut contract);
+        let parked_bytes = used_bytes(&contract, "alice.near");
+        assert!(contract
+            .nft_token("1".to_string())
+            .unwrap()
+            .approved_account_ids
+            .unwrap()
+            .is_empty());
+
+        callback(vec![PromiseResult::Successful(b"true".to_vec())]);
+        let kept = contract.nft_resolve_transfer(
+            account("alice.near"),
+            account("bob.near"),
+            "1".to_string(),
+        );
+
+        assert!(!kept);
+        let token = contract.nft_token("1".to_string()).unwrap();
+        assert_eq!(token.owner_id, account("alice.near"));
+        let approvals = token.approved_account_ids.unwrap();
+        assert_eq!(approvals.len(), 2);
+        assert_eq!(approvals[&account("market.near")], 1);
+        assert_eq!(approvals[&account("other.near")], 2);
+        // the transfer back clears the approvals for indexers, the approve logs that follow put
+        // them back
+        assert_eq!(
+            near_sdk::test_utils::get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_transfer","data":[{"old_owner_id":"bob.near","new_owner_id":"alice.near","token_ids":["1"]}]}"#,
+                r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_approve","data":[{"owner_id":"alice.near","token_id":"1","account_id":"market.near","approval_id":1},{"owner_id":"alice.near","token_id":"1","account_id":"other.near","approval_id":2}]}"#,
+            ]
+        );
+        // the approvals were never released, so their storage is still charged to alice
+        assert!(used_bytes(&contract, "alice.near") >= parked_bytes);
+        assert!(contract
+            .pending_approvals
+            .get(&("1".to_string(), account("alice.near")))
+            .is_none());
+    }
+
+    #[test]
+    fn accepted_transfer_call_credits_the_parked_approvals() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        transfer_call_with_approvals(&mut contract);
+
+        callback(vec![PromiseResult::Successful(b"false".to_vec())]);
+        let kept = contract.nft_resolve_transfer(
+            account("alice.near"),
+            account("bob.near"),
+            "1".to_string(),
+        );
+
+        assert!(kept);
+        assert_eq!(owner_of(&contract, "1"), account("bob.near"));
+        assert_eq!(
+            used_bytes(&contract, "alice.near"),
+            contract.storage_registration_bytes
+        );
+        assert!(contract
+            .pending_approvals
+            .get(&("1".to_string(), account("alice.near")))
+            .is_none());
+    }
+}
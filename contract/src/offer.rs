@@ -0,0 +1,331 @@
+use crate::*;
+use near_sdk::json_types::U64;
+
+/// NEAR or fungible tokens escrowed by `buyer_id` to buy a token from its owner at the time of the offer
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Offer {
+    pub token_id: TokenId,
+    pub buyer_id: AccountId,
+    pub owner_id: AccountId,
+    // times the token had changed hands when the offer was made, the offer goes stale once it
+    // changes hands again, even back to `owner_id`
+    pub transfer_count: U64,
+    // escrowed yoctoNEAR, or units of `ft_contract_id`
+    pub amount: U128,
+    // nanoseconds since the epoch after which the offer can't be accepted
+    pub expires_at: Option<U64>,
+    // NEP-141 contract the offer is escrowed in, NEAR if unset
+    pub ft_contract_id: Option<AccountId>,
+}
+
+impl Offer {
+    pub fn is_expired(&self) -> bool {
+        matches!(self.expires_at, Some(expires_at) if env::block_timestamp() >= expires_at.0)
+    }
+}
+
+/// Logs an offer event for `offer`
+fn emit_offer_event(variant: fn(Vec<NftOfferLog>) -> EventLogVariant, offer: &Offer) {
+    variant(vec![NftOfferLog {
+        token_id: offer.token_id.clone(),
+        buyer_id: offer.buyer_id.to_string(),
+        owner_id: offer.owner_id.to_string(),
+        amount: offer.amount.0.to_string(),
+        expires_at: offer.expires_at.map(|expires_at| expires_at.0.to_string()),
+        ft_contract_id: offer.ft_contract_id.as_ref().map(|id| id.to_string()),
+    }])
+    .emit();
+}
+
+impl Contract {
+    /// Returns true if the offer can't be accepted anymore
+    fn is_stale_offer(&self, offer: &Offer) -> bool {
+        offer.is_expired()
+            || self.internal_transfer_count(&offer.token_id) != offer.transfer_count.0
+    }
+
+    /// Stores an offer and its indexes, charging the buyer for the storage
+    fn internal_add_offer(&mut self, offer: &Offer) {
+        let initial_storage_usage = env::storage_usage();
+        self.offers
+            .insert(&(offer.token_id.clone(), offer.buyer_id.clone()), offer);
+        let mut buyers = self
+            .offers_by_token
+            .get(&offer.token_id)
+            .unwrap_or_else(|| {
+                UnorderedSet::new(StorageKey::OffersByTokenInner {
+                    token_hash: env::sha256(offer.token_id.as_bytes()),
+                })
+            });
+        buyers.insert(&offer.buyer_id);
+        self.offers_by_token.insert(&offer.token_id, &buyers);
+        internal_add_to_token_set(
+            &mut self.offers_by_buyer,
+            &offer.buyer_id,
+            &offer.token_id,
+            |account_hash| StorageKey::OffersByBuyerInner { account_hash },
+        );
+        self.internal_storage_debit(
+            &offer.buyer_id,
+            env::storage_usage() - initial_storage_usage,
+        );
+    }
+
+    /// Removes an offer and its indexes, crediting the storage back to the buyer.
+    /// The escrowed amount is left to the caller.
+    fn internal_remove_offer(&mut self, token_id: &TokenId, buyer_id: &AccountId) -> Option<Offer> {
+        let initial_storage_usage = env::storage_usage();
+        let offer = self.offers.remove(&(token_id.clone(), buyer_id.clone()))?;
+        if let Some(mut buyers) = self.offers_by_token.get(token_id) {
+            buyers.remove(buyer_id);
+            if buyers.is_empty() {
+                self.offers_by_token.remove(token_id);
+            } else {
+                self.offers_by_token.insert(token_id, &buyers);
+            }
+        }
+        internal_remove_from_token_set(&mut self.offers_by_buyer, buyer_id, token_id);
+        self.internal_storage_credit(
+            buyer_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
+        Some(offer)
+    }
+
+    /// Escrows an offer of `amount` in `ft_contract_id`, or NEAR if unset, for a token.
+    /// A previous offer of the buyer on the token is replaced and refunded in its own currency.
+    pub(crate) fn internal_make_offer(
+        &mut self,
+        buyer_id: &AccountId,
+        token_id: TokenId,
+        ft_contract_id: Option<&AccountId>,
+        amount: Balance,
+        expiry: Option<U64>,
+    ) {
+        require!(amount > 0, "cypher: Must attach the amount offered");
+        let owner_id = self
+            .owner_by_id
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token not found"));
+        require!(
+            buyer_id != &owner_id,
+            "cypher: Cannot make an offer on your own token"
+        );
+        require!(
+            !matches!(expiry, Some(expiry) if expiry.0 <= env::block_timestamp()),
+            "cypher: Expiry must be in the future"
+        );
+
+        if let Some(replaced) = self.internal_remove_offer(&token_id, buyer_id) {
+            self.internal_pay(
+                replaced.ft_contract_id.as_ref(),
+                buyer_id,
+                replaced.amount.0,
+                buyer_id,
+            );
+        }
+        let offer = Offer {
+            transfer_count: self.internal_transfer_count(&token_id).into(),
+            token_id,
+            buyer_id: buyer_id.clone(),
+            owner_id,
+            amount: amount.into(),
+            expires_at: expiry,
+            ft_contract_id: ft_contract_id.cloned(),
+        };
+        self.internal_add_offer(&offer);
+        emit_offer_event(EventLogVariant::NftOfferMake, &offer);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Offer the attached deposit for a token, listed or not. The deposit is escrowed until the
+    /// offer is accepted or cancelled. Making a new offer on the same token replaces the old one.
+    #[payable]
+    pub fn nft_make_offer(&mut self, token_id: TokenId, expiry: Option<U64>) {
+        self.internal_make_offer(
+            &env::predecessor_account_id(),
+            token_id,
+            None,
+            env::attached_deposit(),
+            expiry,
+        );
+    }
+
+    /// Cancel an offer and get the escrow back. The buyer can cancel at any time, anyone can
+    /// clear an offer that has expired or whose token has changed hands.
+    #[payable]
+    pub fn nft_cancel_offer(&mut self, token_id: TokenId, buyer_id: Option<AccountId>) {
+        assert_one_yocto();
+        let predecessor_account_id = env::predecessor_account_id();
+        let buyer_id = buyer_id.unwrap_or_else(|| predecessor_account_id.clone());
+        let offer = self
+            .offers
+            .get(&(token_id.clone(), buyer_id.clone()))
+            .unwrap_or_else(|| env::panic_str("cypher: Offer not found"));
+        require!(
+            predecessor_account_id == buyer_id || self.is_stale_offer(&offer),
+            "cypher: Only the buyer can cancel a live offer"
+        );
+
+        self.internal_remove_offer(&token_id, &buyer_id);
+        self.internal_pay(
+            offer.ft_contract_id.as_ref(),
+            &buyer_id,
+            offer.amount.0,
+            &buyer_id,
+        );
+        emit_offer_event(EventLogVariant::NftOfferCancel, &offer);
+    }
+
+    /// Accept an offer on a token you own. The token goes to the buyer and the escrow is split
+    /// between the royalty recipients and you as in `nft_payout`.
+    #[payable]
+    pub fn nft_accept_offer(&mut self, token_id: TokenId, buyer_id: AccountId) {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+        let offer = self
+            .offers
+            .get(&(token_id.clone(), buyer_id.clone()))
+            .unwrap_or_else(|| env::panic_str("cypher: Offer not found"));
+        require!(
+            offer.owner_id == owner_id,
+            "cypher: Predecessor must be the owner the offer was made to"
+        );
+        require!(!self.is_stale_offer(&offer), "cypher: The offer is stale");
+
+        self.internal_remove_offer(&token_id, &buyer_id);
+        let payout = self.internal_payout(&owner_id, &token_id, offer.amount.0, u32::MAX);
+        let (_, approved_account_ids) = self.internal_transfer(
+            &owner_id,
+            &buyer_id,
+            &token_id,
+            None,
+            &Some("nft_accept_offer".to_string()),
+        );
+
+        // credit the seller for the storage of the cleared approvals
+        if let Some(approved_account_ids) = approved_account_ids {
+            self.internal_storage_credit(
+                &owner_id,
+                bytes_for_approved_account_ids_raw(&approved_account_ids),
+            );
+        }
+        self.internal_record_sale(
+            &token_id,
+            &owner_id,
+            &buyer_id,
+            offer.amount.0,
+            offer.ft_contract_id.as_ref(),
+            &payout,
+        );
+        self.internal_distribute_payout(offer.ft_contract_id.as_ref(), payout, &buyer_id);
+        emit_offer_event(EventLogVariant::NftOfferAccept, &offer);
+    }
+
+    /// get the offer of `buyer_id` on a token
+    pub fn nft_offer(&self, token_id: TokenId, buyer_id: AccountId) -> Option<Offer> {
+        self.offers.get(&(token_id, buyer_id))
+    }
+
+    /// Query for the offers on a token using pagination, stale offers included
+    pub fn nft_offers_for_token(
+        &self,
+        token_id: TokenId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Offer> {
+        let buyers = if let Some(buyers) = self.offers_by_token.get(&token_id) {
+            buyers
+        } else {
+            return vec![];
+        };
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+        buyers
+            .iter()
+            .skip(start_index as usize)
+            .take(limit)
+            .filter_map(|buyer_id| self.offers.get(&(token_id.clone(), buyer_id)))
+            .collect()
+    }
+
+    /// Query for the offers made by an account using pagination, stale offers included
+    pub fn nft_offers_by_account(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Offer> {
+        let token_set = if let Some(token_set) = self.offers_by_buyer.get(&account_id) {
+            token_set
+        } else {
+            return vec![];
+        };
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+        token_set
+            .iter()
+            .skip(start_index as usize)
+            .take(limit)
+            .filter_map(|token_id| self.offers.get(&(token_id, account_id.clone())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+
+    #[test]
+    fn offer_goes_stale_when_the_token_comes_back() {
+        let mut contract = setup(&["alice.near", "bob.near", "carol.near"]);
+        mint(&mut contract, "1", "alice.near");
+
+        call(&account("carol.near"), 5 * NEAR, 0);
+        contract.nft_make_offer("1".to_string(), None);
+        let offer = contract
+            .nft_offer("1".to_string(), account("carol.near"))
+            .unwrap();
+        assert!(!contract.is_stale_offer(&offer));
+
+        transfer(&mut contract, "1", "bob.near", 1);
+        assert!(contract.is_stale_offer(&offer));
+        transfer(&mut contract, "1", "alice.near", 2);
+        assert_eq!(owner_of(&contract, "1"), account("alice.near"));
+        assert!(contract.is_stale_offer(&offer));
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: The offer is stale")]
+    fn stale_offer_cannot_be_accepted() {
+        let mut contract = setup(&["alice.near", "bob.near", "carol.near"]);
+        mint(&mut contract, "1", "alice.near");
+        call(&account("carol.near"), 5 * NEAR, 0);
+        contract.nft_make_offer("1".to_string(), None);
+        transfer(&mut contract, "1", "bob.near", 1);
+        transfer(&mut contract, "1", "alice.near", 2);
+
+        call(&account("alice.near"), 1, 3);
+        contract.nft_accept_offer("1".to_string(), account("carol.near"));
+    }
+
+    #[test]
+    fn accepting_an_offer_moves_the_token() {
+        let mut contract = setup(&["alice.near", "carol.near"]);
+        mint(&mut contract, "1", "alice.near");
+        call(&account("carol.near"), 5 * NEAR, 0);
+        contract.nft_make_offer("1".to_string(), None);
+
+        call(&account("alice.near"), 1, 1);
+        contract.nft_accept_offer("1".to_string(), account("carol.near"));
+        assert_eq!(owner_of(&contract, "1"), account("carol.near"));
+        assert!(contract
+            .nft_offer("1".to_string(), account("carol.near"))
+            .is_none());
+    }
+}
@@ -0,0 +1,239 @@
+use crate::*;
+use near_sdk::json_types::U64;
+
+/// Entries evicted per append at most, so lowering the cap shrinks long histories gradually
+/// instead of in one unbounded call
+const MAX_EVICTIONS_PER_APPEND: u64 = 2;
+
+/// How a token came to its owner
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProvenanceKind {
+    Mint,
+    Transfer,
+    // returned to its previous owner because the receiver of `nft_transfer_call` refused it
+    TransferCallRevert,
+    // returned by the contract owner after it was frozen, see `nft_recover_token`
+    Recovery,
+    Sale {
+        // in yoctoNEAR, or in the smallest unit of `ft_contract_id`
+        price: U128,
+        ft_contract_id: Option<AccountId>,
+    },
+}
+
+/// An owner in the history of a token
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProvenanceEntry {
+    // position in the full history, the mint is 0. Gaps at the start mean older entries were
+    // dropped to stay within the retention cap.
+    pub index: U64,
+    pub owner_id: AccountId,
+    // nanoseconds since the epoch
+    pub timestamp: U64,
+    pub block_height: U64,
+    pub kind: ProvenanceKind,
+}
+
+impl Contract {
+    /// Appends a new owner to the history of a token, dropping the oldest entries beyond the
+    /// retention cap. Does nothing while the cap is 0. Storage is paid for by the caller.
+    pub(crate) fn internal_record_provenance(
+        &mut self,
+        token_id: &TokenId,
+        owner_id: &AccountId,
+        kind: ProvenanceKind,
+    ) {
+        if self.provenance_cap == 0 {
+            return;
+        }
+        let (mut first, next) = self.provenance_windows.get(token_id).unwrap_or((0, 0));
+        self.provenance.insert(
+            &(token_id.clone(), next),
+            &ProvenanceEntry {
+                index: next.into(),
+                owner_id: owner_id.clone(),
+                timestamp: env::block_timestamp().into(),
+                block_height: env::block_height().into(),
+                kind,
+            },
+        );
+        let next = next + 1;
+
+        let mut evicted = 0;
+        while next - first > self.provenance_cap && evicted < MAX_EVICTIONS_PER_APPEND {
+            self.provenance.remove(&(token_id.clone(), first));
+            first += 1;
+            evicted += 1;
+        }
+        self.provenance_windows.insert(token_id, &(first, next));
+    }
+
+    /// Marks the latest entry in the history of a token as a sale, if it is `buyer_id` taking
+    /// ownership. Sales record the price after the token has changed hands.
+    pub(crate) fn internal_mark_provenance_sale(
+        &mut self,
+        token_id: &TokenId,
+        buyer_id: &AccountId,
+        price: Balance,
+        ft_contract_id: Option<&AccountId>,
+    ) {
+        let (first, next) = match self.provenance_windows.get(token_id) {
+            Some(window) => window,
+            None => return,
+        };
+        if next == first {
+            return;
+        }
+        let key = (token_id.clone(), next - 1);
+        if let Some(mut entry) = self.provenance.get(&key) {
+            if &entry.owner_id == buyer_id {
+                entry.kind = ProvenanceKind::Sale {
+                    price: price.into(),
+                    ft_contract_id: ft_contract_id.cloned(),
+                };
+                self.provenance.insert(&key, &entry);
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Set how many owners the history of each token keeps, 0 stops recording new ones.
+    /// Histories longer than a lowered cap are trimmed as their tokens change hands.
+    pub fn set_provenance_cap(&mut self, provenance_cap: u64) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only owner can set the provenance cap",
+        );
+
+        self.provenance_cap = provenance_cap;
+    }
+
+    /// get how many owners the history of each token keeps
+    pub fn provenance_cap(&self) -> U64 {
+        self.provenance_cap.into()
+    }
+
+    /// Query for the owners of a token, oldest first, using pagination.
+    /// `from_index` counts from the oldest entry still kept.
+    pub fn nft_token_history(
+        &self,
+        token_id: TokenId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<ProvenanceEntry> {
+        let (first, next) = if let Some(window) = self.provenance_windows.get(&token_id) {
+            window
+        } else {
+            return vec![];
+        };
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        let limit = limit.unwrap_or(u64::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+
+        let start = first.saturating_add(start_index.min(u64::MAX as u128) as u64);
+        let end = start.saturating_add(limit).min(next);
+        (start..end)
+            .filter_map(|index| self.provenance.get(&(token_id.clone(), index)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn set_cap(contract: &mut Contract, provenance_cap: u64) {
+        call(&owner(), 0, 0);
+        contract.set_provenance_cap(provenance_cap);
+    }
+
+    /// Sends "1" back and forth between alice and bob `count` times
+    fn pass_around(contract: &mut Contract, count: usize) {
+        for i in 0..count {
+            let receiver_id = if owner_of(contract, "1") == account("alice.near") {
+                "bob.near"
+            } else {
+                "alice.near"
+            };
+            transfer(contract, "1", receiver_id, i as u64 + 1);
+        }
+    }
+
+    fn history(contract: &Contract) -> Vec<u64> {
+        contract
+            .nft_token_history("1".to_string(), None, None)
+            .into_iter()
+            .map(|entry| entry.index.0)
+            .collect()
+    }
+
+    #[test]
+    fn nothing_is_recorded_while_the_cap_is_zero() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        assert_eq!(contract.provenance_cap().0, 0);
+        mint(&mut contract, "1", "alice.near");
+        pass_around(&mut contract, 2);
+        assert!(history(&contract).is_empty());
+    }
+
+    #[test]
+    fn history_keeps_the_latest_owners_up_to_the_cap() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        set_cap(&mut contract, 3);
+        mint(&mut contract, "1", "alice.near");
+        pass_around(&mut contract, 4);
+
+        let entries = contract.nft_token_history("1".to_string(), None, None);
+        assert_eq!(history(&contract), vec![2, 3, 4]);
+        assert_eq!(entries[0].owner_id, account("alice.near"));
+        assert_eq!(entries[1].owner_id, account("bob.near"));
+        assert_eq!(entries[2].kind, ProvenanceKind::Transfer);
+        // the oldest entries are gone from storage, not just from the view
+        assert!(contract.provenance.get(&("1".to_string(), 1)).is_none());
+
+        let page = contract.nft_token_history("1".to_string(), Some(U128(1)), Some(1));
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].index.0, 3);
+    }
+
+    #[test]
+    fn lowering_the_cap_trims_two_entries_per_append() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        set_cap(&mut contract, 10);
+        mint(&mut contract, "1", "alice.near");
+        pass_around(&mut contract, 7);
+        assert_eq!(history(&contract), (0..8).collect::<Vec<u64>>());
+
+        set_cap(&mut contract, 2);
+        // nothing is trimmed until the token changes hands
+        assert_eq!(history(&contract).len(), 8);
+        let mut lengths = Vec::new();
+        for _ in 0..7 {
+            pass_around(&mut contract, 1);
+            lengths.push(history(&contract).len());
+        }
+
+        // each append adds one entry and evicts at most two, oldest first
+        assert_eq!(lengths, vec![7, 6, 5, 4, 3, 2, 2]);
+        assert_eq!(history(&contract), vec![13, 14]);
+        assert!((0..13).all(|index| contract.provenance.get(&("1".to_string(), index)).is_none()));
+    }
+
+    #[test]
+    fn raising_the_cap_keeps_what_is_left() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        set_cap(&mut contract, 2);
+        mint(&mut contract, "1", "alice.near");
+        pass_around(&mut contract, 3);
+        set_cap(&mut contract, 5);
+        pass_around(&mut contract, 2);
+        assert_eq!(history(&contract), vec![2, 3, 4, 5]);
+    }
+}
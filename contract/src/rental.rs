@@ -0,0 +1,398 @@
+use crate::*;
+use near_sdk::json_types::U64;
+
+/// Length of a rental day in nanoseconds
+const RENTAL_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// Longest rental a listing can offer, in days
+pub const MAX_RENTAL_DAYS: u32 = 365;
+
+/// An account allowed to use a token, without owning it, until `expires_at`
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenUser {
+    pub user_id: AccountId,
+    // nanoseconds since the epoch
+    pub expires_at: U64,
+    // rented through `nft_rent`, the token can't change hands or users until it expires
+    pub paid: bool,
+}
+
+impl TokenUser {
+    pub fn is_expired(&self) -> bool {
+        env::block_timestamp() >= self.expires_at.0
+    }
+}
+
+/// A token offered for rent through `nft_rent`
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RentalListing {
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    // yoctoNEAR, or units of `ft_contract_id`
+    pub price_per_day: U128,
+    pub max_days: u32,
+    // NEP-141 contract rentals are paid in through `ft_transfer_call`, NEAR if unset
+    pub ft_contract_id: Option<AccountId>,
+}
+
+/// Logs the user of a token being set, or cleared if `user` is unset
+fn emit_user_event(token_id: &TokenId, user: Option<&TokenUser>) {
+    EventLogVariant::NftUpdateUser(vec![NftUserLog {
+        token_id: token_id.clone(),
+        user_id: user.map(|user| user.user_id.to_string()),
+        expires_at: user.map(|user| user.expires_at.0.to_string()),
+    }])
+    .emit();
+}
+
+/// Logs a rental market event for `listing`
+pub(crate) fn emit_rental_event(
+    variant: fn(Vec<NftRentalLog>) -> EventLogVariant,
+    listing: &RentalListing,
+) {
+    variant(vec![NftRentalLog {
+        owner_id: listing.owner_id.to_string(),
+        token_id: listing.token_id.clone(),
+        price_per_day: listing.price_per_day.0.to_string(),
+        max_days: listing.max_days,
+        ft_contract_id: listing.ft_contract_id.as_ref().map(|id| id.to_string()),
+    }])
+    .emit();
+}
+
+impl Contract {
+    /// Panics unless `account_id` owns the token or is approved for it, returns the owner
+    fn expect_owner_or_approved(&self, token_id: &TokenId, account_id: &AccountId) -> AccountId {
+        let owner_id = self
+            .owner_by_id
+            .get(token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token not found"));
+        let approved = self
+            .approvals_by_id
+            .as_ref()
+            .and_then(|by_id| by_id.get(token_id))
+            .map(|approved_account_ids| approved_account_ids.contains_key(account_id))
+            .unwrap_or(false);
+        require!(
+            account_id == &owner_id || approved,
+            "cypher: Predecessor must be token owner or approved"
+        );
+        owner_id
+    }
+
+    /// Sets the user of a token, charging its owner for the storage
+    fn internal_set_user(&mut self, token_id: &TokenId, owner_id: &AccountId, user: &TokenUser) {
+        let initial_storage_usage = env::storage_usage();
+        self.token_users.insert(token_id, user);
+        self.internal_storage_debit(
+            owner_id,
+            env::storage_usage().saturating_sub(initial_storage_usage),
+        );
+        emit_user_event(token_id, Some(user));
+    }
+
+    /// Removes the user of a token, crediting the storage back to `owner_id`.
+    /// Called on every change of ownership, a user doesn't survive a new owner.
+    pub(crate) fn internal_clear_user(&mut self, token_id: &TokenId, owner_id: &AccountId) {
+        let initial_storage_usage = env::storage_usage();
+        if let Some(user) = self.token_users.remove(token_id) {
+            self.internal_storage_credit(
+                owner_id,
+                initial_storage_usage.saturating_sub(env::storage_usage()),
+            );
+            if !user.is_expired() {
+                emit_user_event(token_id, None);
+            }
+        }
+    }
+
+    /// Removes the rental listing of a token, crediting the storage back to its owner.
+    /// Called on every change of ownership, like `internal_remove_listing`.
+    pub(crate) fn internal_remove_rental_listing(
+        &mut self,
+        token_id: &TokenId,
+    ) -> Option<RentalListing> {
+        let initial_storage_usage = env::storage_usage();
+        let listing = self.rental_listings.remove(token_id)?;
+        self.internal_storage_credit(
+            &listing.owner_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
+        Some(listing)
+    }
+
+    /// Returns the user of a token if it hasn't expired
+    fn internal_active_user(&self, token_id: &TokenId) -> Option<TokenUser> {
+        self.token_users
+            .get(token_id)
+            .filter(|user| !user.is_expired())
+    }
+
+    /// Whether the token is rented out through `nft_rent` and the rental hasn't expired
+    pub(crate) fn internal_is_rented(&self, token_id: &TokenId) -> bool {
+        matches!(self.internal_active_user(token_id), Some(user) if user.paid)
+    }
+
+    /// Rents a token to `renter_id` for `days`, paid with `amount` in `ft_contract_id`, or NEAR
+    /// if unset. Returns the price and its payout, which are left to the caller to distribute
+    /// along with any excess.
+    pub(crate) fn internal_rent(
+        &mut self,
+        renter_id: &AccountId,
+        token_id: &TokenId,
+        days: u32,
+        ft_contract_id: Option<&AccountId>,
+        amount: Balance,
+    ) -> (Balance, Payout) {
+        self.internal_assert_not_blocked(renter_id);
+        let listing = self
+            .rental_listings
+            .get(token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token is not listed for rent"));
+        require!(
+            listing.ft_contract_id.as_ref() == ft_contract_id,
+            "cypher: The rental is in another currency"
+        );
+        require!(
+            days > 0 && days <= listing.max_days,
+            format!("cypher: Rentals last from 1 to {} days", listing.max_days)
+        );
+        self.internal_assert_token_unlocked(token_id);
+        require!(
+            self.internal_active_user(token_id).is_none(),
+            "cypher: Token already has a user"
+        );
+        let price = listing
+            .price_per_day
+            .0
+            .checked_mul(days as u128)
+            .unwrap_or_else(|| env::panic_str("cypher: Rental price overflows"));
+        require!(
+            amount >= price,
+            format!("cypher: Must pay at least {}", price)
+        );
+
+        let payout = self.internal_payout(&listing.owner_id, token_id, price, u32::MAX);
+        self.internal_set_user(
+            token_id,
+            &listing.owner_id,
+            &TokenUser {
+                user_id: renter_id.clone(),
+                expires_at: env::block_timestamp()
+                    .checked_add(days as u64 * RENTAL_DAY)
+                    .unwrap_or_else(|| env::panic_str("cypher: Rental expiry overflows"))
+                    .into(),
+                paid: true,
+            },
+        );
+        (price, payout)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Let `user_id` use a token until `expires_at`, in nanoseconds since the epoch, as its owner
+    /// or an approved account. The user is cleared when the token changes hands.
+    #[payable]
+    pub fn nft_set_user(&mut self, token_id: TokenId, user_id: AccountId, expires_at: U64) {
+        assert_one_yocto();
+        let owner_id = self.expect_owner_or_approved(&token_id, &env::predecessor_account_id());
+        require!(
+            expires_at.0 > env::block_timestamp(),
+            "cypher: Expiry must be in the future"
+        );
+        require!(
+            !self.internal_is_rented(&token_id),
+            "cypher: Token is rented out"
+        );
+
+        self.internal_set_user(
+            &token_id,
+            &owner_id,
+            &TokenUser {
+                user_id,
+                expires_at,
+                paid: false,
+            },
+        );
+    }
+
+    /// Clear the user of a token as its owner or an approved account, unless it is rented out.
+    /// Anyone can clear a user that has expired.
+    #[payable]
+    pub fn nft_clear_user(&mut self, token_id: TokenId) {
+        assert_one_yocto();
+        let user = self
+            .token_users
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token has no user"));
+        let owner_id = if user.is_expired() {
+            self.owner_by_id
+                .get(&token_id)
+                .unwrap_or_else(|| env::panic_str("cypher: Token not found"))
+        } else {
+            require!(!user.paid, "cypher: Token is rented out");
+            self.expect_owner_or_approved(&token_id, &env::predecessor_account_id())
+        };
+        self.internal_clear_user(&token_id, &owner_id);
+    }
+
+    /// Offer a token you own for rent at `price_per_day` yoctoNEAR, or units of an accepted
+    /// fungible token if `ft_contract_id` is set, for up to `max_days`, replacing any previous
+    /// rental listing of it
+    #[payable]
+    pub fn nft_list_rental(
+        &mut self,
+        token_id: TokenId,
+        price_per_day: U128,
+        max_days: u32,
+        ft_contract_id: Option<AccountId>,
+    ) {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+        require!(
+            self.owner_by_id.get(&token_id).as_ref() == Some(&owner_id),
+            "cypher: Predecessor must be token owner"
+        );
+        require!(price_per_day.0 > 0, "cypher: Price must be positive");
+        require!(
+            max_days > 0 && max_days <= MAX_RENTAL_DAYS,
+            format!("cypher: Rentals last from 1 to {} days", MAX_RENTAL_DAYS)
+        );
+        require!(
+            !self.internal_is_staked(&token_id),
+            "cypher: Token is staked"
+        );
+        self.internal_assert_accepted_ft(ft_contract_id.as_ref());
+
+        let listing = RentalListing {
+            token_id,
+            owner_id,
+            price_per_day,
+            max_days,
+            ft_contract_id,
+        };
+        let initial_storage_usage = env::storage_usage();
+        self.rental_listings.insert(&listing.token_id, &listing);
+        self.internal_storage_debit(
+            &listing.owner_id,
+            env::storage_usage().saturating_sub(initial_storage_usage),
+        );
+        emit_rental_event(EventLogVariant::NftRentalList, &listing);
+    }
+
+    /// Stop offering a token you own for rent, rentals already paid for carry on
+    #[payable]
+    pub fn nft_delist_rental(&mut self, token_id: TokenId) {
+        assert_one_yocto();
+        let listing = self
+            .rental_listings
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token is not listed for rent"));
+        require!(
+            listing.owner_id == env::predecessor_account_id(),
+            "cypher: Predecessor must be token owner"
+        );
+        self.internal_remove_rental_listing(&token_id);
+        emit_rental_event(EventLogVariant::NftRentalDelist, &listing);
+    }
+
+    /// Rent a token listed for rent for `days` with the attached deposit, becoming its user.
+    /// The price is split between the royalty recipients and the owner as in `nft_payout`,
+    /// any overpayment is refunded.
+    #[payable]
+    pub fn nft_rent(&mut self, token_id: TokenId, days: u32) {
+        let renter_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        let (price, payout) = self.internal_rent(&renter_id, &token_id, days, None, deposit);
+
+        distribute_payout(payout);
+        if deposit > price {
+            Promise::new(renter_id).transfer(deposit - price);
+        }
+    }
+
+    /// get the account allowed to use a token, if any and not expired
+    pub fn nft_user_of(&self, token_id: TokenId) -> Option<AccountId> {
+        self.internal_active_user(&token_id)
+            .map(|user| user.user_id)
+    }
+
+    /// get the user of a token with its expiry, if any and not expired
+    pub fn nft_token_user(&self, token_id: TokenId) -> Option<TokenUser> {
+        self.internal_active_user(&token_id)
+    }
+
+    /// get the rental listing of a token, if it is for rent
+    pub fn nft_rental_listing(&self, token_id: TokenId) -> Option<RentalListing> {
+        self.rental_listings.get(&token_id)
+    }
+
+    /// Query for the tokens for rent using pagination
+    pub fn nft_rental_listings(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<RentalListing> {
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        require!(
+            (self.rental_listings.len() as u128) >= start_index,
+            "cypher: Out of bounds, please use a smaller from_index."
+        );
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+
+        self.rental_listings
+            .values()
+            .skip(start_index as usize)
+            .take(limit)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn list_rental(contract: &mut Contract, price_per_day: Balance, max_days: u32) {
+        call(&account("alice.near"), 1, 0);
+        contract.nft_list_rental("1".to_string(), price_per_day.into(), max_days, None);
+    }
+
+    #[test]
+    fn renting_makes_the_renter_the_user() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        list_rental(&mut contract, NEAR, MAX_RENTAL_DAYS);
+
+        call(&account("bob.near"), 3 * NEAR, 1);
+        contract.nft_rent("1".to_string(), 3);
+        let user = contract.nft_token_user("1".to_string()).unwrap();
+        assert_eq!(user.user_id, account("bob.near"));
+        assert_eq!(user.expires_at.0, 1 + 3 * RENTAL_DAY);
+        assert!(contract.internal_is_rented(&"1".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Rentals last from 1 to 365 days")]
+    fn listing_cannot_offer_more_than_a_year() {
+        let mut contract = setup(&["alice.near"]);
+        mint(&mut contract, "1", "alice.near");
+        list_rental(&mut contract, 1, u32::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Account bob.near is blocked")]
+    fn blocked_account_cannot_rent() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        list_rental(&mut contract, NEAR, 3);
+        call(&owner(), 0, 0);
+        contract.nft_block_accounts(vec![account("bob.near")]);
+
+        call(&account("bob.near"), NEAR, 1);
+        contract.nft_rent("1".to_string(), 1);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::*;
+
+/// Logs a revenue event for `amount` deposited or claimed by `account_id`
+fn emit_revenue_event(
+    variant: fn(Vec<NftRevenueLog>) -> EventLogVariant,
+    account_id: &AccountId,
+    amount: Balance,
+) {
+    variant(vec![NftRevenueLog {
+        account_id: account_id.to_string(),
+        amount: amount.to_string(),
+    }])
+    .emit();
+}
+
+/// An account's standing in the revenue shared between all tokens
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct RevenueAccount {
+    // revenue per token already accounted for in `unclaimed`
+    pub paid_per_token: Balance,
+    // revenue earned and not claimed yet
+    pub unclaimed: Balance,
+}
+
+impl Contract {
+    fn internal_token_count(&self, account_id: &AccountId) -> u128 {
+        self.tokens_per_owner
+            .as_ref()
+            .and_then(|tokens_per_owner| tokens_per_owner.get(account_id))
+            .map(|token_set| token_set.len() as u128)
+            .unwrap_or(0)
+    }
+
+    /// Returns an account's standing as of now, with the revenue its tokens earned since
+    /// it was last settled added to `unclaimed`
+    fn internal_revenue_account(&self, account_id: &AccountId) -> RevenueAccount {
+        let mut account = self.revenue_accounts.get(account_id).unwrap_or_default();
+        account.unclaimed += self.internal_token_count(account_id)
+            * (self.revenue_per_token - account.paid_per_token);
+        account.paid_per_token = self.revenue_per_token;
+        account
+    }
+
+    /// Credits an account with the revenue its tokens earned so far.
+    /// Must be called before the number of tokens it holds changes.
+    pub(crate) fn internal_settle_revenue(&mut self, account_id: &AccountId) {
+        // nothing was ever shared, or the account is up to date
+        let paid_per_token = self
+            .revenue_accounts
+            .get(account_id)
+            .map(|account| account.paid_per_token)
+            .unwrap_or(0);
+        if paid_per_token == self.revenue_per_token {
+            return;
+        }
+        let account = self.internal_revenue_account(account_id);
+        self.revenue_accounts.insert(account_id, &account);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Share the attached deposit equally between all tokens, to be claimed by their holders.
+    /// What doesn't divide evenly is carried over to the next deposit.
+    #[payable]
+    pub fn nft_deposit_revenue(&mut self) {
+        let amount = env::attached_deposit();
+        require!(amount > 0, "cypher: Requires attached deposit");
+        let supply = self.owner_by_id.len() as u128;
+        require!(
+            supply > 0,
+            "cypher: There are no tokens to share revenue between"
+        );
+
+        let total = amount + self.revenue_remainder;
+        self.revenue_per_token += total / supply;
+        self.revenue_remainder = total % supply;
+        self.revenue_deposited += amount;
+        emit_revenue_event(
+            EventLogVariant::NftRevenueDeposit,
+            &env::predecessor_account_id(),
+            amount,
+        );
+    }
+
+    /// Withdraw the revenue earned by the tokens you hold and have held
+    #[payable]
+    pub fn nft_claim_revenue(&mut self) -> U128 {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut account = self.internal_revenue_account(&account_id);
+        let amount = account.unclaimed;
+        require!(amount > 0, "cypher: No revenue to claim");
+
+        account.unclaimed = 0;
+        let initial_storage_usage = env::storage_usage();
+        if self.internal_token_count(&account_id) == 0 {
+            self.revenue_accounts.remove(&account_id);
+        } else {
+            self.revenue_accounts.insert(&account_id, &account);
+        }
+        // claiming must not fail over its own bookkeeping, charged without a balance check
+        self.internal_storage_debit_unchecked(
+            &account_id,
+            env::storage_usage().saturating_sub(initial_storage_usage),
+        );
+        emit_revenue_event(EventLogVariant::NftRevenueClaim, &account_id, amount);
+        Promise::new(account_id).transfer(amount);
+        amount.into()
+    }
+
+    /// get the revenue an account can claim
+    pub fn nft_revenue_of(&self, account_id: AccountId) -> U128 {
+        self.internal_revenue_account(&account_id).unclaimed.into()
+    }
+
+    /// get the revenue each token has earned since the first deposit
+    pub fn nft_revenue_per_token(&self) -> U128 {
+        self.revenue_per_token.into()
+    }
+
+    /// get the total revenue deposited
+    pub fn nft_revenue_deposited(&self) -> U128 {
+        self.revenue_deposited.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn deposit_revenue(contract: &mut Contract, amount: u128) {
+        call(&owner(), amount, 0);
+        contract.nft_deposit_revenue();
+    }
+
+    #[test]
+    fn revenue_follows_the_holder_at_the_time_of_deposit() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        mint(&mut contract, "2", "bob.near");
+
+        deposit_revenue(&mut contract, 10 * NEAR);
+        transfer(&mut contract, "1", "bob.near", 1);
+        deposit_revenue(&mut contract, 10 * NEAR);
+
+        assert_eq!(contract.nft_revenue_of(account("alice.near")).0, 5 * NEAR);
+        assert_eq!(contract.nft_revenue_of(account("bob.near")).0, 15 * NEAR);
+
+        call(&account("alice.near"), 1, 2);
+        assert_eq!(contract.nft_claim_revenue().0, 5 * NEAR);
+        assert_eq!(contract.nft_revenue_of(account("alice.near")).0, 0);
+        assert_eq!(contract.nft_revenue_of(account("bob.near")).0, 15 * NEAR);
+    }
+}
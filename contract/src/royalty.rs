@@ -15,46 +15,351 @@ pub trait NonFungibleTokenRoyalty {
     ) -> Option<Payout>;
 }
 
-#[near_bindgen]
-impl NonFungibleTokenRoyalty for Contract {
-    /// calculates the payout for a token given the passed in balance. This is a view method
-    fn nft_payout(&self, token_id: String, balance: U128, max_len_payout: u32) -> Payout {
-        let owner_id = self
-            .owner_by_id
-            .get(&token_id)
-            .expect("cypher: Token doesn't exists!");
+/// Most accounts a token's royalty can be split between: 7 perpetual royalties and the contract owner
+const MAX_ROYALTY_RECIPIENTS: usize = 8;
+
+/// How many tokens `nft_transfer_royalties` goes through when no limit is given
+const DEFAULT_TRANSFER_ROYALTIES_LIMIT: u64 = 50;
+
+/// An account's royalty on a token, in basis points out of 10000
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoyaltyShare {
+    pub token_id: TokenId,
+    pub basis_points: u32,
+}
+
+/// What a call to `nft_transfer_royalties` did
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoyaltyTransferPage {
+    // `from_index` of the next call, past the tokens whose share was only split
+    pub next_index: U128,
+    // tokens left to go through from `next_index`, 0 once the whole set was
+    pub left: U128,
+}
+
+/// Royalties set on every mint, changed through governance
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoyaltyPolicy {
+    // royalty of the contract owner on every token
+    pub owner_basis_points: u32,
+    // most a token's royalties can add up to, the owner's included
+    pub max_basis_points: u32,
+}
+
+impl Default for RoyaltyPolicy {
+    fn default() -> Self {
+        Self {
+            owner_basis_points: 150,
+            max_basis_points: MINTER_ROYALTY_CAP,
+        }
+    }
+}
+
+impl RoyaltyPolicy {
+    pub fn assert_valid(&self) {
+        require!(
+            self.max_basis_points <= 10_000,
+            "cypher: Royalties cannot add up to more than 100%"
+        );
+        require!(
+            self.owner_basis_points <= self.max_basis_points,
+            "cypher: Owner royalty cannot be more than the maximum"
+        );
+    }
+
+    /// Panics unless `perpetual_royalties` fit in the policy next to the owner's royalty
+    pub fn assert_valid_royalties(&self, perpetual_royalties: &HashMap<AccountId, u32>) {
+        // check bleow gas limit of 5 royalties
+        assert!(
+            perpetual_royalties.len() <= 7,
+            " Cannot add more than 7 perpetual royalties"
+        );
+        let mut total_perpetual = self.owner_basis_points;
+        for amount in perpetual_royalties.values() {
+            assert!(
+                *amount < self.max_basis_points,
+                " The threshold royalty is capped at 70% for an accountId"
+            );
+            total_perpetual += amount;
+            assert!(
+                total_perpetual <= self.max_basis_points,
+                " The sum of all the perpetual royalties cannot be more than 70%"
+            );
+        }
+    }
+}
+
+/// pays every account in `payout` its share out of the contract's balance
+pub(crate) fn distribute_payout(payout: Payout) {
+    for (account_id, amount) in payout.payout {
+        if amount.0 > 0 {
+            Promise::new(account_id).transfer(amount.0);
+        }
+    }
+}
+
+/// Splits `balance` between the royalty recipients and `owner_id` so that the amounts add up to
+/// exactly `balance`: the owner gets whatever the royalties leave, rounding remainder included.
+/// The payout lists at most `max_len_payout` accounts (never fewer than the owner); when there are
+/// more recipients, the smallest shares are folded into the owner's.
+pub(crate) fn compute_payout(
+    owner_id: &AccountId,
+    royalty: &HashMap<AccountId, u32>,
+    balance: Balance,
+    max_len_payout: u32,
+) -> Payout {
+    let mut shares: Vec<(AccountId, Balance)> = royalty
+        .iter()
+        .filter(|(account_id, _)| *account_id != owner_id)
+        .map(|(account_id, percentage)| {
+            (account_id.clone(), royalty_to_payout(*percentage, balance).0)
+        })
+        .collect();
+    // largest shares first, ties broken by account so the result doesn't depend on map order
+    shares.sort_by(|(a_id, a), (b_id, b)| b.cmp(a).then_with(|| a_id.cmp(b_id)));
+    shares.truncate(max_len_payout.saturating_sub(1) as usize);
+
+    let royalties_paid: Balance = shares.iter().map(|(_, amount)| amount).sum();
+    let mut payout = Payout {
+        payout: shares
+            .into_iter()
+            .map(|(account_id, amount)| (account_id, U128(amount)))
+            .collect(),
+    };
+    payout
+        .payout
+        .insert(owner_id.clone(), U128(balance - royalties_paid));
+    payout
+}
+
+impl Contract {
+    /// calculates how `balance` paid for a token owned by `owner_id` is split between its royalty
+    /// recipients and the owner
+    pub(crate) fn internal_payout(
+        &self,
+        owner_id: &AccountId,
+        token_id: &TokenId,
+        balance: Balance,
+        max_len_payout: u32,
+    ) -> Payout {
+        self.internal_assert_not_frozen(token_id);
         let royalty = if let Some(royalty_by_id) = &self.royalty_by_id {
-            royalty_by_id.get(&token_id).unwrap().royalty
+            royalty_by_id.get(token_id).unwrap().royalty
         } else {
             HashMap::new()
         };
+        compute_payout(owner_id, &royalty, balance, max_len_payout)
+    }
 
-        assert!(
-            royalty.len() as u32 <= max_len_payout,
-            "cypher: Market cannot payout to that many receivers"
+    /// Moves `basis_points` of the royalty of `old_account_id` on a token, all of it if unset, to
+    /// `new_account_id`. The token's total royalty doesn't change. Returns the log of the move and
+    /// whether `old_account_id` kept part of its share, leaving the token in its set.
+    fn internal_transfer_royalty(
+        &mut self,
+        token_id: &TokenId,
+        old_account_id: &AccountId,
+        new_account_id: &AccountId,
+        basis_points: Option<u32>,
+    ) -> (NftRoyaltyLog, bool) {
+        let royalty_by_id = self
+            .royalty_by_id
+            .as_mut()
+            .unwrap_or_else(|| env::panic_str("cypher: NFT does not support royalties"));
+        let mut token_royalty = royalty_by_id
+            .get(token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token not found"));
+        let share = token_royalty
+            .royalty
+            .get(old_account_id)
+            .copied()
+            .unwrap_or_else(|| env::panic_str("cypher: No royalty on this token"));
+        let moved = basis_points.unwrap_or(share);
+        require!(
+            moved > 0 && moved <= share,
+            "cypher: Can only move part of your own royalty share"
         );
 
-        let balance_u128: u128 = balance.into();
+        if moved == share {
+            token_royalty.royalty.remove(old_account_id);
+            internal_remove_from_token_set(
+                &mut self.royalty_tokens_by_account,
+                old_account_id,
+                token_id,
+            );
+        } else {
+            token_royalty
+                .royalty
+                .insert(old_account_id.clone(), share - moved);
+        }
+        *token_royalty
+            .royalty
+            .entry(new_account_id.clone())
+            .or_insert(0) += moved;
+        require!(
+            token_royalty.royalty.len() <= MAX_ROYALTY_RECIPIENTS,
+            "cypher: Token has reached the maximum number of royalty recipients"
+        );
+        royalty_by_id.insert(token_id, &token_royalty);
+        internal_add_to_token_set(
+            &mut self.royalty_tokens_by_account,
+            new_account_id,
+            token_id,
+            |account_hash| StorageKey::RoyaltyTokensByAccountInner { account_hash },
+        );
 
-        let mut payout: Payout = Payout {
-            payout: HashMap::new(),
+        let log = NftRoyaltyLog {
+            token_id: token_id.clone(),
+            old_account_id: old_account_id.to_string(),
+            new_account_id: new_account_id.to_string(),
+            basis_points: moved,
         };
-        let mut total_perpetual = 0;
-
-        for (k, v) in royalty.iter() {
-            if *k != owner_id {
-                let key = k.clone();
-                payout
-                    .payout
-                    .insert(key, royalty_to_payout(*v, balance_u128));
-                total_perpetual += *v;
+        (log, moved < share)
+    }
+
+    /// Charges or credits the caller for the storage change since `initial_storage_usage`
+    fn internal_settle_royalty_storage(&mut self, account_id: &AccountId, initial_storage_usage: u64) {
+        let storage_usage = env::storage_usage();
+        if storage_usage > initial_storage_usage {
+            self.internal_storage_debit(account_id, storage_usage - initial_storage_usage);
+        } else {
+            self.internal_storage_credit(account_id, initial_storage_usage - storage_usage);
+        }
+    }
+}
+
+/// Logs the royalty shares that changed hands
+fn emit_royalty_update(logs: Vec<NftRoyaltyLog>) {
+    EventLogVariant::NftRoyaltyUpdate(logs).emit();
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Give `basis_points` of your royalty on a token, or all of it if unset, to `receiver_id`
+    #[payable]
+    pub fn nft_transfer_royalty(
+        &mut self,
+        token_id: TokenId,
+        receiver_id: AccountId,
+        basis_points: Option<u32>,
+    ) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        require!(account_id != receiver_id, "cypher: Cannot transfer royalty to yourself");
+
+        let initial_storage_usage = env::storage_usage();
+        let (log, _) =
+            self.internal_transfer_royalty(&token_id, &account_id, &receiver_id, basis_points);
+        self.internal_settle_royalty_storage(&account_id, initial_storage_usage);
+        emit_royalty_update(vec![log]);
+    }
+
+    /// Give `basis_points` of your royalty, or all of it if unset, to `receiver_id` on a page of
+    /// the tokens you earn royalties on, starting at `from_index`. At most `limit` tokens are gone
+    /// through. Tokens whose whole share moved leave your set and those only split stay in it,
+    /// so the next call starts at the returned `next_index`. Call again until `left` reaches 0.
+    #[payable]
+    pub fn nft_transfer_royalties(
+        &mut self,
+        receiver_id: AccountId,
+        basis_points: Option<u32>,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> RoyaltyTransferPage {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        require!(account_id != receiver_id, "cypher: Cannot transfer royalty to yourself");
+
+        let mut index: u64 = from_index.map(|i| i.0 as u64).unwrap_or_default();
+        let limit = limit.unwrap_or(DEFAULT_TRANSFER_ROYALTIES_LIMIT);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+
+        let initial_storage_usage = env::storage_usage();
+        let mut logs = Vec::new();
+        for _ in 0..limit {
+            // read the set again every time, removing a token swaps the last one into its place
+            let token_id = match self
+                .royalty_tokens_by_account
+                .get(&account_id)
+                .and_then(|token_set| token_set.as_vector().get(index))
+            {
+                Some(token_id) => token_id,
+                None => break,
+            };
+            let (log, kept) =
+                self.internal_transfer_royalty(&token_id, &account_id, &receiver_id, basis_points);
+            logs.push(log);
+            if kept {
+                index += 1;
             }
         }
-        payout.payout.insert(
-            owner_id,
-            royalty_to_payout(10000 - total_perpetual, balance_u128),
-        );
-        payout
+        self.internal_settle_royalty_storage(&account_id, initial_storage_usage);
+        if !logs.is_empty() {
+            emit_royalty_update(logs);
+        }
+
+        let remaining = self
+            .royalty_tokens_by_account
+            .get(&account_id)
+            .map(|token_set| token_set.len())
+            .unwrap_or(0);
+        RoyaltyTransferPage {
+            next_index: U128(index.into()),
+            left: U128(remaining.saturating_sub(index).into()),
+        }
+    }
+
+    /// get the royalties set on every mint
+    pub fn nft_royalty_policy(&self) -> RoyaltyPolicy {
+        self.royalty_policy.clone()
+    }
+
+    /// Query for the tokens an account earns royalties on, and its share of each, using pagination
+    pub fn nft_royalties_for_account(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<RoyaltyShare> {
+        let token_set = if let Some(token_set) = self.royalty_tokens_by_account.get(&account_id) {
+            token_set
+        } else {
+            return vec![];
+        };
+        let royalty_by_id = if let Some(royalty_by_id) = &self.royalty_by_id {
+            royalty_by_id
+        } else {
+            return vec![];
+        };
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+        token_set
+            .iter()
+            .skip(start_index as usize)
+            .take(limit)
+            .filter_map(|token_id| {
+                let basis_points = *royalty_by_id.get(&token_id)?.royalty.get(&account_id)?;
+                Some(RoyaltyShare {
+                    token_id,
+                    basis_points,
+                })
+            })
+            .collect()
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenRoyalty for Contract {
+    /// calculates the payout for a token given the passed in balance. This is a view method
+    fn nft_payout(&self, token_id: String, balance: U128, max_len_payout: u32) -> Payout {
+        let owner_id = self
+            .owner_by_id
+            .get(&token_id)
+            .expect("cypher: Token doesn't exists!");
+        self.internal_payout(&owner_id, &token_id, balance.into(), max_len_payout)
     }
 
     /// transfers the token to the receiver ID and returns the payout object that should be payed given the passed in balance.
@@ -69,50 +374,216 @@ impl NonFungibleTokenRoyalty for Contract {
     ) -> Option<Payout> {
         assert_one_yocto();
         let sender_id = env::predecessor_account_id();
-        let royalty = if let Some(royalty_by_id) = &self.royalty_by_id {
-            royalty_by_id.get(&token_id).unwrap().royalty
-        } else {
-            HashMap::new()
-        };
+        let owner_id = self
+            .owner_by_id
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token not found"));
+
+        // split the balance before the token changes hands, the payout depends on the owner
+        let payout = balance.map(|balance| {
+            self.internal_payout(
+                &owner_id,
+                &token_id,
+                balance.into(),
+                max_len_payout.unwrap_or(u32::MAX),
+            )
+        });
 
         //transfer the token to the passed in receiver and get the previous token object back
-        let (previous_owner_id, _) =
+        let (previous_owner_id, approved_account_ids) =
             self.internal_transfer(&sender_id, &receiver_id, &token_id, approval_id, &None);
 
-        let mut total_perpetual = 0;
-        let payout = if let Some(balance) = balance {
-            let balance_u128: u128 = u128::from(balance);
-            let mut payout: Payout = Payout {
-                payout: HashMap::new(),
-            };
+        // credit the previous owner for the storage of the cleared approvals
+        if let Some(approved_account_ids) = approved_account_ids {
+            self.internal_storage_credit(
+                &previous_owner_id,
+                bytes_for_approved_account_ids_raw(&approved_account_ids),
+            );
+        }
 
-            assert!(
-                royalty.len() as u32 <= max_len_payout.unwrap(),
-                "cypher: Market cannot pay that many people"
+        if let (Some(balance), Some(payout)) = (balance, &payout) {
+            self.internal_record_sale(
+                &token_id,
+                &previous_owner_id,
+                &receiver_id,
+                balance.0,
+                None,
+                payout,
             );
+        }
+        payout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
 
-            for (k, v) in royalty.iter() {
-                let key = k.clone();
-                if key != previous_owner_id {
-                    payout
-                        .payout
-                        .insert(key, royalty_to_payout(*v, balance_u128));
-                    total_perpetual += *v;
-                }
+    fn total(payout: &Payout) -> Balance {
+        payout.payout.values().map(|amount| amount.0).sum()
+    }
+
+    #[test]
+    fn payout_sums_to_balance() {
+        let owner = account("owner.near");
+        let royalty = HashMap::from([
+            (account("a.near"), 333),
+            (account("b.near"), 1_001),
+            (account("c.near"), 7),
+        ]);
+        for balance in [0, 1, 7, 999, 10_001, 123_456_789, 10u128.pow(24) + 3] {
+            let payout = compute_payout(&owner, &royalty, balance, u32::MAX);
+            assert_eq!(total(&payout), balance);
+            assert_eq!(payout.payout.len(), 4);
+        }
+    }
+
+    #[test]
+    fn rounding_remainder_goes_to_owner() {
+        let owner = account("owner.near");
+        let royalty = HashMap::from([(account("a.near"), 3_333), (account("b.near"), 3_333)]);
+        let payout = compute_payout(&owner, &royalty, 10, u32::MAX);
+        assert_eq!(payout.payout[&account("a.near")].0, 3);
+        assert_eq!(payout.payout[&account("b.near")].0, 3);
+        assert_eq!(payout.payout[&owner].0, 4);
+    }
+
+    #[test]
+    fn owner_royalty_is_not_paid_twice() {
+        let owner = account("owner.near");
+        let royalty = HashMap::from([(owner.clone(), 150), (account("a.near"), 1_000)]);
+        let payout = compute_payout(&owner, &royalty, 10_000, u32::MAX);
+        assert_eq!(payout.payout.len(), 2);
+        assert_eq!(payout.payout[&account("a.near")].0, 1_000);
+        assert_eq!(payout.payout[&owner].0, 9_000);
+    }
+
+    #[test]
+    fn smallest_shares_fold_into_owner() {
+        let owner = account("owner.near");
+        let royalty = HashMap::from([
+            (account("a.near"), 100),
+            (account("b.near"), 2_000),
+            (account("c.near"), 500),
+            (account("d.near"), 500),
+        ]);
+        let payout = compute_payout(&owner, &royalty, 1_000_003, 3);
+        assert_eq!(payout.payout.len(), 3);
+        assert_eq!(total(&payout), 1_000_003);
+        assert_eq!(payout.payout[&account("b.near")].0, 200_000);
+        // c.near and d.near tie, the account order decides
+        assert_eq!(payout.payout[&account("c.near")].0, 50_000);
+        assert!(!payout.payout.contains_key(&account("d.near")));
+        assert_eq!(payout.payout[&owner].0, 750_003);
+    }
+
+    #[test]
+    fn max_len_payout_of_one_or_less_pays_the_owner_only() {
+        let owner = account("owner.near");
+        let royalty = HashMap::from([(account("a.near"), 1_000)]);
+        for max_len_payout in [0, 1] {
+            let payout = compute_payout(&owner, &royalty, 500, max_len_payout);
+            assert_eq!(payout.payout.len(), 1);
+            assert_eq!(payout.payout[&owner].0, 500);
+        }
+    }
+
+    fn mint_with_royalty(contract: &mut Contract, token_id: &str, basis_points: u32) {
+        call(&owner(), 0, 0);
+        contract.nft_mint(
+            token_id.to_string(),
+            metadata(),
+            account("bob.near"),
+            Some(HashMap::from([(account("alice.near"), basis_points)])),
+            None,
+        );
+    }
+
+    fn transfer_royalties(
+        contract: &mut Contract,
+        basis_points: Option<u32>,
+        from_index: u128,
+    ) -> RoyaltyTransferPage {
+        call(&account("alice.near"), 1, 0);
+        contract.nft_transfer_royalties(
+            account("carol.near"),
+            basis_points,
+            Some(U128(from_index)),
+            Some(2),
+        )
+    }
+
+    fn shares(contract: &Contract, account_id: &str) -> HashMap<TokenId, u32> {
+        contract
+            .nft_royalties_for_account(account(account_id), None, None)
+            .into_iter()
+            .map(|share| (share.token_id, share.basis_points))
+            .collect()
+    }
+
+    #[test]
+    fn transfer_royalties_pages_past_split_shares() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        for token_id in ["1", "2", "3"] {
+            mint_with_royalty(&mut contract, token_id, 500);
+        }
+
+        // splitting the share keeps the tokens in the set, the next page starts after them
+        let page = transfer_royalties(&mut contract, Some(200), 0);
+        assert_eq!((page.next_index.0, page.left.0), (2, 1));
+        let page = transfer_royalties(&mut contract, Some(200), page.next_index.0);
+        assert_eq!((page.next_index.0, page.left.0), (3, 0));
+        assert_eq!(
+            shares(&contract, "carol.near"),
+            HashMap::from([
+                ("1".to_string(), 200),
+                ("2".to_string(), 200),
+                ("3".to_string(), 200)
+            ])
+        );
+
+        // moving the whole share drops the tokens from the set, the cursor stays in place
+        let page = transfer_royalties(&mut contract, None, 0);
+        assert_eq!((page.next_index.0, page.left.0), (0, 1));
+        let page = transfer_royalties(&mut contract, None, page.next_index.0);
+        assert_eq!((page.next_index.0, page.left.0), (0, 0));
+        assert!(shares(&contract, "alice.near").is_empty());
+        assert_eq!(shares(&contract, "carol.near")["2"], 500);
+    }
+
+    #[test]
+    fn transfer_royalties_moves_each_share_once_when_pages_mix_moves_and_splits() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        // the whole share of "1" moves, "2" and "3" are split, with the last token swapped in
+        // place of the first as it leaves the set
+        mint_with_royalty(&mut contract, "1", 200);
+        mint_with_royalty(&mut contract, "2", 500);
+        mint_with_royalty(&mut contract, "3", 500);
+
+        let mut from_index = 0;
+        let mut pages = 0;
+        loop {
+            let page = transfer_royalties(&mut contract, Some(200), from_index);
+            pages += 1;
+            from_index = page.next_index.0;
+            if page.left.0 == 0 {
+                break;
             }
-            assert!(
-                total_perpetual <= MINTER_ROYALTY_CAP,
-                "cypher: Royalties should not be more than caps of 70%"
-            );
-            payout.payout.insert(
-                previous_owner_id,
-                royalty_to_payout(10000 - total_perpetual, balance_u128),
-            );
-            Some(payout)
-        } else {
-            None
-        };
+        }
 
-        payout
+        assert_eq!(pages, 2);
+        assert_eq!(
+            shares(&contract, "carol.near"),
+            HashMap::from([
+                ("1".to_string(), 200),
+                ("2".to_string(), 200),
+                ("3".to_string(), 200)
+            ])
+        );
+        assert_eq!(
+            shares(&contract, "alice.near"),
+            HashMap::from([("2".to_string(), 300), ("3".to_string(), 300)])
+        );
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,435 @@
+use crate::*;
+use near_sdk::json_types::U64;
+
+/// How long a safe transfer waits for the receiver when no timeout is given (7 days)
+const DEFAULT_SAFE_TRANSFER_TIMEOUT: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+/// A transfer started with `nft_safe_transfer` that the receiver hasn't accepted yet.
+/// The token stays with its owner and can't be transferred in the meantime.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingTransfer {
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    // the owner or an approved account, who pays for the pending transfer's storage
+    pub sender_id: AccountId,
+    pub receiver_id: AccountId,
+    pub approval_id: Option<u64>,
+    pub memo: Option<String>,
+    // nanoseconds since the epoch after which the transfer lapses
+    pub expires_at: U64,
+}
+
+impl PendingTransfer {
+    pub fn is_lapsed(&self) -> bool {
+        env::block_timestamp() >= self.expires_at.0
+    }
+}
+
+/// Logs a safe transfer event for `pending`
+fn emit_pending_transfer_event(
+    variant: fn(Vec<NftPendingTransferLog>) -> EventLogVariant,
+    pending: &PendingTransfer,
+) {
+    variant(vec![NftPendingTransferLog {
+        owner_id: pending.owner_id.to_string(),
+        receiver_id: pending.receiver_id.to_string(),
+        token_id: pending.token_id.clone(),
+        expires_at: pending.expires_at.0.to_string(),
+        memo: pending.memo.clone(),
+    }])
+    .emit();
+}
+
+impl Contract {
+    /// Records a pending transfer and its indexes, charging the sender for the storage
+    fn internal_add_pending_transfer(&mut self, pending: &PendingTransfer) {
+        let initial_storage_usage = env::storage_usage();
+        self.pending_transfers.insert(&pending.token_id, pending);
+        internal_add_to_token_set(
+            &mut self.pending_transfers_by_owner,
+            &pending.owner_id,
+            &pending.token_id,
+            |account_hash| StorageKey::PendingTransfersByOwnerInner { account_hash },
+        );
+        internal_add_to_token_set(
+            &mut self.pending_transfers_by_receiver,
+            &pending.receiver_id,
+            &pending.token_id,
+            |account_hash| StorageKey::PendingTransfersByReceiverInner { account_hash },
+        );
+        self.internal_storage_debit(
+            &pending.sender_id,
+            env::storage_usage() - initial_storage_usage,
+        );
+    }
+
+    /// Removes the pending transfer of a token, crediting the storage back to its sender
+    fn internal_remove_pending_transfer(&mut self, token_id: &TokenId) -> Option<PendingTransfer> {
+        let initial_storage_usage = env::storage_usage();
+        let pending = self.pending_transfers.remove(token_id)?;
+        internal_remove_from_token_set(
+            &mut self.pending_transfers_by_owner,
+            &pending.owner_id,
+            token_id,
+        );
+        internal_remove_from_token_set(
+            &mut self.pending_transfers_by_receiver,
+            &pending.receiver_id,
+            token_id,
+        );
+        self.internal_storage_credit(
+            &pending.sender_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
+        Some(pending)
+    }
+
+    /// Returns true if the token has a pending transfer that hasn't lapsed.
+    /// A lapsed one is cleared on the way.
+    pub(crate) fn internal_has_pending_transfer(&mut self, token_id: &TokenId) -> bool {
+        match self.pending_transfers.get(token_id) {
+            Some(pending) if pending.is_lapsed() => {
+                self.internal_remove_pending_transfer(token_id);
+                emit_pending_transfer_event(EventLogVariant::NftTransferLapsed, &pending);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Pages through the live pending transfers of one of the indexes
+    fn pending_transfers_in_set(
+        &self,
+        by_account: &LookupMap<AccountId, UnorderedSet<TokenId>>,
+        account_id: &AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<PendingTransfer> {
+        let token_set = if let Some(token_set) = by_account.get(account_id) {
+            token_set
+        } else {
+            return vec![];
+        };
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+        token_set
+            .iter()
+            .skip(start_index as usize)
+            .take(limit)
+            .filter_map(|token_id| self.pending_transfers.get(&token_id))
+            .filter(|pending| !pending.is_lapsed())
+            .collect()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Start a transfer that only completes once `receiver_id` accepts it with `nft_accept_transfer`.
+    /// Until then the token stays with its owner and can't be transferred. The transfer lapses
+    /// after `timeout` nanoseconds (7 days by default).
+    #[payable]
+    pub fn nft_safe_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+        timeout: Option<U64>,
+    ) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let owner_id = self
+            .owner_by_id
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token not found"));
+
+        require!(
+            sender_id == owner_id
+                || self.nft_is_approved(token_id.clone(), sender_id.clone(), approval_id),
+            "cypher: Sender not approved"
+        );
+        self.internal_assert_transfer_accounts(&sender_id, &owner_id, &receiver_id);
+        self.internal_assert_token_unlocked(&token_id);
+
+        let timeout = timeout
+            .map(|timeout| timeout.0)
+            .unwrap_or(DEFAULT_SAFE_TRANSFER_TIMEOUT);
+        let pending = PendingTransfer {
+            token_id,
+            owner_id,
+            sender_id,
+            receiver_id,
+            approval_id,
+            memo,
+            expires_at: env::block_timestamp().saturating_add(timeout).into(),
+        };
+        self.internal_add_pending_transfer(&pending);
+        emit_pending_transfer_event(EventLogVariant::NftTransferPending, &pending);
+    }
+
+    /// Receive a token sent with `nft_safe_transfer`
+    #[payable]
+    pub fn nft_accept_transfer(&mut self, token_id: TokenId) {
+        assert_one_yocto();
+        let receiver_id = env::predecessor_account_id();
+        let pending = self
+            .pending_transfers
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: No pending transfer for this token"));
+        require!(
+            pending.receiver_id == receiver_id,
+            "cypher: Only the receiver can accept the transfer"
+        );
+        require!(
+            !pending.is_lapsed(),
+            "cypher: The pending transfer has lapsed"
+        );
+
+        self.internal_remove_pending_transfer(&token_id);
+        let (previous_owner_id, approved_account_ids) = self.internal_transfer(
+            &pending.sender_id,
+            &receiver_id,
+            &token_id,
+            pending.approval_id,
+            &pending.memo,
+        );
+
+        // credit the previous owner for the storage of the cleared approvals
+        if let Some(approved_account_ids) = approved_account_ids {
+            self.internal_storage_credit(
+                &previous_owner_id,
+                bytes_for_approved_account_ids_raw(&approved_account_ids),
+            );
+        }
+        emit_pending_transfer_event(EventLogVariant::NftTransferAccepted, &pending);
+    }
+
+    /// Cancel a pending safe transfer. The sender and the token owner can cancel at any time,
+    /// anyone can clear a transfer that has lapsed.
+    #[payable]
+    pub fn nft_cancel_transfer(&mut self, token_id: TokenId) {
+        assert_one_yocto();
+        let predecessor_account_id = env::predecessor_account_id();
+        let pending = self
+            .pending_transfers
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: No pending transfer for this token"));
+        let lapsed = pending.is_lapsed();
+        require!(
+            lapsed
+                || predecessor_account_id == pending.sender_id
+                || predecessor_account_id == pending.owner_id,
+            "cypher: Only the sender or the owner can cancel the transfer"
+        );
+
+        self.internal_remove_pending_transfer(&token_id);
+        if lapsed {
+            emit_pending_transfer_event(EventLogVariant::NftTransferLapsed, &pending);
+        } else {
+            emit_pending_transfer_event(EventLogVariant::NftTransferCancelled, &pending);
+        }
+    }
+
+    /// get the pending safe transfer of a token, if it hasn't lapsed
+    pub fn nft_pending_transfer(&self, token_id: TokenId) -> Option<PendingTransfer> {
+        self.pending_transfers
+            .get(&token_id)
+            .filter(|pending| !pending.is_lapsed())
+    }
+
+    /// Query for the pending safe transfers waiting for `account_id` to accept them
+    pub fn nft_pending_transfers_for_receiver(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<PendingTransfer> {
+        self.pending_transfers_in_set(
+            &self.pending_transfers_by_receiver,
+            &account_id,
+            from_index,
+            limit,
+        )
+    }
+
+    /// Query for the pending safe transfers of tokens owned by `account_id`
+    pub fn nft_pending_transfers_for_owner(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<PendingTransfer> {
+        self.pending_transfers_in_set(
+            &self.pending_transfers_by_owner,
+            &account_id,
+            from_index,
+            limit,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    const TIMEOUT: u64 = 1_000;
+
+    fn used_bytes(contract: &Contract, account_id: &str) -> u64 {
+        contract
+            .internal_storage_account(&account(account_id))
+            .used_bytes
+    }
+
+    /// Mints "1" to alice and safe-sends it to bob at timestamp 0
+    fn safe_transfer(contract: &mut Contract) {
+        mint(contract, "1", "alice.near");
+        call(&account("alice.near"), 1, 0);
+        contract.nft_safe_transfer(
+            account("bob.near"),
+            "1".to_string(),
+            None,
+            None,
+            Some(U64(TIMEOUT)),
+        );
+    }
+
+    #[test]
+    fn accepting_moves_the_token_and_refunds_the_sender() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        safe_transfer(&mut contract);
+        assert_eq!(owner_of(&contract, "1"), account("alice.near"));
+        assert!(used_bytes(&contract, "alice.near") > contract.storage_registration_bytes);
+        assert_eq!(
+            contract
+                .nft_pending_transfers_for_receiver(account("bob.near"), None, None)
+                .len(),
+            1
+        );
+
+        call(&account("bob.near"), 1, TIMEOUT - 1);
+        contract.nft_accept_transfer("1".to_string());
+
+        assert_eq!(owner_of(&contract, "1"), account("bob.near"));
+        assert!(contract.nft_pending_transfer("1".to_string()).is_none());
+        assert!(contract
+            .nft_pending_transfers_for_owner(account("alice.near"), None, None)
+            .is_empty());
+        assert_eq!(
+            used_bytes(&contract, "alice.near"),
+            contract.storage_registration_bytes
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Only the receiver can accept the transfer")]
+    fn only_the_receiver_can_accept() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        safe_transfer(&mut contract);
+        call(&account("carol.near"), 1, 0);
+        contract.nft_accept_transfer("1".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Token has a pending transfer")]
+    fn pending_token_cannot_be_transferred() {
+        let mut contract = setup(&["alice.near", "bob.near", "carol.near"]);
+        safe_transfer(&mut contract);
+        transfer(&mut contract, "1", "carol.near", 0);
+    }
+
+    #[test]
+    fn cancelling_keeps_the_token_and_refunds_the_sender() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        safe_transfer(&mut contract);
+
+        call(&account("alice.near"), 1, 0);
+        contract.nft_cancel_transfer("1".to_string());
+
+        assert_eq!(owner_of(&contract, "1"), account("alice.near"));
+        assert!(contract.nft_pending_transfer("1".to_string()).is_none());
+        assert_eq!(
+            used_bytes(&contract, "alice.near"),
+            contract.storage_registration_bytes
+        );
+        assert!(near_sdk::test_utils::get_logs()[0].contains(r#""event":"nft_transfer_cancelled""#));
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Only the sender or the owner can cancel the transfer")]
+    fn others_cannot_cancel_before_the_transfer_lapses() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        safe_transfer(&mut contract);
+        call(&account("carol.near"), 1, TIMEOUT - 1);
+        contract.nft_cancel_transfer("1".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: The pending transfer has lapsed")]
+    fn lapsed_transfer_cannot_be_accepted() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        safe_transfer(&mut contract);
+        call(&account("bob.near"), 1, TIMEOUT);
+        contract.nft_accept_transfer("1".to_string());
+    }
+
+    #[test]
+    fn anyone_can_clear_a_lapsed_transfer() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        safe_transfer(&mut contract);
+        call(&account("carol.near"), 0, TIMEOUT);
+        assert!(contract.nft_pending_transfer("1".to_string()).is_none());
+
+        call(&account("carol.near"), 1, TIMEOUT);
+        contract.nft_cancel_transfer("1".to_string());
+
+        assert!(contract.pending_transfers.get(&"1".to_string()).is_none());
+        assert_eq!(
+            used_bytes(&contract, "alice.near"),
+            contract.storage_registration_bytes
+        );
+        assert!(near_sdk::test_utils::get_logs()[0].contains(r#""event":"nft_transfer_lapsed""#));
+    }
+
+    #[test]
+    fn lapsed_transfer_is_cleared_by_the_next_transfer() {
+        let mut contract = setup(&["alice.near", "bob.near", "carol.near"]);
+        safe_transfer(&mut contract);
+
+        transfer(&mut contract, "1", "carol.near", TIMEOUT);
+
+        assert_eq!(owner_of(&contract, "1"), account("carol.near"));
+        assert!(contract.pending_transfers.get(&"1".to_string()).is_none());
+        assert_eq!(
+            used_bytes(&contract, "alice.near"),
+            contract.storage_registration_bytes
+        );
+    }
+
+    #[test]
+    fn approved_sender_pays_for_the_pending_transfer() {
+        let mut contract = setup(&["alice.near", "bob.near", "market.near"]);
+        mint(&mut contract, "1", "alice.near");
+        call(&account("alice.near"), NEAR / 100, 0);
+        contract.nft_approve("1".to_string(), account("market.near"), None, None);
+        call(&account("market.near"), 1, 0);
+        contract.nft_safe_transfer(account("bob.near"), "1".to_string(), Some(1), None, None);
+        assert!(used_bytes(&contract, "market.near") > contract.storage_registration_bytes);
+
+        call(&account("bob.near"), 1, 0);
+        contract.nft_accept_transfer("1".to_string());
+
+        assert_eq!(owner_of(&contract, "1"), account("bob.near"));
+        assert_eq!(
+            used_bytes(&contract, "market.near"),
+            contract.storage_registration_bytes
+        );
+        assert_eq!(
+            used_bytes(&contract, "alice.near"),
+            contract.storage_registration_bytes
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use crate::*;
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{Base58CryptoHash, U64};
+use near_sdk::CryptoHash;
+use std::ops::Bound;
+
+/// Tokens, then holders, processed by a single `nft_snapshot_step` call when no limit is given
+const DEFAULT_SNAPSHOT_STEP_LIMIT: u64 = 100;
+
+/// The holders of the collection, taken page by page over the tokens.
+/// Each token is counted with the owner it had when its page was taken.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Snapshot {
+    pub snapshot_id: U64,
+    // when the snapshot was started
+    pub block_height: U64,
+    pub timestamp: U64,
+    pub token_count: U64,
+    pub holder_count: U64,
+    // true once every token has been counted and the merkle root computed
+    pub finished: bool,
+    // root of the merkle tree over `snapshot_leaf` of each holder, in the order of
+    // `nft_snapshot_holders`, unset until finished or if there are no holders
+    pub merkle_root: Option<Base58CryptoHash>,
+}
+
+/// An account's number of tokens in a snapshot
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SnapshotHolder {
+    pub account_id: AccountId,
+    pub count: U64,
+}
+
+/// Where an unfinished snapshot is at
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct SnapshotProgress {
+    // last token counted, tokens are taken in the order of `owner_by_id`
+    pub last_token_id: Option<TokenId>,
+    pub tokens_done: bool,
+    // holders hashed into `frontier` so far
+    pub holders_hashed: u64,
+    // pending left node at each level of the merkle tree
+    pub frontier: Vec<Option<CryptoHash>>,
+}
+
+fn hash_pair(left: &CryptoHash, right: &CryptoHash) -> CryptoHash {
+    let mut bytes = Vec::with_capacity(64);
+    bytes.extend_from_slice(left);
+    bytes.extend_from_slice(right);
+    env::sha256(&bytes).try_into().unwrap()
+}
+
+/// Leaf of a snapshot's merkle tree: sha256 of the borsh serialized `(account_id, count)`
+pub fn snapshot_leaf(account_id: &AccountId, count: u64) -> CryptoHash {
+    let bytes = (account_id, count).try_to_vec().unwrap();
+    env::sha256(&bytes).try_into().unwrap()
+}
+
+/// Adds the next leaf to a merkle tree built left to right, keeping only a pending node per level
+fn frontier_push(frontier: &mut Vec<Option<CryptoHash>>, leaf: CryptoHash) {
+    let mut node = leaf;
+    for pending in frontier.iter_mut() {
+        match pending.take() {
+            Some(left) => node = hash_pair(&left, &node),
+            None => {
+                *pending = Some(node);
+                return;
+            }
+        }
+    }
+    frontier.push(Some(node));
+}
+
+/// Root of the tree in which a node without a sibling is carried up to the next level unchanged
+fn frontier_root(frontier: &[Option<CryptoHash>]) -> Option<CryptoHash> {
+    frontier
+        .iter()
+        .flatten()
+        .fold(None, |root, left| match root {
+            None => Some(*left),
+            Some(right) => Some(hash_pair(left, &right)),
+        })
+}
+
+impl Contract {
+    fn expect_snapshot(&self, snapshot_id: u64) -> Snapshot {
+        self.snapshots
+            .get(snapshot_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Snapshot not found"))
+    }
+
+    /// Counts up to `limit` more tokens for their owners, returns how many were counted
+    fn internal_snapshot_tokens(
+        &mut self,
+        snapshot: &mut Snapshot,
+        progress: &mut SnapshotProgress,
+        limit: u64,
+    ) -> u64 {
+        let snapshot_id = snapshot.snapshot_id.0;
+        let owners: Vec<(TokenId, AccountId)> = match &progress.last_token_id {
+            Some(last_token_id) => self
+                .owner_by_id
+                .range((Bound::Excluded(last_token_id.clone()), Bound::Unbounded))
+                .take(limit as usize)
+                .collect(),
+            None => self.owner_by_id.iter().take(limit as usize).collect(),
+        };
+
+        let mut holders = self
+            .snapshot_holders
+            .get(&snapshot_id)
+            .unwrap_or_else(|| Vector::new(StorageKey::SnapshotHoldersInner { snapshot_id }));
+        for (_, owner_id) in &owners {
+            let key = (snapshot_id, owner_id.clone());
+            let count = self.snapshot_balances.get(&key).unwrap_or(0);
+            if count == 0 {
+                holders.push(owner_id);
+            }
+            self.snapshot_balances.insert(&key, &(count + 1));
+        }
+        self.snapshot_holders.insert(&snapshot_id, &holders);
+
+        let counted = owners.len() as u64;
+        snapshot.token_count.0 += counted;
+        snapshot.holder_count = holders.len().into();
+        progress.tokens_done = counted < limit;
+        if let Some((last_token_id, _)) = owners.into_iter().last() {
+            progress.last_token_id = Some(last_token_id);
+        }
+        counted
+    }
+
+    /// Hashes up to `limit` more holders into the merkle tree, finishing the snapshot after the last
+    fn internal_snapshot_hash_holders(
+        &mut self,
+        snapshot: &mut Snapshot,
+        progress: &mut SnapshotProgress,
+        limit: u64,
+    ) {
+        let snapshot_id = snapshot.snapshot_id.0;
+        if let Some(holders) = self.snapshot_holders.get(&snapshot_id) {
+            let end = holders
+                .len()
+                .min(progress.holders_hashed.saturating_add(limit));
+            for index in progress.holders_hashed..end {
+                let account_id = holders.get(index).unwrap();
+                let count = self
+                    .snapshot_balances
+                    .get(&(snapshot_id, account_id.clone()))
+                    .unwrap_or(0);
+                frontier_push(&mut progress.frontier, snapshot_leaf(&account_id, count));
+            }
+            progress.holders_hashed = end;
+        }
+
+        if progress.holders_hashed == snapshot.holder_count.0 {
+            snapshot.finished = true;
+            snapshot.merkle_root = frontier_root(&progress.frontier).map(Base58CryptoHash::from);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Start a snapshot of the holders, to be taken with `nft_snapshot_step`. The attached
+    /// deposit is added to the owner's storage balance, which pays for the snapshot.
+    #[payable]
+    pub fn nft_snapshot_start(&mut self) -> U64 {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only owner can take snapshots",
+        );
+        let owner_id = self.owner_id.clone();
+        self.internal_storage_deposit(&owner_id, env::attached_deposit());
+
+        let initial_storage_usage = env::storage_usage();
+        let snapshot_id = self.snapshots.len();
+        self.snapshots.push(&Snapshot {
+            snapshot_id: snapshot_id.into(),
+            block_height: env::block_height().into(),
+            timestamp: env::block_timestamp().into(),
+            token_count: 0.into(),
+            holder_count: 0.into(),
+            finished: false,
+            merkle_root: None,
+        });
+        self.snapshot_progress.insert(
+            &snapshot_id,
+            &SnapshotProgress {
+                last_token_id: None,
+                tokens_done: false,
+                holders_hashed: 0,
+                frontier: vec![],
+            },
+        );
+        self.internal_storage_debit(&owner_id, env::storage_usage() - initial_storage_usage);
+        snapshot_id.into()
+    }
+
+    /// Count the owners of up to `limit` more tokens, then hash up to `limit` more holders into
+    /// the merkle root. Returns true once the snapshot is finished.
+    #[payable]
+    pub fn nft_snapshot_step(&mut self, snapshot_id: U64, limit: Option<u64>) -> bool {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only owner can take snapshots",
+        );
+        let limit = limit.unwrap_or(DEFAULT_SNAPSHOT_STEP_LIMIT);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+        let mut snapshot = self.expect_snapshot(snapshot_id.0);
+        let mut progress = if let Some(progress) = self.snapshot_progress.get(&snapshot_id.0) {
+            progress
+        } else {
+            return true;
+        };
+        let owner_id = self.owner_id.clone();
+        self.internal_storage_deposit(&owner_id, env::attached_deposit());
+
+        let initial_storage_usage = env::storage_usage();
+        let mut remaining = limit;
+        if !progress.tokens_done {
+            remaining -= self.internal_snapshot_tokens(&mut snapshot, &mut progress, limit);
+        }
+        if progress.tokens_done && remaining > 0 {
+            self.internal_snapshot_hash_holders(&mut snapshot, &mut progress, remaining);
+        }
+
+        if snapshot.finished {
+            self.snapshot_progress.remove(&snapshot_id.0);
+        } else {
+            self.snapshot_progress.insert(&snapshot_id.0, &progress);
+        }
+        self.snapshots.replace(snapshot_id.0, &snapshot);
+        self.internal_storage_debit(
+            &owner_id,
+            env::storage_usage().saturating_sub(initial_storage_usage),
+        );
+        snapshot.finished
+    }
+
+    /// get a snapshot
+    pub fn nft_snapshot(&self, snapshot_id: U64) -> Option<Snapshot> {
+        self.snapshots.get(snapshot_id.0)
+    }
+
+    /// Query for the snapshots, oldest first, using pagination
+    pub fn nft_snapshots(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Snapshot> {
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+        self.snapshots
+            .iter()
+            .skip(start_index as usize)
+            .take(limit)
+            .collect()
+    }
+
+    /// get the number of tokens an account held in a snapshot, so far if it isn't finished
+    pub fn nft_snapshot_balance_of(&self, snapshot_id: U64, account_id: AccountId) -> U64 {
+        self.snapshot_balances
+            .get(&(snapshot_id.0, account_id))
+            .unwrap_or(0)
+            .into()
+    }
+
+    /// Query for the holders in a snapshot, in the order of the merkle tree's leaves,
+    /// using pagination
+    pub fn nft_snapshot_holders(
+        &self,
+        snapshot_id: U64,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<SnapshotHolder> {
+        let holders = if let Some(holders) = self.snapshot_holders.get(&snapshot_id.0) {
+            holders
+        } else {
+            return vec![];
+        };
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+        holders
+            .iter()
+            .skip(start_index as usize)
+            .take(limit)
+            .map(|account_id| SnapshotHolder {
+                count: self.nft_snapshot_balance_of(snapshot_id, account_id.clone()),
+                account_id,
+            })
+            .collect()
+    }
+
+    /// get the merkle root of a finished snapshot over (account, count) of its holders
+    pub fn nft_snapshot_merkle_root(&self, snapshot_id: U64) -> Option<Base58CryptoHash> {
+        self.snapshots.get(snapshot_id.0)?.merkle_root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds the tree level by level, carrying a node without a sibling up unchanged
+    fn naive_root(leaves: &[CryptoHash]) -> Option<CryptoHash> {
+        let mut level = leaves.to_vec();
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_pair(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+        }
+        level.first().copied()
+    }
+
+    #[test]
+    fn frontier_matches_naive_tree() {
+        let leaves: Vec<CryptoHash> = (0..17u64)
+            .map(|count| snapshot_leaf(&"holder.near".parse().unwrap(), count))
+            .collect();
+        for n in 0..=leaves.len() {
+            let mut frontier = vec![];
+            for leaf in &leaves[..n] {
+                frontier_push(&mut frontier, *leaf);
+            }
+            assert_eq!(
+                frontier_root(&frontier),
+                naive_root(&leaves[..n]),
+                "{} leaves",
+                n
+            );
+        }
+    }
+
+    #[test]
+    fn single_leaf_is_the_root() {
+        let leaf = snapshot_leaf(&"holder.near".parse().unwrap(), 3);
+        let mut frontier = vec![];
+        frontier_push(&mut frontier, leaf);
+        assert_eq!(frontier_root(&frontier), Some(leaf));
+    }
+}
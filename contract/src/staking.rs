@@ -0,0 +1,267 @@
+use crate::*;
+use near_sdk::json_types::U64;
+
+/// Length of a staking day in nanoseconds
+const STAKING_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// How fast staked tokens earn points and how long they stay staked at least
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakingPolicy {
+    // points earned by a staked token per day
+    pub points_per_day: U128,
+    // nanoseconds a token must stay staked before it can be unstaked
+    pub min_lock: U64,
+}
+
+impl Default for StakingPolicy {
+    fn default() -> Self {
+        Self {
+            points_per_day: 1.into(),
+            min_lock: STAKING_DAY.into(),
+        }
+    }
+}
+
+/// A token staked by its owner, which keeps it but can't transfer or approve it
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Stake {
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    // nanoseconds since the epoch
+    pub staked_at: U64,
+}
+
+/// An account's staking, points are kept in point-nanoseconds per day to avoid rounding
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct StakingAccount {
+    pub staked_count: u64,
+    pub points: u128,
+    // value of the contract's staking accumulator already accounted for in `points`
+    pub paid_accumulator: u128,
+}
+
+/// Logs a staking event for `stake`
+fn emit_stake_event(variant: fn(Vec<NftStakeLog>) -> EventLogVariant, stake: &Stake) {
+    variant(vec![NftStakeLog {
+        owner_id: stake.owner_id.to_string(),
+        token_id: stake.token_id.clone(),
+    }])
+    .emit();
+}
+
+impl Contract {
+    /// Returns the point-nanoseconds per day a token staked since the first stake has earned
+    fn internal_staking_accumulator(&self) -> u128 {
+        let elapsed = env::block_timestamp().saturating_sub(self.staking_updated_at) as u128;
+        self.staking_accumulator + self.staking_policy.points_per_day.0 * elapsed
+    }
+
+    /// Returns an account's staking as of now
+    fn internal_staking_account(&self, account_id: &AccountId) -> StakingAccount {
+        let accumulator = self.internal_staking_accumulator();
+        let mut account = self.staking_accounts.get(account_id).unwrap_or_default();
+        account.points += account.staked_count as u128 * (accumulator - account.paid_accumulator);
+        account.paid_accumulator = accumulator;
+        account
+    }
+
+    /// Brings the accumulator up to now, so the policy can change without affecting the past
+    fn internal_update_staking_accumulator(&mut self) {
+        self.staking_accumulator = self.internal_staking_accumulator();
+        self.staking_updated_at = env::block_timestamp();
+    }
+
+    /// Unstakes a token if it is staked, crediting the storage back to its owner who keeps the
+    /// points it earned
+    pub(crate) fn internal_remove_stake(&mut self, token_id: &TokenId) {
+        let stake = if let Some(stake) = self.stakes.get(token_id) {
+            stake
+        } else {
+            return;
+        };
+        let initial_storage_usage = env::storage_usage();
+        let mut account = self.internal_staking_account(&stake.owner_id);
+        account.staked_count -= 1;
+        self.staking_accounts.insert(&stake.owner_id, &account);
+        self.stakes.remove(token_id);
+        internal_remove_from_token_set(&mut self.stakes_by_owner, &stake.owner_id, token_id);
+        self.internal_storage_credit(
+            &stake.owner_id,
+            initial_storage_usage.saturating_sub(env::storage_usage()),
+        );
+        emit_stake_event(EventLogVariant::NftUnstake, &stake);
+    }
+
+    /// Whether the token is staked, staked tokens can't be transferred or approved
+    pub(crate) fn internal_is_staked(&self, token_id: &TokenId) -> bool {
+        self.stakes.get(token_id).is_some()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Stake a token you own, it earns points until it is unstaked. The token can't be
+    /// transferred or approved while staked, and any sale or rental listing of it is removed.
+    #[payable]
+    pub fn nft_stake(&mut self, token_id: TokenId) {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+        require!(
+            self.owner_by_id.get(&token_id).as_ref() == Some(&owner_id),
+            "cypher: Predecessor must be token owner"
+        );
+        self.internal_assert_token_unlocked(&token_id);
+        if let Some(listing) = self.internal_remove_listing(&token_id) {
+            emit_listing_event(EventLogVariant::NftDelist, &listing);
+        }
+        if let Some(listing) = self.internal_remove_rental_listing(&token_id) {
+            emit_rental_event(EventLogVariant::NftRentalDelist, &listing);
+        }
+
+        let initial_storage_usage = env::storage_usage();
+        let mut account = self.internal_staking_account(&owner_id);
+        account.staked_count += 1;
+        self.staking_accounts.insert(&owner_id, &account);
+        let stake = Stake {
+            token_id,
+            owner_id,
+            staked_at: env::block_timestamp().into(),
+        };
+        self.stakes.insert(&stake.token_id, &stake);
+        internal_add_to_token_set(
+            &mut self.stakes_by_owner,
+            &stake.owner_id,
+            &stake.token_id,
+            |account_hash| StorageKey::StakesByOwnerInner { account_hash },
+        );
+        self.internal_storage_debit(
+            &stake.owner_id,
+            env::storage_usage().saturating_sub(initial_storage_usage),
+        );
+        emit_stake_event(EventLogVariant::NftStake, &stake);
+    }
+
+    /// Unstake a token you staked, once it has been staked for the minimum lock period.
+    /// The points it earned stay with you.
+    #[payable]
+    pub fn nft_unstake(&mut self, token_id: TokenId) {
+        assert_one_yocto();
+        let stake = self
+            .stakes
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("cypher: Token is not staked"));
+        require!(
+            stake.owner_id == env::predecessor_account_id(),
+            "cypher: Predecessor must be token owner"
+        );
+        require!(
+            env::block_timestamp() >= stake.staked_at.0 + self.staking_policy.min_lock.0,
+            "cypher: Token is still in its minimum lock period"
+        );
+
+        self.internal_remove_stake(&token_id);
+    }
+
+    /// Set the points a staked token earns per day and the minimum lock period, in nanoseconds.
+    /// Points earned so far are kept, the new rate applies from now on.
+    pub fn set_staking_policy(&mut self, policy: StakingPolicy) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only owner can set the staking policy",
+        );
+
+        self.internal_update_staking_accumulator();
+        self.staking_policy = policy;
+    }
+
+    /// get the points a staked token earns per day and the minimum lock period
+    pub fn staking_policy(&self) -> StakingPolicy {
+        self.staking_policy.clone()
+    }
+
+    /// get the stake of a token, if it is staked
+    pub fn nft_stake_of(&self, token_id: TokenId) -> Option<Stake> {
+        self.stakes.get(&token_id)
+    }
+
+    /// get the points an account has earned staking
+    pub fn nft_staking_points(&self, account_id: AccountId) -> U128 {
+        (self.internal_staking_account(&account_id).points / STAKING_DAY as u128).into()
+    }
+
+    /// Query for the tokens an account has staked using pagination
+    pub fn nft_staked_tokens(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Stake> {
+        let token_set = if let Some(token_set) = self.stakes_by_owner.get(&account_id) {
+            token_set
+        } else {
+            return vec![];
+        };
+        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "cypher: Cannot provide limit of 0.");
+        token_set
+            .iter()
+            .skip(start_index as usize)
+            .take(limit)
+            .filter_map(|token_id| self.stakes.get(&token_id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn stake(contract: &mut Contract, token_id: &str, owner_id: &str, timestamp: u64) {
+        call(&account(owner_id), 1, timestamp);
+        contract.nft_stake(token_id.to_string());
+    }
+
+    fn points_at(contract: &Contract, account_id: &str, timestamp: u64) -> u128 {
+        call(&account(account_id), 0, timestamp);
+        contract.nft_staking_points(account(account_id)).0
+    }
+
+    #[test]
+    fn policy_change_applies_from_then_on() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        mint(&mut contract, "1", "alice.near");
+        mint(&mut contract, "2", "bob.near");
+        stake(&mut contract, "1", "alice.near", 0);
+
+        call(&owner(), 0, 2 * STAKING_DAY);
+        contract.set_staking_policy(StakingPolicy {
+            points_per_day: 10.into(),
+            min_lock: STAKING_DAY.into(),
+        });
+        assert_eq!(points_at(&contract, "alice.near", 2 * STAKING_DAY), 2);
+
+        stake(&mut contract, "2", "bob.near", 3 * STAKING_DAY);
+        assert_eq!(points_at(&contract, "alice.near", 5 * STAKING_DAY), 2 + 30);
+        assert_eq!(points_at(&contract, "bob.near", 5 * STAKING_DAY), 20);
+
+        // unstaking keeps the points, the token stops earning
+        call(&account("alice.near"), 1, 5 * STAKING_DAY);
+        contract.nft_unstake("1".to_string());
+        assert_eq!(points_at(&contract, "alice.near", 9 * STAKING_DAY), 32);
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Token is still in its minimum lock period")]
+    fn unstaking_waits_for_the_minimum_lock() {
+        let mut contract = setup(&["alice.near"]);
+        mint(&mut contract, "1", "alice.near");
+        stake(&mut contract, "1", "alice.near", 0);
+        call(&account("alice.near"), 1, STAKING_DAY - 1);
+        contract.nft_unstake("1".to_string());
+    }
+}
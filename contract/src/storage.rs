@@ -0,0 +1,438 @@
+use crate::*;
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::{log, Promise};
+
+/// Storage paid for by an account registered through NEP-145
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccountStorage {
+    /// total yoctoNEAR deposited by the account
+    pub total: Balance,
+    /// bytes of contract storage charged to the account, registration included
+    pub used_bytes: u64,
+}
+
+impl AccountStorage {
+    /// yoctoNEAR locked to cover `used_bytes`
+    pub fn locked(&self) -> Balance {
+        Balance::from(self.used_bytes) * env::storage_byte_cost()
+    }
+
+    /// yoctoNEAR that can be withdrawn or spent on new storage
+    pub fn available(&self) -> Balance {
+        self.total.saturating_sub(self.locked())
+    }
+}
+
+impl Contract {
+    /// Measures the bytes taken by a storage record for the longest possible account ID
+    pub(crate) fn measure_storage_registration_bytes(&mut self) {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        self.storage_accounts.insert(
+            &tmp_account_id,
+            &AccountStorage {
+                total: 0,
+                used_bytes: 0,
+            },
+        );
+        self.storage_registration_bytes = env::storage_usage() - initial_storage_usage;
+        self.storage_accounts.remove(&tmp_account_id);
+    }
+
+    /// Returns the storage record of a registered account
+    pub(crate) fn internal_storage_account(&self, account_id: &AccountId) -> AccountStorage {
+        let account = self.storage_accounts.get(account_id);
+        require!(
+            account.is_some(),
+            format!("cypher: {} is not registered for storage", account_id)
+        );
+        account.unwrap()
+    }
+
+    /// Adds `amount` to the storage deposit of a registered account
+    pub(crate) fn internal_storage_deposit(&mut self, account_id: &AccountId, amount: Balance) {
+        if amount == 0 {
+            return;
+        }
+        let mut account = self.internal_storage_account(account_id);
+        account.total += amount;
+        self.storage_accounts.insert(account_id, &account);
+    }
+
+    /// Charges `bytes` of new storage to `account_id`, panics if the deposit doesn't cover it
+    pub(crate) fn internal_storage_debit(&mut self, account_id: &AccountId, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        let mut account = self.internal_storage_account(account_id);
+        account.used_bytes += bytes;
+        require!(
+            account.locked() <= account.total,
+            format!(
+                "cypher: {} must deposit {} more yoctoNEAR to cover storage",
+                account_id,
+                account.locked().saturating_sub(account.total)
+            )
+        );
+        self.storage_accounts.insert(account_id, &account);
+    }
+
+    /// Charges `bytes` of storage to `account_id` even if the deposit doesn't cover it.
+    /// Only used to restore state in callbacks, which must not fail.
+    pub(crate) fn internal_storage_debit_unchecked(&mut self, account_id: &AccountId, bytes: u64) {
+        if let Some(mut account) = self.storage_accounts.get(account_id) {
+            account.used_bytes += bytes;
+            self.storage_accounts.insert(account_id, &account);
+        }
+    }
+
+    /// Credits `bytes` of released storage back to the available balance of `account_id`
+    pub(crate) fn internal_storage_credit(&mut self, account_id: &AccountId, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        if let Some(mut account) = self.storage_accounts.get(account_id) {
+            account.used_bytes = account
+                .used_bytes
+                .saturating_sub(bytes)
+                .max(self.storage_registration_bytes);
+            self.storage_accounts.insert(account_id, &account);
+        }
+    }
+}
+
+#[near_bindgen]
+impl StorageManagement for Contract {
+    /// Deposits the attached NEAR into the storage balance of `account_id`, registering it if needed
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let registration_only = registration_only.unwrap_or(false);
+
+        if let Some(mut account) = self.storage_accounts.get(&account_id) {
+            if registration_only {
+                log!("cypher: {} is already registered", account_id);
+                if amount > 0 {
+                    Promise::new(env::predecessor_account_id()).transfer(amount);
+                }
+            } else {
+                account.total += amount;
+                self.storage_accounts.insert(&account_id, &account);
+            }
+        } else {
+            let min_balance = self.storage_balance_bounds().min.0;
+            require!(
+                amount >= min_balance,
+                "cypher: The attached deposit is less than the minimum storage balance"
+            );
+            let total = if registration_only {
+                let refund = amount - min_balance;
+                if refund > 0 {
+                    Promise::new(env::predecessor_account_id()).transfer(refund);
+                }
+                min_balance
+            } else {
+                amount
+            };
+            self.storage_accounts.insert(
+                &account_id,
+                &AccountStorage {
+                    total,
+                    used_bytes: self.storage_registration_bytes,
+                },
+            );
+        }
+        self.storage_balance_of(account_id).unwrap()
+    }
+
+    /// Withdraws `amount` (all by default) of the caller's available storage balance
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut account = self.internal_storage_account(&account_id);
+        let available = account.available();
+        let amount = amount.map(|amount| amount.0).unwrap_or(available);
+        require!(
+            amount <= available,
+            "cypher: The amount is greater than the available storage balance"
+        );
+        if amount > 0 {
+            account.total -= amount;
+            self.storage_accounts.insert(&account_id, &account);
+            Promise::new(account_id.clone()).transfer(amount);
+        }
+        self.storage_balance_of(account_id).unwrap()
+    }
+
+    /// Unregisters the caller and returns their deposit. With `force`, storage still
+    /// charged to the caller (e.g. for tokens they minted) is forfeited.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let account = if let Some(account) = self.storage_accounts.get(&account_id) {
+            account
+        } else {
+            log!("cypher: {} is not registered", account_id);
+            return false;
+        };
+
+        require!(
+            self.nft_supply_for_owner(account_id.clone()).0 == 0,
+            "cypher: Can't unregister an account that owns tokens"
+        );
+        require!(
+            force.unwrap_or(false) || account.used_bytes <= self.storage_registration_bytes,
+            "cypher: Can't unregister an account with storage in use, use force to forfeit it"
+        );
+
+        self.storage_accounts.remove(&account_id);
+        let refund = account.total.saturating_sub(
+            Balance::from(account.used_bytes - self.storage_registration_bytes)
+                * env::storage_byte_cost(),
+        );
+        if refund > 0 {
+            Promise::new(account_id).transfer(refund);
+        }
+        true
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: (Balance::from(self.storage_registration_bytes) * env::storage_byte_cost()).into(),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_accounts
+            .get(&account_id)
+            .map(|account| StorageBalance {
+                total: account.total.into(),
+                available: account.available().into(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::get_created_receipts;
+
+    fn used_bytes(contract: &Contract, account_id: &str) -> u64 {
+        contract
+            .internal_storage_account(&account(account_id))
+            .used_bytes
+    }
+
+    /// yoctoNEAR sent to `account_id` by the last call
+    fn refunded(account_id: &str) -> Balance {
+        get_created_receipts()
+            .into_iter()
+            .filter(|receipt| receipt.receiver_id == account(account_id))
+            .flat_map(|receipt| receipt.actions)
+            .map(|action| match action {
+                VmAction::Transfer { deposit } => deposit,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn deposit_registers_and_tops_up() {
+        let mut contract = setup(&[]);
+        let min = contract.storage_balance_bounds().min.0;
+
+        call(&account("alice.near"), min, 0);
+        let balance = contract.storage_deposit(None, None);
+        assert_eq!(balance.total.0, min);
+        assert_eq!(balance.available.0, 0);
+
+        call(&account("alice.near"), NEAR, 0);
+        let balance = contract.storage_deposit(None, None);
+        assert_eq!(balance.total.0, min + NEAR);
+        assert_eq!(balance.available.0, NEAR);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "cypher: The attached deposit is less than the minimum storage balance"
+    )]
+    fn deposit_below_the_minimum_is_refused() {
+        let mut contract = setup(&[]);
+        let min = contract.storage_balance_bounds().min.0;
+        call(&account("alice.near"), min - 1, 0);
+        contract.storage_deposit(None, None);
+    }
+
+    #[test]
+    fn registration_only_refunds_the_excess() {
+        let mut contract = setup(&[]);
+        let min = contract.storage_balance_bounds().min.0;
+
+        call(&account("alice.near"), NEAR, 0);
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total.0, min);
+        assert_eq!(refunded("alice.near"), NEAR - min);
+
+        call(&account("alice.near"), NEAR, 0);
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total.0, min);
+        assert_eq!(refunded("alice.near"), NEAR);
+    }
+
+    #[test]
+    fn withdraw_returns_the_available_balance() {
+        let mut contract = setup(&["alice.near"]);
+        let available = contract
+            .storage_balance_of(account("alice.near"))
+            .unwrap()
+            .available
+            .0;
+
+        call(&account("alice.near"), 1, 0);
+        let balance = contract.storage_withdraw(Some(U128(available / 2)));
+        assert_eq!(balance.available.0, available - available / 2);
+        assert_eq!(refunded("alice.near"), available / 2);
+
+        call(&account("alice.near"), 1, 0);
+        let balance = contract.storage_withdraw(None);
+        assert_eq!(balance.available.0, 0);
+        assert_eq!(refunded("alice.near"), available - available / 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: The amount is greater than the available storage balance")]
+    fn withdraw_beyond_the_available_balance_is_refused() {
+        let mut contract = setup(&["alice.near"]);
+        mint(&mut contract, "1", "alice.near");
+        let available = contract.storage_balance_of(owner()).unwrap().available.0;
+        call(&owner(), 1, 0);
+        contract.storage_withdraw(Some(U128(available + 1)));
+    }
+
+    #[test]
+    fn unregister_refunds_the_whole_deposit() {
+        let mut contract = setup(&["alice.near"]);
+
+        call(&account("alice.near"), 1, 0);
+        assert!(contract.storage_unregister(None));
+        assert_eq!(refunded("alice.near"), NEAR);
+        assert!(contract.storage_balance_of(account("alice.near")).is_none());
+
+        call(&account("alice.near"), 1, 0);
+        assert!(!contract.storage_unregister(None));
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Can't unregister an account with storage in use")]
+    fn unregister_with_storage_in_use_needs_force() {
+        let mut contract = setup(&["alice.near"]);
+        // the owner pays for the token it mints to alice
+        mint(&mut contract, "1", "alice.near");
+        call(&owner(), 1, 0);
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    fn forced_unregister_forfeits_the_storage_in_use() {
+        let mut contract = setup(&["alice.near"]);
+        mint(&mut contract, "1", "alice.near");
+        let storage = contract.internal_storage_account(&owner());
+        let in_use = Balance::from(storage.used_bytes - contract.storage_registration_bytes)
+            * env::storage_byte_cost();
+        assert!(in_use > 0);
+
+        call(&owner(), 1, 0);
+        assert!(contract.storage_unregister(Some(true)));
+        assert_eq!(refunded("owner.near"), NEAR - in_use);
+        assert!(contract.storage_balance_of(owner()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: Can't unregister an account that owns tokens")]
+    fn unregister_an_account_that_owns_tokens_is_refused() {
+        let mut contract = setup(&["alice.near"]);
+        mint(&mut contract, "1", "alice.near");
+        call(&account("alice.near"), 1, 0);
+        contract.storage_unregister(Some(true));
+    }
+
+    #[test]
+    fn mint_approve_and_transfer_move_storage_between_accounts() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        let registration = contract.storage_registration_bytes;
+
+        mint(&mut contract, "1", "alice.near");
+        assert!(used_bytes(&contract, "owner.near") > registration);
+        assert_eq!(used_bytes(&contract, "alice.near"), registration);
+
+        call(&account("alice.near"), 1, 0);
+        contract.nft_approve("1".to_string(), account("market.near"), None, None);
+        assert_eq!(
+            used_bytes(&contract, "alice.near"),
+            registration + bytes_for_approved_account_id(&account("market.near"))
+        );
+
+        transfer(&mut contract, "1", "bob.near", 0);
+        assert_eq!(used_bytes(&contract, "alice.near"), registration);
+        assert!(used_bytes(&contract, "bob.near") > registration);
+    }
+
+    #[test]
+    fn approve_deposit_tops_up_the_storage_balance() {
+        let mut contract = setup(&["alice.near"]);
+        mint(&mut contract, "1", "alice.near");
+        call(&account("alice.near"), NEAR, 0);
+        contract.nft_approve("1".to_string(), account("market.near"), None, None);
+        assert_eq!(
+            contract
+                .storage_balance_of(account("alice.near"))
+                .unwrap()
+                .total
+                .0,
+            2 * NEAR
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "cypher: carol.near is not registered for storage")]
+    fn transfer_receivers_must_be_registered() {
+        let mut contract = setup(&["alice.near"]);
+        mint(&mut contract, "1", "alice.near");
+        transfer(&mut contract, "1", "carol.near", 0);
+    }
+
+    #[test]
+    fn transfer_round_trip_leaves_the_balances_unchanged() {
+        let mut contract = setup(&["alice.near", "bob.near"]);
+        let registration = contract.storage_registration_bytes;
+        mint(&mut contract, "1", "alice.near");
+        transfer(&mut contract, "1", "bob.near", 0);
+        let bob_holding = used_bytes(&contract, "bob.near");
+        transfer(&mut contract, "1", "alice.near", 0);
+        let alice = used_bytes(&contract, "alice.near");
+        let bob = used_bytes(&contract, "bob.near");
+        // bob gives back what he paid to hold the token, but keeps paying for the transfer
+        // count his first receipt created
+        assert!(bob < bob_holding);
+        assert!(bob > registration);
+        assert!(alice > registration);
+
+        transfer(&mut contract, "1", "bob.near", 0);
+        assert_eq!(used_bytes(&contract, "bob.near"), bob_holding);
+        transfer(&mut contract, "1", "alice.near", 0);
+        assert_eq!(used_bytes(&contract, "alice.near"), alice);
+        assert_eq!(used_bytes(&contract, "bob.near"), bob);
+    }
+}
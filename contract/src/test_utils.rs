@@ -0,0 +1,95 @@
+use crate::*;
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::test_utils::VMContextBuilder;
+use near_sdk::testing_env;
+
+pub const NEAR: Balance = 10u128.pow(24);
+
+pub fn account(id: &str) -> AccountId {
+    id.parse().unwrap()
+}
+
+pub fn owner() -> AccountId {
+    account("owner.near")
+}
+
+/// Makes the next calls come from `predecessor_id` with `deposit` attached at `timestamp`.
+/// Contract storage is kept between calls.
+pub fn call(predecessor_id: &AccountId, deposit: Balance, timestamp: u64) {
+    testing_env!(VMContextBuilder::new()
+        .current_account_id(account("cypher.near"))
+        .predecessor_account_id(predecessor_id.clone())
+        .signer_account_id(predecessor_id.clone())
+        .attached_deposit(deposit)
+        .block_timestamp(timestamp)
+        .account_balance(1_000_000 * NEAR)
+        .build());
+}
+
+/// Makes the next call a callback of the contract on itself, seeing `results` as the results
+/// of the promises it was chained on
+pub fn callback(results: Vec<PromiseResult>) {
+    let context = VMContextBuilder::new()
+        .current_account_id(account("cypher.near"))
+        .predecessor_account_id(account("cypher.near"))
+        .signer_account_id(account("cypher.near"))
+        .account_balance(1_000_000 * NEAR)
+        .build();
+    testing_env!(
+        context,
+        near_sdk::VMConfig::test(),
+        near_sdk::RuntimeFeesConfig::test(),
+        Default::default(),
+        results
+    );
+}
+
+/// A contract owned by `owner.near`, with every account in `account_ids` registered for storage
+pub fn setup(account_ids: &[&str]) -> Contract {
+    call(&owner(), 0, 0);
+    let mut contract = Contract::new_default_meta(owner());
+    for account_id in account_ids.iter().copied().map(account).chain([owner()]) {
+        call(&account_id, NEAR, 0);
+        contract.storage_deposit(None, None);
+    }
+    contract
+}
+
+pub fn metadata() -> TokenMetadata {
+    TokenMetadata {
+        title: None,
+        description: None,
+        media: None,
+        media_hash: None,
+        copies: None,
+        issued_at: None,
+        expires_at: None,
+        starts_at: None,
+        updated_at: None,
+        extra: None,
+        reference: None,
+        reference_hash: None,
+    }
+}
+
+/// Mints `token_id` to `owner_id` as the contract owner
+pub fn mint(contract: &mut Contract, token_id: &str, owner_id: &str) {
+    call(&owner(), 0, 0);
+    contract.nft_mint(
+        token_id.to_string(),
+        metadata(),
+        account(owner_id),
+        None,
+        None,
+    );
+}
+
+pub fn owner_of(contract: &Contract, token_id: &str) -> AccountId {
+    contract.owner_by_id.get(&token_id.to_string()).unwrap()
+}
+
+/// Transfers `token_id` from its owner to `receiver_id` at `timestamp`
+pub fn transfer(contract: &mut Contract, token_id: &str, receiver_id: &str, timestamp: u64) {
+    call(&owner_of(contract, token_id), 1, timestamp);
+    contract.nft_transfer(account(receiver_id), token_id.to_string(), None, None);
+}
@@ -0,0 +1,214 @@
+//! Checking the replayed model against dumps of the contract's views
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use nft_cypher::TokenJson;
+
+use crate::{Model, TokenState};
+
+/// A difference between the model and what the contract returned
+#[derive(Debug, PartialEq)]
+pub enum Drift {
+    /// The dump has a token the events never minted
+    MissingFromModel { token_id: String },
+    /// The events say a token exists that the dump doesn't have
+    MissingFromDump { token_id: String },
+    Owner {
+        token_id: String,
+        model: String,
+        dump: String,
+    },
+    Approvals {
+        token_id: String,
+        model: BTreeMap<String, u64>,
+        dump: BTreeMap<String, u64>,
+    },
+    Royalty {
+        token_id: String,
+        model: BTreeMap<String, u32>,
+        dump: BTreeMap<String, u32>,
+    },
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::MissingFromModel { token_id } => {
+                write!(f, "token {}: in the dump but never minted", token_id)
+            }
+            Drift::MissingFromDump { token_id } => {
+                write!(f, "token {}: minted but missing from the dump", token_id)
+            }
+            Drift::Owner {
+                token_id,
+                model,
+                dump,
+            } => write!(
+                f,
+                "token {}: owner {} in the model, {} in the dump",
+                token_id, model, dump
+            ),
+            Drift::Approvals {
+                token_id,
+                model,
+                dump,
+            } => write!(
+                f,
+                "token {}: approvals {:?} in the model, {:?} in the dump",
+                token_id, model, dump
+            ),
+            Drift::Royalty {
+                token_id,
+                model,
+                dump,
+            } => write!(
+                f,
+                "token {}: royalty {:?} in the model, {:?} in the dump",
+                token_id, model, dump
+            ),
+        }
+    }
+}
+
+/// Compares the model with a dump of `nft_tokens`, or of `nft_tokens_for_owner` if `owner_id` is
+/// given, in which case only that account's tokens are compared. Royalties are only compared for
+/// tokens whose royalty the model knows.
+pub fn diff(model: &Model, dump: &[TokenJson], owner_id: Option<&str>) -> Vec<Drift> {
+    let dumped: BTreeMap<&str, &TokenJson> = dump
+        .iter()
+        .map(|token| (token.token_id.as_str(), token))
+        .collect();
+    let modelled: BTreeMap<&str, &TokenState> = model
+        .tokens
+        .iter()
+        .filter(|(_, token)| !matches!(owner_id, Some(owner_id) if token.owner_id != owner_id))
+        .map(|(token_id, token)| (token_id.as_str(), token))
+        .collect();
+
+    let mut drifts = Vec::new();
+    for (token_id, token) in &dumped {
+        // with an owner dump, a token owned by someone else in the model is an owner drift
+        match model.tokens.get(*token_id) {
+            None => drifts.push(Drift::MissingFromModel {
+                token_id: token_id.to_string(),
+            }),
+            Some(state) => diff_token(token_id, state, token, &mut drifts),
+        }
+    }
+    for token_id in modelled.keys() {
+        if !dumped.contains_key(token_id) {
+            drifts.push(Drift::MissingFromDump {
+                token_id: token_id.to_string(),
+            });
+        }
+    }
+    drifts
+}
+
+fn diff_token(token_id: &str, state: &TokenState, token: &TokenJson, drifts: &mut Vec<Drift>) {
+    if state.owner_id != token.owner_id.as_str() {
+        drifts.push(Drift::Owner {
+            token_id: token_id.to_string(),
+            model: state.owner_id.clone(),
+            dump: token.owner_id.to_string(),
+        });
+    }
+
+    let approvals: BTreeMap<String, u64> = token
+        .approved_account_ids
+        .iter()
+        .flatten()
+        .map(|(account_id, approval_id)| (account_id.to_string(), *approval_id))
+        .collect();
+    if state.approved_account_ids != approvals {
+        drifts.push(Drift::Approvals {
+            token_id: token_id.to_string(),
+            model: state.approved_account_ids.clone(),
+            dump: approvals,
+        });
+    }
+
+    if let Some(model_royalty) = &state.royalty {
+        let royalty: BTreeMap<String, u32> = token
+            .royalty
+            .iter()
+            .flatten()
+            .map(|(account_id, basis_points)| (account_id.to_string(), *basis_points))
+            .collect();
+        if model_royalty != &royalty {
+            drifts.push(Drift::Royalty {
+                token_id: token_id.to_string(),
+                model: model_royalty.clone(),
+                dump: royalty,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_log, read_token_dump};
+
+    fn dump(json: &str) -> Vec<TokenJson> {
+        read_token_dump(json.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn matching_dump_has_no_drift() {
+        let tokens = dump(
+            r#"[{"token_id":"1","owner_id":"user1.near","metadata":null,"royalty":{"owner.near":150},"approved_account_ids":{}}]"#,
+        );
+        let model = Model::from_tokens(&tokens);
+        assert!(diff(&model, &tokens, None).is_empty());
+        assert!(diff(&model, &tokens, Some("user1.near")).is_empty());
+    }
+
+    #[test]
+    fn reports_each_kind_of_drift() {
+        let model = Model::from_tokens(&dump(
+            r#"[{"token_id":"1","owner_id":"user1.near","metadata":null,"royalty":{"owner.near":150},"approved_account_ids":{}},
+                {"token_id":"2","owner_id":"user1.near","metadata":null,"royalty":null,"approved_account_ids":{}}]"#,
+        ));
+        let tokens = dump(
+            r#"[{"token_id":"1","owner_id":"user2.near","metadata":null,"royalty":{"owner.near":300},"approved_account_ids":{"market.near":1}}]
+               [{"token_id":"3","owner_id":"user1.near","metadata":null,"royalty":null,"approved_account_ids":null}]"#,
+        );
+        let drifts = diff(&model, &tokens, None);
+        assert_eq!(drifts.len(), 5);
+        assert!(drifts.contains(&Drift::Owner {
+            token_id: "1".to_string(),
+            model: "user1.near".to_string(),
+            dump: "user2.near".to_string(),
+        }));
+        assert!(drifts.contains(&Drift::MissingFromModel {
+            token_id: "3".to_string()
+        }));
+        assert!(drifts.contains(&Drift::MissingFromDump {
+            token_id: "2".to_string()
+        }));
+    }
+
+    #[test]
+    fn refused_transfer_call_keeps_its_approvals() {
+        // logged by nft_approve twice, nft_transfer_call, then nft_resolve_transfer when the
+        // receiver refused the token
+        let mut model = Model::default();
+        for line in [
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_mint","data":[{"owner_id":"user1.near","token_ids":["1"]}]}"#,
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_approve","data":[{"owner_id":"user1.near","token_id":"1","account_id":"market.near","approval_id":1}]}"#,
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_approve","data":[{"owner_id":"user1.near","token_id":"1","account_id":"other.near","approval_id":2}]}"#,
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_transfer","data":[{"old_owner_id":"user1.near","new_owner_id":"receiver.near","token_ids":["1"]}]}"#,
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_transfer","data":[{"old_owner_id":"receiver.near","new_owner_id":"user1.near","token_ids":["1"]}]}"#,
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_approve","data":[{"owner_id":"user1.near","token_id":"1","account_id":"market.near","approval_id":1},{"owner_id":"user1.near","token_id":"1","account_id":"other.near","approval_id":2}]}"#,
+        ] {
+            model.apply(&parse_log(line).unwrap().unwrap().event);
+        }
+        let tokens = dump(
+            r#"[{"token_id":"1","owner_id":"user1.near","metadata":null,"royalty":null,"approved_account_ids":{"market.near":1,"other.near":2}}]"#,
+        );
+        assert!(model.anomalies.is_empty());
+        assert!(diff(&model, &tokens, None).is_empty());
+    }
+}
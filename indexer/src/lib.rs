@@ -0,0 +1,48 @@
+//! Replays the events logged by the Cypher NFT contract into an off-chain model of its tokens,
+//! and checks that model against what the contract's views return.
+//!
+//! Events are parsed with the contract's own [`EventLog`](nft_cypher::EventLog) types, so that
+//! the indexer and the contract can't disagree on their format.
+
+pub mod diff;
+pub mod model;
+pub mod parse;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+pub use crate::diff::*;
+pub use crate::model::*;
+pub use crate::parse::*;
+
+use std::fmt;
+use std::io;
+
+/// Errors reading events or token dumps
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::Json(err) => write!(f, "invalid json: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
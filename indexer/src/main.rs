@@ -0,0 +1,155 @@
+//! Command line for replaying the contract's events and checking them against its views.
+//!
+//! ```text
+//! cypher-indexer replay [OPTIONS] [FILE...]
+//! cypher-indexer diff --dump DUMP [--owner ACCOUNT] [OPTIONS] [FILE...]
+//!
+//! Events are read from the FILEs, or stdin if there are none or FILE is "-".
+//!
+//! Options:
+//!   --rpc              inputs are JSON-RPC transaction statuses instead of log lines
+//!   --contract ID      with --rpc, only read the logs of receipts executed by ID
+//!   --base DUMP        start from a token dump instead of an empty model
+//!   --sqlite PATH      also write the model to SQLite (with the `sqlite` feature)
+//! ```
+//!
+//! `replay` prints the model as JSON. `diff` prints every drift between the model and DUMP, a
+//! dump of `nft_tokens` or, with `--owner`, of `nft_tokens_for_owner`, and exits with 1 if
+//! there are any or if the replay ran into anomalies.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::process;
+
+use nft_cypher::EventLog;
+use nft_cypher_indexer::{diff, read_log_lines, read_rpc_responses, read_token_dump, Error, Model};
+
+const USAGE: &str = "usage: cypher-indexer (replay | diff --dump DUMP [--owner ACCOUNT]) \
+[--rpc] [--contract ID] [--base DUMP] [--sqlite PATH] [FILE...]";
+
+#[derive(Default)]
+struct Options {
+    command: String,
+    rpc: bool,
+    contract_id: Option<String>,
+    base: Option<String>,
+    dump: Option<String>,
+    owner_id: Option<String>,
+    sqlite: Option<String>,
+    inputs: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        command: args.next().ok_or_else(|| USAGE.to_string())?,
+        ..Options::default()
+    };
+    if options.command != "replay" && options.command != "diff" {
+        return Err(USAGE.to_string());
+    }
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--rpc" => options.rpc = true,
+            "--contract" => options.contract_id = Some(value("--contract")?),
+            "--base" => options.base = Some(value("--base")?),
+            "--dump" => options.dump = Some(value("--dump")?),
+            "--owner" => options.owner_id = Some(value("--owner")?),
+            "--sqlite" => options.sqlite = Some(value("--sqlite")?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            _ => options.inputs.push(arg),
+        }
+    }
+    if options.command == "diff" && options.dump.is_none() {
+        return Err("diff needs --dump".to_string());
+    }
+    Ok(options)
+}
+
+fn open(path: &str) -> Result<Box<dyn Read>, Error> {
+    if path == "-" {
+        Ok(Box::new(io::stdin()))
+    } else {
+        Ok(Box::new(File::open(path)?))
+    }
+}
+
+fn read_events(options: &Options) -> Result<Vec<EventLog>, Error> {
+    let inputs = if options.inputs.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        options.inputs.clone()
+    };
+    let mut events = Vec::new();
+    for input in &inputs {
+        let reader = open(input)?;
+        if options.rpc {
+            events.extend(read_rpc_responses(reader, options.contract_id.as_deref())?);
+        } else {
+            events.extend(read_log_lines(BufReader::new(reader))?);
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(feature = "sqlite")]
+fn write_sqlite(model: &Model, path: &str) -> Result<(), String> {
+    nft_cypher_indexer::sqlite::write_model(model, std::path::Path::new(path))
+        .map_err(|err| format!("sqlite: {}", err))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn write_sqlite(_model: &Model, _path: &str) -> Result<(), String> {
+    Err("--sqlite needs the indexer built with the `sqlite` feature".to_string())
+}
+
+fn run(options: Options) -> Result<bool, String> {
+    let mut model = match &options.base {
+        Some(base) => {
+            let tokens = open(base)
+                .and_then(read_token_dump)
+                .map_err(|err| format!("{}: {}", base, err))?;
+            Model::from_tokens(&tokens)
+        }
+        None => Model::default(),
+    };
+    for event in read_events(&options).map_err(|err| err.to_string())? {
+        model.apply(&event.event);
+    }
+    for anomaly in &model.anomalies {
+        eprintln!("anomaly: {}", anomaly);
+    }
+    if let Some(path) = &options.sqlite {
+        write_sqlite(&model, path)?;
+    }
+
+    match &options.dump {
+        Some(dump) if options.command == "diff" => {
+            let tokens = open(dump)
+                .and_then(read_token_dump)
+                .map_err(|err| format!("{}: {}", dump, err))?;
+            let drifts = diff(&model, &tokens, options.owner_id.as_deref());
+            for drift in &drifts {
+                println!("{}", drift);
+            }
+            Ok(drifts.is_empty() && model.anomalies.is_empty())
+        }
+        _ => {
+            let json = serde_json::to_string_pretty(&model).map_err(|err| err.to_string())?;
+            println!("{}", json);
+            Ok(true)
+        }
+    }
+}
+
+fn main() {
+    let result = parse_args(std::env::args().skip(1)).and_then(run);
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! In-memory model of the contract's tokens, rebuilt by replaying its events
+
+use std::collections::BTreeMap;
+
+use nft_cypher::{EventLogVariant, TokenJson};
+use serde::Serialize;
+
+/// What the events say about a token
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TokenState {
+    pub owner_id: String,
+    pub approved_account_ids: BTreeMap<String, u64>,
+    /// Basis points per recipient. Mint events don't carry royalties, so this is only known for
+    /// tokens the model was seeded with.
+    pub royalty: Option<BTreeMap<String, u32>>,
+}
+
+/// The tokens of the contract as seen through its events
+#[derive(Debug, Default, Serialize)]
+pub struct Model {
+    pub tokens: BTreeMap<String, TokenState>,
+    /// Events that didn't fit the model, e.g. a transfer from an account that didn't own the token
+    pub anomalies: Vec<String>,
+    pub events_applied: u64,
+}
+
+impl Model {
+    /// Starts from a dump of the contract's tokens, to replay only the events logged after it
+    pub fn from_tokens(tokens: &[TokenJson]) -> Self {
+        let tokens = tokens
+            .iter()
+            .map(|token| {
+                let state = TokenState {
+                    owner_id: token.owner_id.to_string(),
+                    approved_account_ids: token
+                        .approved_account_ids
+                        .iter()
+                        .flatten()
+                        .map(|(account_id, approval_id)| (account_id.to_string(), *approval_id))
+                        .collect(),
+                    royalty: token.royalty.as_ref().map(|royalty| {
+                        royalty
+                            .iter()
+                            .map(|(account_id, basis_points)| {
+                                (account_id.to_string(), *basis_points)
+                            })
+                            .collect()
+                    }),
+                };
+                (token.token_id.clone(), state)
+            })
+            .collect();
+        Self {
+            tokens,
+            ..Self::default()
+        }
+    }
+
+    /// Tokens owned by `owner_id`
+    pub fn tokens_for_owner<'a>(
+        &'a self,
+        owner_id: &'a str,
+    ) -> impl Iterator<Item = (&'a String, &'a TokenState)> + 'a {
+        self.tokens
+            .iter()
+            .filter(move |(_, token)| token.owner_id == owner_id)
+    }
+
+    /// Applies the changes an event makes to owners, approvals and royalties.
+    /// Market, auction and offer events leave the model alone, the transfers they cause are
+    /// logged as transfer events of their own.
+    pub fn apply(&mut self, event: &EventLogVariant) {
+        self.events_applied += 1;
+        match event {
+            EventLogVariant::NftMint(logs) => {
+                for log in logs {
+                    for token_id in &log.token_ids {
+                        let state = TokenState {
+                            owner_id: log.owner_id.clone(),
+                            approved_account_ids: BTreeMap::new(),
+                            royalty: None,
+                        };
+                        if self.tokens.insert(token_id.clone(), state).is_some() {
+                            self.anomaly(format!("token {} minted twice", token_id));
+                        }
+                    }
+                }
+            }
+            EventLogVariant::NftTransfer(logs) => {
+                for log in logs {
+                    for token_id in &log.token_ids {
+                        let owner_id = self.owner_of(token_id, "transferred");
+                        match owner_id {
+                            Some(owner_id) if owner_id != log.old_owner_id => {
+                                self.anomaly(format!(
+                                    "token {} transferred from {} but owned by {}",
+                                    token_id, log.old_owner_id, owner_id
+                                ))
+                            }
+                            _ => {}
+                        }
+                        if let Some(token) = self.tokens.get_mut(token_id) {
+                            token.owner_id = log.new_owner_id.clone();
+                            token.approved_account_ids.clear();
+                        }
+                    }
+                }
+            }
+            EventLogVariant::NftBurn(logs) => {
+                for log in logs {
+                    for token_id in &log.token_ids {
+                        if self.tokens.remove(token_id).is_none() {
+                            self.anomaly(format!("unknown token {} burnt", token_id));
+                        }
+                    }
+                }
+            }
+            EventLogVariant::NftApprove(logs) => {
+                for log in logs {
+                    if let Some(token) = self.token_mut(&log.token_id, "approved") {
+                        token
+                            .approved_account_ids
+                            .insert(log.account_id.clone(), log.approval_id);
+                    }
+                }
+            }
+            EventLogVariant::NftRevoke(logs) => {
+                for log in logs {
+                    if let Some(token) = self.token_mut(&log.token_id, "revoked") {
+                        token.approved_account_ids.remove(&log.account_id);
+                    }
+                }
+            }
+            EventLogVariant::NftRevokeAll(logs) => {
+                for log in logs {
+                    if let Some(token) = self.token_mut(&log.token_id, "revoked") {
+                        token.approved_account_ids.clear();
+                    }
+                }
+            }
+            EventLogVariant::NftRoyaltyUpdate(logs) => {
+                for log in logs {
+                    self.apply_royalty_update(
+                        &log.token_id,
+                        &log.old_account_id,
+                        &log.new_account_id,
+                        log.basis_points,
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn apply_royalty_update(
+        &mut self,
+        token_id: &str,
+        old_account_id: &str,
+        new_account_id: &str,
+        basis_points: u32,
+    ) {
+        let royalty = match self.token_mut(token_id, "royalty updated") {
+            Some(TokenState {
+                royalty: Some(royalty),
+                ..
+            }) => royalty,
+            _ => return,
+        };
+        let share = royalty.get(old_account_id).copied().unwrap_or(0);
+        if share < basis_points {
+            let message = format!(
+                "token {}: {} moved {} basis points of royalty but had {}",
+                token_id, old_account_id, basis_points, share
+            );
+            self.anomaly(message);
+            return;
+        }
+        if share == basis_points {
+            royalty.remove(old_account_id);
+        } else {
+            royalty.insert(old_account_id.to_string(), share - basis_points);
+        }
+        *royalty.entry(new_account_id.to_string()).or_insert(0) += basis_points;
+    }
+
+    fn owner_of(&mut self, token_id: &str, action: &str) -> Option<String> {
+        self.token_mut(token_id, action)
+            .map(|token| token.owner_id.clone())
+    }
+
+    fn token_mut(&mut self, token_id: &str, action: &str) -> Option<&mut TokenState> {
+        if !self.tokens.contains_key(token_id) {
+            self.anomaly(format!("unknown token {} {}", token_id, action));
+        }
+        self.tokens.get_mut(token_id)
+    }
+
+    fn anomaly(&mut self, message: String) {
+        self.anomalies.push(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_log;
+
+    fn replay(lines: &[&str]) -> Model {
+        let mut model = Model::default();
+        for line in lines {
+            model.apply(&parse_log(line).unwrap().unwrap().event);
+        }
+        model
+    }
+
+    #[test]
+    fn replays_ownership_and_approvals() {
+        let model = replay(&[
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_mint","data":[{"owner_id":"user1.near","token_ids":["1","2"]}]}"#,
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_approve","data":[{"owner_id":"user1.near","token_id":"1","account_id":"market.near","approval_id":1}]}"#,
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_approve","data":[{"owner_id":"user1.near","token_id":"2","account_id":"market.near","approval_id":1}]}"#,
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_transfer","data":[{"authorized_id":"market.near","old_owner_id":"user1.near","new_owner_id":"user2.near","token_ids":["1"]}]}"#,
+        ]);
+        assert!(model.anomalies.is_empty());
+        assert_eq!(model.tokens["1"].owner_id, "user2.near");
+        assert!(model.tokens["1"].approved_account_ids.is_empty());
+        assert_eq!(model.tokens["2"].approved_account_ids["market.near"], 1);
+        assert_eq!(model.tokens_for_owner("user1.near").count(), 1);
+    }
+
+    #[test]
+    fn reports_transfers_that_dont_fit() {
+        let model = replay(&[
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_mint","data":[{"owner_id":"user1.near","token_ids":["1"]}]}"#,
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_transfer","data":[{"old_owner_id":"user3.near","new_owner_id":"user2.near","token_ids":["1","9"]}]}"#,
+        ]);
+        assert_eq!(
+            model.anomalies,
+            vec![
+                "token 1 transferred from user3.near but owned by user1.near".to_string(),
+                "unknown token 9 transferred".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn moves_royalty_shares_of_seeded_tokens() {
+        let mut model = Model::default();
+        model.tokens.insert(
+            "1".to_string(),
+            TokenState {
+                owner_id: "user1.near".to_string(),
+                approved_account_ids: BTreeMap::new(),
+                royalty: Some(BTreeMap::from([("studio.near".to_string(), 500)])),
+            },
+        );
+        model.apply(&parse_log(r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_royalty_update","data":[{"token_id":"1","old_account_id":"studio.near","new_account_id":"user2.near","basis_points":200}]}"#).unwrap().unwrap().event);
+        let royalty = model.tokens["1"].royalty.as_ref().unwrap();
+        assert_eq!(royalty["studio.near"], 300);
+        assert_eq!(royalty["user2.near"], 200);
+    }
+
+    #[test]
+    fn burnt_tokens_leave_the_model() {
+        let model = replay(&[
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_mint","data":[{"owner_id":"user1.near","token_ids":["1","2"]}]}"#,
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_burn","data":[{"owner_id":"user1.near","token_ids":["1","9"]}]}"#,
+        ]);
+        assert!(!model.tokens.contains_key("1"));
+        assert!(model.tokens.contains_key("2"));
+        assert_eq!(model.anomalies, vec!["unknown token 9 burnt".to_string()]);
+    }
+}
@@ -0,0 +1,140 @@
+//! Extracting the contract's events from log lines, JSON-RPC responses and token dumps
+
+use std::io::{BufRead, Read};
+
+use nft_cypher::{EventLog, TokenJson, NFT_STANDARD_NAME};
+use serde_json::Value;
+
+use crate::Error;
+
+/// Prefix of the log lines that carry an event, as defined by NEP-297
+pub const EVENT_JSON_PREFIX: &str = "EVENT_JSON:";
+
+/// Parses a log line. Returns `None` if the line isn't an event of the contract's standard,
+/// and an error if it claims to be one but can't be read as such.
+pub fn parse_log(log: &str) -> Option<Result<EventLog, Error>> {
+    let json = log.trim().strip_prefix(EVENT_JSON_PREFIX)?;
+    let value: Value = match serde_json::from_str(json) {
+        Ok(value) => value,
+        Err(err) => return Some(Err(err.into())),
+    };
+    if value.get("standard").and_then(Value::as_str) != Some(NFT_STANDARD_NAME) {
+        return None;
+    }
+    Some(serde_json::from_value(value).map_err(Error::from))
+}
+
+/// Reads the events in a stream of log lines, one per line, skipping other lines
+pub fn read_log_lines(reader: impl BufRead) -> Result<Vec<EventLog>, Error> {
+    let mut events = Vec::new();
+    for line in reader.lines() {
+        if let Some(event) = parse_log(&line?) {
+            events.push(event?);
+        }
+    }
+    Ok(events)
+}
+
+/// Collects the logs of a transaction status returned by the `tx` or `EXPERIMENTAL_tx_status`
+/// JSON-RPC methods, in execution order. With `contract_id`, only the logs of receipts executed by
+/// that account are kept. Accepts a full response, its `result`, or an array of either.
+pub fn logs_from_rpc(value: &Value, contract_id: Option<&str>) -> Vec<String> {
+    if let Some(responses) = value.as_array() {
+        return responses
+            .iter()
+            .flat_map(|response| logs_from_rpc(response, contract_id))
+            .collect();
+    }
+    let result = value.get("result").unwrap_or(value);
+    let outcomes = result.get("transaction_outcome").into_iter().chain(
+        result
+            .get("receipts_outcome")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten(),
+    );
+
+    let mut logs = Vec::new();
+    for outcome in outcomes.filter_map(|outcome| outcome.get("outcome")) {
+        let executor_id = outcome.get("executor_id").and_then(Value::as_str);
+        if contract_id.is_some() && executor_id != contract_id {
+            continue;
+        }
+        let lines = outcome
+            .get("logs")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten();
+        logs.extend(lines.filter_map(Value::as_str).map(str::to_string));
+    }
+    logs
+}
+
+/// Reads the events in a stream of JSON-RPC transaction statuses, see [`logs_from_rpc`]
+pub fn read_rpc_responses(
+    reader: impl Read,
+    contract_id: Option<&str>,
+) -> Result<Vec<EventLog>, Error> {
+    let mut events = Vec::new();
+    for value in serde_json::Deserializer::from_reader(reader).into_iter::<Value>() {
+        for log in logs_from_rpc(&value?, contract_id) {
+            if let Some(event) = parse_log(&log) {
+                events.push(event?);
+            }
+        }
+    }
+    Ok(events)
+}
+
+/// Reads the tokens of a dump made of one or more JSON arrays, as returned by the `nft_tokens` or
+/// `nft_tokens_for_owner` views, one page after the other
+pub fn read_token_dump(reader: impl Read) -> Result<Vec<TokenJson>, Error> {
+    let mut tokens = Vec::new();
+    for page in serde_json::Deserializer::from_reader(reader).into_iter::<Vec<TokenJson>>() {
+        tokens.extend(page?);
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nft_cypher::EventLogVariant;
+
+    const MINT: &str = r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_mint","data":[{"owner_id":"user1.near","token_ids":["1"]}]}"#;
+
+    #[test]
+    fn parses_contract_events_only() {
+        let event = parse_log(MINT).unwrap().unwrap();
+        assert!(matches!(event.event, EventLogVariant::NftMint(_)));
+
+        let ft =
+            r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_mint","data":[]}"#;
+        assert!(parse_log(ft).is_none());
+        assert!(parse_log("cypher: user1.near is already registered").is_none());
+        assert!(
+            parse_log(r#"EVENT_JSON:{"standard":"nep171","event":"nft_unknown"}"#)
+                .unwrap()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn reads_rpc_logs_of_the_contract() {
+        let response = serde_json::json!({
+            "jsonrpc": "2.0",
+            "result": {
+                "transaction_outcome": {"outcome": {"executor_id": "user1.near", "logs": []}},
+                "receipts_outcome": [
+                    {"outcome": {"executor_id": "nft.near", "logs": [MINT]}},
+                    {"outcome": {"executor_id": "other.near", "logs": [MINT]}}
+                ]
+            }
+        });
+        assert_eq!(
+            logs_from_rpc(&response, Some("nft.near")),
+            vec![MINT.to_string()]
+        );
+        assert_eq!(logs_from_rpc(&response, None).len(), 2);
+    }
+}
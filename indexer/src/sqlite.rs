@@ -0,0 +1,54 @@
+//! Writing the replayed model to SQLite, for backends that query it with SQL
+
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+use crate::Model;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS tokens (
+        token_id TEXT PRIMARY KEY,
+        owner_id TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS tokens_by_owner ON tokens (owner_id);
+    CREATE TABLE IF NOT EXISTS approvals (
+        token_id TEXT NOT NULL,
+        account_id TEXT NOT NULL,
+        approval_id INTEGER NOT NULL,
+        PRIMARY KEY (token_id, account_id)
+    );
+    CREATE TABLE IF NOT EXISTS royalties (
+        token_id TEXT NOT NULL,
+        account_id TEXT NOT NULL,
+        basis_points INTEGER NOT NULL,
+        PRIMARY KEY (token_id, account_id)
+    );
+";
+
+/// Replaces the contents of the database at `path` with the model, in a single transaction
+pub fn write_model(model: &Model, path: &Path) -> rusqlite::Result<()> {
+    let mut conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch("DELETE FROM tokens; DELETE FROM approvals; DELETE FROM royalties;")?;
+    for (token_id, token) in &model.tokens {
+        tx.execute(
+            "INSERT INTO tokens (token_id, owner_id) VALUES (?1, ?2)",
+            params![token_id, token.owner_id],
+        )?;
+        for (account_id, approval_id) in &token.approved_account_ids {
+            tx.execute(
+                "INSERT INTO approvals (token_id, account_id, approval_id) VALUES (?1, ?2, ?3)",
+                params![token_id, account_id, *approval_id as i64],
+            )?;
+        }
+        for (account_id, basis_points) in token.royalty.iter().flatten() {
+            tx.execute(
+                "INSERT INTO royalties (token_id, account_id, basis_points) VALUES (?1, ?2, ?3)",
+                params![token_id, account_id, basis_points],
+            )?;
+        }
+    }
+    tx.commit()
+}